@@ -3,24 +3,22 @@
 //! This module provides functionality for checking and using huge pages
 //! for memory mapping.
 #![allow(unused_variables)]
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
-use crate::advanced::HugePageSize;
+use crate::advanced::{HugePageSize, ThpMode};
+use crate::error::{Error, Result};
 
 /// Check if huge pages are supported on the current system.
+///
+/// On Linux, this means that at least one hugetlb pool has pages configured;
+/// see `HugePagePools` for the details.
 #[inline]
 pub fn is_supported() -> bool {
     #[cfg(target_os = "linux")]
     {
-        // Check if huge pages are enabled in the kernel
-        if let Ok(mut file) = File::open("/proc/meminfo") {
-            let mut contents = String::new();
-            if file.read_to_string(&mut contents).is_ok() {
-                return contents.contains("HugePages_Total") && !contents.contains("HugePages_Total: 0");
-            }
-        }
-        false
+        HugePagePools::read().is_ok_and(|pools| pools.pools().iter().any(|pool| pool.total > 0))
     }
     
     #[cfg(windows)]
@@ -89,16 +87,7 @@ pub fn default_huge_page_size() -> Option<HugePageSize> {
 pub fn is_1gb_supported() -> bool {
     #[cfg(target_os = "linux")]
     {
-        // Check if 1GB huge pages are enabled in the kernel
-        if let Ok(mut file) = File::open("/sys/kernel/mm/hugepages/hugepages-1048576kB/nr_hugepages") {
-            let mut contents = String::new();
-            if file.read_to_string(&mut contents).is_ok() {
-                if let Ok(count) = contents.trim().parse::<usize>() {
-                    return count > 0;
-                }
-            }
-        }
-        false
+        HugePagePools::read().is_ok_and(|pools| pools.get(HugePageSize::OneGB).is_some_and(|pool| pool.total > 0))
     }
     
     #[cfg(not(target_os = "linux"))]
@@ -106,4 +95,577 @@ pub fn is_1gb_supported() -> bool {
         // 1GB huge pages are primarily a Linux feature
         false
     }
-}
\ No newline at end of file
+}
+
+/// Default mount point of sysfs.
+const SYSFS_ROOT: &str = "/sys";
+
+/// The page counts of one hugetlb pool on one NUMA node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeHugePages {
+    /// The NUMA node.
+    pub node: u32,
+    
+    /// Number of persistent huge pages allocated on the node.
+    pub total: u64,
+    
+    /// Number of huge pages on the node not yet faulted in.
+    pub free: u64,
+    
+    /// Number of surplus huge pages on the node, allocated beyond `total`.
+    pub surplus: u64,
+}
+
+/// The page counts of the hugetlb pool of one page size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HugePagePool {
+    /// Size of the pages in bytes.
+    pub page_size: usize,
+    
+    /// Number of persistent huge pages in the pool (`nr_hugepages`).
+    pub total: u64,
+    
+    /// Number of huge pages not yet faulted in (`free_hugepages`).
+    pub free: u64,
+    
+    /// Number of free huge pages promised to existing mappings
+    /// (`resv_hugepages`).
+    pub reserved: u64,
+    
+    /// Number of huge pages allocated beyond `total` (`surplus_hugepages`).
+    pub surplus: u64,
+    
+    /// Maximum number of surplus huge pages (`nr_overcommit_hugepages`).
+    pub overcommit: u64,
+    
+    /// Page counts per NUMA node, sorted by node.
+    pub nodes: Vec<NodeHugePages>,
+}
+
+impl HugePagePool {
+    /// Return the page size as a `HugePageSize`, if it is one of the sizes
+    /// that memory maps can request.
+    #[inline]
+    pub fn huge_page_size(&self) -> Option<HugePageSize> {
+        [HugePageSize::TwoMB, HugePageSize::OneGB]
+            .into_iter()
+            .find(|size| size.bytes() == self.page_size)
+    }
+    
+    /// Return the number of free pages that new mappings can get without
+    /// allocating surplus pages.
+    #[inline]
+    pub fn available(&self) -> u64 {
+        self.free.saturating_sub(self.reserved)
+    }
+    
+    /// Return the number of bytes that new mappings can get without allocating
+    /// surplus pages.
+    #[inline]
+    pub fn available_bytes(&self) -> u64 {
+        self.available().saturating_mul(self.page_size as u64)
+    }
+    
+    /// Return the page counts on NUMA node `node`, if it has this pool.
+    #[inline]
+    pub fn node(&self, node: u32) -> Option<&NodeHugePages> {
+        self.nodes.iter().find(|counts| counts.node == node)
+    }
+}
+
+/// A report of every hugetlb pool on the system, read from
+/// `/sys/kernel/mm/hugepages` and `/sys/devices/system/node/node*/hugepages`.
+///
+/// The counts are a snapshot: other processes may take pages at any time, so
+/// a successful capacity check does not guarantee that a mapping succeeds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HugePagePools {
+    /// Pools sorted by page size.
+    pools: Vec<HugePagePool>,
+}
+
+impl HugePagePools {
+    /// Read the hugetlb pools of the system.
+    ///
+    /// Systems without hugetlb support have no pools.
+    #[inline]
+    pub fn read() -> Result<HugePagePools> {
+        HugePagePools::read_from(SYSFS_ROOT)
+    }
+    
+    /// Read the hugetlb pools from a sysfs tree mounted at `root` instead of
+    /// `/sys`, such as a copy of it.
+    pub fn read_from<P: AsRef<Path>>(root: P) -> Result<HugePagePools> {
+        let root = root.as_ref();
+        let mut pools = Vec::new();
+        for (page_size, dir) in read_pool_dirs(&root.join("kernel/mm/hugepages"))? {
+            pools.push(HugePagePool {
+                page_size,
+                total: read_count(&dir.join("nr_hugepages"))?,
+                free: read_count(&dir.join("free_hugepages"))?,
+                reserved: read_count(&dir.join("resv_hugepages"))?,
+                surplus: read_count(&dir.join("surplus_hugepages"))?,
+                overcommit: read_count(&dir.join("nr_overcommit_hugepages"))?,
+                nodes: Vec::new(),
+            });
+        }
+        
+        // Add the per-node counts of each pool
+        for (node, node_dir) in read_node_dirs(&root.join("devices/system/node"))? {
+            for (page_size, dir) in read_pool_dirs(&node_dir.join("hugepages"))? {
+                let pool = match pools.iter_mut().find(|pool| pool.page_size == page_size) {
+                    Some(pool) => pool,
+                    None => continue,
+                };
+                pool.nodes.push(NodeHugePages {
+                    node,
+                    total: read_count(&dir.join("nr_hugepages"))?,
+                    free: read_count(&dir.join("free_hugepages"))?,
+                    surplus: read_count(&dir.join("surplus_hugepages"))?,
+                });
+            }
+        }
+        
+        pools.sort_by_key(|pool| pool.page_size);
+        for pool in &mut pools {
+            pool.nodes.sort_by_key(|counts| counts.node);
+        }
+        Ok(HugePagePools { pools })
+    }
+    
+    /// Return every pool, sorted by page size.
+    #[inline]
+    pub fn pools(&self) -> &[HugePagePool] {
+        &self.pools
+    }
+    
+    /// Return the pool of pages of `size`, if the system supports it.
+    #[inline]
+    pub fn get(&self, size: HugePageSize) -> Option<&HugePagePool> {
+        self.pools.iter().find(|pool| pool.page_size == size.bytes())
+    }
+    
+    /// Return true if a mapping of `len` bytes backed by pages of `size` fits
+    /// in the free pages of the pool, on NUMA node `node` if one is given.
+    ///
+    /// Surplus pages that the kernel may allocate on demand are not counted.
+    pub fn can_allocate(&self, size: HugePageSize, len: usize, node: Option<u32>) -> bool {
+        let pool = match self.get(size) {
+            Some(pool) => pool,
+            None => return false,
+        };
+        let pages = len.div_ceil(size.bytes()) as u64;
+        match node {
+            // Reservations are only tracked system-wide, so they are charged
+            // against every node
+            Some(node) => pool
+                .node(node)
+                .is_some_and(|counts| counts.free.saturating_sub(pool.reserved) >= pages),
+            None => pool.available() >= pages,
+        }
+    }
+}
+
+/// List the `hugepages-<size>kB` directories in `dir` with their page sizes
+/// in bytes. A missing directory has none.
+fn read_pool_dirs(dir: &Path) -> Result<Vec<(usize, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(Error::Io(err)),
+    };
+    
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let size = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("hugepages-"))
+            .and_then(|name| name.strip_suffix("kB"))
+            .and_then(|kb| kb.parse::<usize>().ok())
+            .and_then(|kb| kb.checked_mul(1024));
+        if let Some(size) = size {
+            dirs.push((size, entry.path()));
+        }
+    }
+    Ok(dirs)
+}
+
+/// List the `node<N>` directories in `dir` with their node numbers. A missing
+/// directory has none.
+fn read_node_dirs(dir: &Path) -> Result<Vec<(u32, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(Error::Io(err)),
+    };
+    
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let node = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|node| node.parse::<u32>().ok());
+        if let Some(node) = node {
+            dirs.push((node, entry.path()));
+        }
+    }
+    Ok(dirs)
+}
+
+/// Read a sysfs file holding a single count.
+fn read_count(path: &Path) -> Result<u64> {
+    let contents = fs::read_to_string(path)?;
+    contents.trim().parse().map_err(|_| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not hold a count", path.display()),
+        ))
+    })
+}
+
+/// Default location of the mount table.
+const MOUNTS_PATH: &str = "/proc/mounts";
+
+/// A mounted hugetlbfs file system, whose files are backed by huge pages.
+///
+/// Files created on hugetlbfs persist until they are removed or the system is
+/// unmounted, so several processes can map the same huge pages by path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HugetlbfsMount {
+    /// The mount point.
+    pub path: PathBuf,
+    
+    /// Size of the pages backing its files in bytes.
+    pub page_size: usize,
+    
+    /// Whether the file system is mounted read-only.
+    pub read_only: bool,
+}
+
+impl HugetlbfsMount {
+    /// Return the page size as a `HugePageSize`, if it is one of the sizes
+    /// that memory maps can request.
+    #[inline]
+    pub fn huge_page_size(&self) -> Option<HugePageSize> {
+        [HugePageSize::TwoMB, HugePageSize::OneGB]
+            .into_iter()
+            .find(|size| size.bytes() == self.page_size)
+    }
+    
+    /// List the hugetlbfs file systems in `/proc/mounts`, in mount order.
+    ///
+    /// Systems without a mount table have none.
+    #[inline]
+    pub fn all() -> Result<Vec<HugetlbfsMount>> {
+        HugetlbfsMount::all_from(MOUNTS_PATH)
+    }
+    
+    /// List the hugetlbfs file systems in a mount table at `path` instead of
+    /// `/proc/mounts`, such as `/proc/<pid>/mounts` of another process.
+    pub fn all_from<P: AsRef<Path>>(path: P) -> Result<Vec<HugetlbfsMount>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(Error::Io(err)),
+        };
+        
+        let mut mounts = Vec::new();
+        for line in contents.lines() {
+            // Fields are: source, mount point, type, options, dump, pass
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 || fields[2] != "hugetlbfs" {
+                continue;
+            }
+            
+            // Mounts without a page size use the default one
+            let options = fields[3].split(',');
+            let page_size = options
+                .clone()
+                .find_map(|option| option.strip_prefix("pagesize="))
+                .map_or_else(
+                    || default_huge_page_size().map(|size| size.bytes()),
+                    parse_page_size,
+                );
+            let page_size = match page_size {
+                Some(page_size) => page_size,
+                None => continue,
+            };
+            
+            mounts.push(HugetlbfsMount {
+                path: PathBuf::from(unescape_mount_field(fields[1])),
+                page_size,
+                read_only: options.clone().any(|option| option == "ro"),
+            });
+        }
+        Ok(mounts)
+    }
+    
+    /// Find a writable hugetlbfs file system with pages of `size`.
+    ///
+    /// Fails with `Error::HugetlbfsNotMounted` if there is none. An
+    /// administrator can mount one with, for example,
+    /// `mount -t hugetlbfs -o pagesize=2M none /dev/hugepages`.
+    pub fn find(size: HugePageSize) -> Result<HugetlbfsMount> {
+        HugetlbfsMount::all()?
+            .into_iter()
+            .find(|mount| mount.page_size == size.bytes() && !mount.read_only)
+            .ok_or(Error::HugetlbfsNotMounted { page_size: size.into() })
+    }
+}
+
+/// Parse a hugetlbfs `pagesize` option such as `2M`, `1G` or `2048K`.
+fn parse_page_size(value: &str) -> Option<usize> {
+    let (digits, unit) = match value.char_indices().find(|&(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, ""),
+    };
+    let shift = match unit {
+        "" => 0,
+        "k" | "K" => 10,
+        "m" | "M" => 20,
+        "g" | "G" => 30,
+        _ => return None,
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+/// Decode the octal escapes (`\040` for a space, for example) that the mount
+/// table uses for whitespace and backslashes in paths.
+fn unescape_mount_field(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        result.push_str(&rest[..index]);
+        let escape = rest.get(index + 1..index + 4);
+        match escape.and_then(|digits| u8::from_str_radix(digits, 8).ok()) {
+            Some(byte) => {
+                result.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                result.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Directory holding the transparent huge page settings on Linux.
+#[cfg(target_os = "linux")]
+const THP_DIR: &str = "/sys/kernel/mm/transparent_hugepage";
+
+/// Which memory the kernel backs with transparent huge pages, from
+/// `/sys/kernel/mm/transparent_hugepage/enabled`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThpEnabled {
+    /// All anonymous memory large enough to hold a huge page.
+    Always,
+    
+    /// Only memory advised with `MADV_HUGEPAGE`.
+    Madvise,
+    
+    /// No memory, except ranges collapsed with `MADV_COLLAPSE`.
+    Never,
+}
+
+/// How hard page faults try to get a transparent huge page, from
+/// `/sys/kernel/mm/transparent_hugepage/defrag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThpDefrag {
+    /// Every fault stalls for direct reclaim and compaction.
+    Always,
+    
+    /// Faults fall back to small pages and leave compaction to kcompactd.
+    Defer,
+    
+    /// Faults in advised memory stall, others defer.
+    DeferMadvise,
+    
+    /// Faults in advised memory stall, others fall back to small pages.
+    Madvise,
+    
+    /// Faults never stall, and only get a huge page if one is free.
+    Never,
+}
+
+/// The transparent huge page settings of the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThpStatus {
+    /// Which memory is backed by transparent huge pages.
+    pub enabled: ThpEnabled,
+    
+    /// How hard page faults try to get a huge page.
+    pub defrag: ThpDefrag,
+}
+
+impl ThpStatus {
+    /// Return true if requesting `mode` for a memory map has an effect on this
+    /// system.
+    ///
+    /// `ThpMode::Enable` needs `enabled` to be `always` or `madvise`;
+    /// `MADV_NOHUGEPAGE` and `MADV_COLLAPSE` do not depend on the settings.
+    #[inline]
+    pub fn honours(&self, mode: ThpMode) -> bool {
+        match mode {
+            ThpMode::Enable => self.enabled != ThpEnabled::Never,
+            ThpMode::Disable | ThpMode::Collapse => true,
+        }
+    }
+    
+    /// Return true if page faults may allocate huge pages, in memory advised
+    /// with `MADV_HUGEPAGE` if `advised` is set.
+    #[inline]
+    pub fn faults_huge_pages(&self, advised: bool) -> bool {
+        match self.enabled {
+            ThpEnabled::Always => true,
+            ThpEnabled::Madvise => advised,
+            ThpEnabled::Never => false,
+        }
+    }
+    
+    /// Return true if page faults stall for direct reclaim and compaction to
+    /// get a huge page, in memory advised with `MADV_HUGEPAGE` if `advised` is
+    /// set, rather than falling back to small pages.
+    #[inline]
+    pub fn compacts_on_fault(&self, advised: bool) -> bool {
+        self.faults_huge_pages(advised)
+            && match self.defrag {
+                ThpDefrag::Always => true,
+                ThpDefrag::DeferMadvise | ThpDefrag::Madvise => advised,
+                ThpDefrag::Defer | ThpDefrag::Never => false,
+            }
+    }
+}
+
+/// Read the transparent huge page settings of the system.
+///
+/// Returns `None` if transparent huge pages are unavailable, such as on
+/// kernels built without them and on platforms other than Linux.
+pub fn transparent_huge_pages() -> Option<ThpStatus> {
+    #[cfg(target_os = "linux")]
+    {
+        let enabled = match read_selected("enabled")?.as_str() {
+            "always" => ThpEnabled::Always,
+            "madvise" => ThpEnabled::Madvise,
+            "never" => ThpEnabled::Never,
+            _ => return None,
+        };
+        let defrag = match read_selected("defrag")?.as_str() {
+            "always" => ThpDefrag::Always,
+            "defer" => ThpDefrag::Defer,
+            "defer+madvise" => ThpDefrag::DeferMadvise,
+            "madvise" => ThpDefrag::Madvise,
+            "never" => ThpDefrag::Never,
+            _ => return None,
+        };
+        Some(ThpStatus { enabled, defrag })
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    {
+        // Transparent huge pages are a Linux feature
+        None
+    }
+}
+
+/// Read the selected value of a transparent huge page setting, which sysfs
+/// shows in brackets among the possible values, as in `always [madvise] never`.
+#[cfg(target_os = "linux")]
+fn read_selected(name: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(Path::new(THP_DIR).join(name)).ok()?;
+    let start = contents.find('[')? + 1;
+    let end = start + contents[start..].find(']')?;
+    Some(contents[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Write `contents` to `path` under `root`, creating its directories.
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    
+    /// Write the counts of a pool directory under `root`.
+    fn write_pool(root: &Path, dir: &str, total: u64, free: u64, surplus: u64) {
+        write(root, &format!("{}/nr_hugepages", dir), &format!("{}\n", total));
+        write(root, &format!("{}/free_hugepages", dir), &format!("{}\n", free));
+        write(root, &format!("{}/surplus_hugepages", dir), &format!("{}\n", surplus));
+    }
+    
+    #[test]
+    fn pools_are_read_from_a_sysfs_tree() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        
+        let dir = "kernel/mm/hugepages/hugepages-2048kB";
+        write_pool(root, dir, 8, 5, 1);
+        write(root, &format!("{}/resv_hugepages", dir), "2\n");
+        write(root, &format!("{}/nr_overcommit_hugepages", dir), "4\n");
+        let dir = "kernel/mm/hugepages/hugepages-1048576kB";
+        write_pool(root, dir, 0, 0, 0);
+        write(root, &format!("{}/resv_hugepages", dir), "0\n");
+        write(root, &format!("{}/nr_overcommit_hugepages", dir), "0\n");
+        
+        write_pool(root, "devices/system/node/node1/hugepages/hugepages-2048kB", 3, 0, 0);
+        write_pool(root, "devices/system/node/node0/hugepages/hugepages-2048kB", 5, 5, 1);
+        // Entries that are not nodes or pools are skipped
+        write(root, "devices/system/node/online", "0-1\n");
+        write(root, "kernel/mm/hugepages/README", "");
+        
+        let pools = HugePagePools::read_from(root).unwrap();
+        let sizes: Vec<usize> = pools.pools().iter().map(|pool| pool.page_size).collect();
+        assert_eq!(sizes, [2 << 20, 1 << 30]);
+        
+        let pool = pools.get(HugePageSize::TwoMB).unwrap();
+        assert_eq!(pool.huge_page_size(), Some(HugePageSize::TwoMB));
+        assert_eq!((pool.total, pool.free, pool.reserved, pool.surplus, pool.overcommit), (8, 5, 2, 1, 4));
+        assert_eq!(pool.available(), 3);
+        assert_eq!(
+            pool.nodes,
+            [
+                NodeHugePages { node: 0, total: 5, free: 5, surplus: 1 },
+                NodeHugePages { node: 1, total: 3, free: 0, surplus: 0 },
+            ]
+        );
+        assert!(pools.get(HugePageSize::OneGB).unwrap().nodes.is_empty());
+        
+        assert!(pools.can_allocate(HugePageSize::TwoMB, 6 << 20, None));
+        assert!(!pools.can_allocate(HugePageSize::TwoMB, 8 << 20, None));
+        assert!(pools.can_allocate(HugePageSize::TwoMB, 6 << 20, Some(0)));
+        assert!(!pools.can_allocate(HugePageSize::TwoMB, 1, Some(1)));
+        assert!(!pools.can_allocate(HugePageSize::OneGB, 1, None));
+    }
+    
+    #[test]
+    fn missing_sysfs_tree_has_no_pools() {
+        let root = tempfile::tempdir().unwrap();
+        let pools = HugePagePools::read_from(root.path()).unwrap();
+        assert!(pools.pools().is_empty());
+        assert!(!pools.can_allocate(HugePageSize::TwoMB, 1, None));
+    }
+    
+    #[test]
+    fn malformed_count_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let dir = "kernel/mm/hugepages/hugepages-2048kB";
+        write_pool(root, dir, 8, 5, 0);
+        write(root, &format!("{}/resv_hugepages", dir), "many\n");
+        write(root, &format!("{}/nr_overcommit_hugepages", dir), "0\n");
+        
+        match HugePagePools::read_from(root) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
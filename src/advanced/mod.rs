@@ -7,6 +7,9 @@ pub mod huge_pages;
 pub mod numa;
 pub mod prefetch;
 
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
 /// Huge page sizes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HugePageSize {
@@ -17,17 +20,309 @@ pub enum HugePageSize {
     OneGB,
 }
 
+impl HugePageSize {
+    /// Return the size of a huge page in bytes.
+    #[inline]
+    pub const fn bytes(self) -> usize {
+        match self {
+            HugePageSize::TwoMB => 2 * 1024 * 1024,
+            HugePageSize::OneGB => 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// The kind of pages backing a memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    /// 1GB huge pages from the hugetlb pool.
+    OneGB,
+    
+    /// 2MB huge pages from the hugetlb pool.
+    TwoMB,
+    
+    /// Regular pages advised to be backed by transparent huge pages.
+    Transparent,
+    
+    /// Regular pages of the system page size.
+    Normal,
+}
+
+impl PageSize {
+    /// Return the size of a page in bytes.
+    ///
+    /// Transparent huge pages are 2MB, although the kernel may back parts of
+    /// the map with regular pages.
+    #[inline]
+    pub fn bytes(self) -> usize {
+        match self {
+            PageSize::OneGB => HugePageSize::OneGB.bytes(),
+            PageSize::TwoMB | PageSize::Transparent => HugePageSize::TwoMB.bytes(),
+            PageSize::Normal => crate::utils::alignment::page_size(),
+        }
+    }
+}
+
+impl From<HugePageSize> for PageSize {
+    #[inline]
+    fn from(size: HugePageSize) -> PageSize {
+        match size {
+            HugePageSize::TwoMB => PageSize::TwoMB,
+            HugePageSize::OneGB => PageSize::OneGB,
+        }
+    }
+}
+
+impl fmt::Display for PageSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageSize::OneGB => write!(f, "1GB"),
+            PageSize::TwoMB => write!(f, "2MB"),
+            PageSize::Transparent => write!(f, "transparent"),
+            PageSize::Normal => write!(f, "normal"),
+        }
+    }
+}
+
+/// Which pages to back an anonymous memory map with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HugePagePolicy {
+    /// Use exactly this kind of page, failing if it is unavailable.
+    Exact(PageSize),
+    
+    /// Try each kind of page in turn, using the first that can be allocated.
+    BestEffort(Vec<PageSize>),
+}
+
+/// How a memory map should use transparent huge pages.
+///
+/// Unlike `HugePageSize`, which needs a preconfigured hugetlb pool,
+/// transparent huge pages come from the regular page allocator and also work
+/// for page-cache files where the kernel supports it. See
+/// `huge_pages::transparent_huge_pages` for what the system will honour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThpMode {
+    /// Keep the map out of transparent huge pages (`MADV_NOHUGEPAGE`).
+    Disable,
+    
+    /// Back the map with transparent huge pages where possible
+    /// (`MADV_HUGEPAGE`).
+    Enable,
+    
+    /// Like `Enable`, and also collapse the map into huge pages right away
+    /// (`MADV_COLLAPSE`) instead of waiting for khugepaged. Only populated
+    /// memory can be collapsed, such as cached file pages or anonymous maps
+    /// created with `populate`.
+    Collapse,
+}
+
+/// A set of NUMA nodes, of any size.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NodeMask {
+    /// Bit `n % 64` of word `n / 64` is set for node `n`. Trailing zero words
+    /// are trimmed, so equal sets compare equal.
+    words: Vec<u64>,
+}
+
+impl NodeMask {
+    /// Return an empty set of nodes.
+    #[inline]
+    pub const fn new() -> NodeMask {
+        NodeMask { words: Vec::new() }
+    }
+    
+    /// Return a set holding only `node`.
+    #[inline]
+    pub fn single(node: u32) -> NodeMask {
+        let mut mask = NodeMask::new();
+        mask.insert(node);
+        mask
+    }
+    
+    /// Add `node` to the set.
+    pub fn insert(&mut self, node: u32) {
+        let word = node as usize / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (node % 64);
+    }
+    
+    /// Remove `node` from the set.
+    pub fn remove(&mut self, node: u32) {
+        if let Some(word) = self.words.get_mut(node as usize / 64) {
+            *word &= !(1 << (node % 64));
+        }
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
+    
+    /// Return true if `node` is in the set.
+    #[inline]
+    pub fn contains(&self, node: u32) -> bool {
+        self.words
+            .get(node as usize / 64)
+            .is_some_and(|word| word & (1 << (node % 64)) != 0)
+    }
+    
+    /// Return true if the set holds no nodes.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+    
+    /// Return the number of nodes in the set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+    
+    /// Return the highest node in the set.
+    #[inline]
+    pub fn max_node(&self) -> Option<u32> {
+        let last = self.words.last()?;
+        Some((self.words.len() as u32 - 1) * 64 + 63 - last.leading_zeros())
+    }
+    
+    /// Iterate over the nodes in the set in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| index as u32 * 64 + bit)
+        })
+    }
+    
+    /// Return the set as 64-bit words, lowest nodes first, as system calls
+    /// taking a node mask expect on 64-bit targets.
+    #[inline]
+    pub fn as_words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+impl From<u32> for NodeMask {
+    #[inline]
+    fn from(node: u32) -> NodeMask {
+        NodeMask::single(node)
+    }
+}
+
+impl<const N: usize> From<[u32; N]> for NodeMask {
+    #[inline]
+    fn from(nodes: [u32; N]) -> NodeMask {
+        nodes.into_iter().collect()
+    }
+}
+
+impl FromIterator<u32> for NodeMask {
+    fn from_iter<I: IntoIterator<Item = u32>>(nodes: I) -> NodeMask {
+        let mut mask = NodeMask::new();
+        for node in nodes {
+            mask.insert(node);
+        }
+        mask
+    }
+}
+
+impl fmt::Display for NodeMask {
+    /// Format the set as a node list, such as `0-3,6`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut nodes = self.iter().peekable();
+        let mut first = true;
+        while let Some(start) = nodes.next() {
+            let mut end = start;
+            while nodes.peek() == Some(&(end + 1)) {
+                end = nodes.next().unwrap_or(end);
+            }
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            if start == end {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}-{}", start, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 /// NUMA policy for memory allocation.
-#[derive(Debug, Clone, Copy)] 
+///
+/// On Linux the policy is applied to the memory map with `mbind`, and governs
+/// where its pages are allocated when they are first touched. Other platforms
+/// ignore it.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NumaPolicy {
-    /// Interleave memory across the specified NUMA nodes.
-    Interleave([u32; 4], usize),
+    /// Interleave pages across the nodes in the mask, page by page
+    /// (`MPOL_INTERLEAVE`).
+    Interleave(NodeMask),
     
-    /// Bind memory to the specified NUMA node.
-    Bind(u32),
+    /// Allocate only from the nodes in the mask, failing when they are out of
+    /// memory (`MPOL_BIND`).
+    Bind(NodeMask),
     
-    /// Prefer allocating memory on the specified NUMA node.
+    /// Prefer allocating memory on the specified NUMA node, falling back to
+    /// other nodes (`MPOL_PREFERRED`).
     Preferred(u32),
+    
+    /// Allocate on the node of the CPU that first touches each page
+    /// (`MPOL_LOCAL`).
+    Local,
+}
+
+/// Flags changing how a `NumaPolicy` is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumaFlags(u32);
+
+impl NumaFlags {
+    /// Fail if pages already allocated in the map do not follow the policy
+    /// (`MPOL_MF_STRICT`). Without `MOVE`, they are only checked.
+    pub const STRICT: NumaFlags = NumaFlags(1);
+    
+    /// Migrate pages already allocated in the map, that only this process
+    /// maps, to follow the policy (`MPOL_MF_MOVE`).
+    pub const MOVE: NumaFlags = NumaFlags(2);
+    
+    /// Return an empty set of flags.
+    #[inline]
+    pub const fn empty() -> NumaFlags {
+        NumaFlags(0)
+    }
+    
+    /// Return true if every flag in `other` is also in `self`.
+    #[inline]
+    pub const fn contains(self, other: NumaFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    
+    /// Return true if no flags are set.
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    
+    /// Return the raw flag bits.
+    #[inline]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl BitOr for NumaFlags {
+    type Output = NumaFlags;
+    
+    #[inline]
+    fn bitor(self, rhs: NumaFlags) -> NumaFlags {
+        NumaFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for NumaFlags {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: NumaFlags) {
+        self.0 |= rhs.0;
+    }
 }
 
 /// Prefetching strategy for memory access.
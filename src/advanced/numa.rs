@@ -2,13 +2,20 @@
 //!
 //! This module provides functionality for NUMA-aware memory mapping.
 
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::advanced::NodeMask;
+use crate::error::{Error, Result};
+
 /// Check if NUMA is supported on the current system.
 #[inline]
 pub fn is_supported() -> bool {
     #[cfg(target_os = "linux")]
     {
         // Check if NUMA is available on Linux
-        std::path::Path::new("/sys/devices/system/node/node0").exists()
+        Path::new("/sys/devices/system/node/node0").exists()
     }
     
     #[cfg(not(target_os = "linux"))]
@@ -23,12 +30,8 @@ pub fn is_supported() -> bool {
 pub fn node_count() -> usize {
     #[cfg(target_os = "linux")]
     {
-        // Count NUMA nodes on Linux
-        let mut count = 0;
-        while std::path::Path::new(&format!("/sys/devices/system/node/node{}", count)).exists() {
-            count += 1;
-        }
-        count
+        // Count the online NUMA nodes on Linux
+        NumaTopology::read().map_or(0, |topology| topology.nodes().len())
     }
     
     #[cfg(not(target_os = "linux"))]
@@ -39,13 +42,14 @@ pub fn node_count() -> usize {
 }
 
 /// Get the NUMA node for the current thread.
+///
+/// On Linux this uses `getcpu`. The thread may be migrated to another node
+/// right after the call, unless it is pinned to the CPUs of one node.
 #[inline]
 pub fn current_node() -> Option<u32> {
     #[cfg(target_os = "linux")]
     {
-        // This is a simplified implementation
-        // In a real-world scenario, you would use libnuma or direct syscalls
-        Some(0)
+        crate::platform::getcpu().ok().map(|(_, node)| node)
     }
     
     #[cfg(not(target_os = "linux"))]
@@ -59,4 +63,300 @@ pub fn current_node() -> Option<u32> {
 #[inline]
 pub fn preferred_node() -> Option<u32> {
     current_node()
-}
\ No newline at end of file
+}
+
+/// Default mount point of sysfs.
+const SYSFS_ROOT: &str = "/sys";
+
+/// One online NUMA node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumaNode {
+    /// The node number.
+    pub id: u32,
+    
+    /// CPUs of the node, in ascending order. Memory-only nodes have none.
+    pub cpus: Vec<u32>,
+    
+    /// Total memory of the node in bytes.
+    pub memory_total: u64,
+    
+    /// Free memory of the node in bytes.
+    pub memory_free: u64,
+    
+    /// Distance from this node to every online node, in ascending node order.
+    /// The distance of a node to itself is normally 10, and other distances
+    /// are relative to it.
+    pub distances: Vec<u32>,
+}
+
+/// The NUMA topology of the system, read from `/sys/devices/system/node`.
+///
+/// Memory counts are a snapshot taken when the topology is read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NumaTopology {
+    /// Online nodes, sorted by node number.
+    nodes: Vec<NumaNode>,
+}
+
+impl NumaTopology {
+    /// Read the NUMA topology of the system.
+    ///
+    /// Systems without NUMA support in sysfs have no nodes.
+    #[inline]
+    pub fn read() -> Result<NumaTopology> {
+        NumaTopology::read_from(SYSFS_ROOT)
+    }
+    
+    /// Read the NUMA topology from a sysfs tree mounted at `root` instead of
+    /// `/sys`, such as a copy of it.
+    pub fn read_from<P: AsRef<Path>>(root: P) -> Result<NumaTopology> {
+        let dir = root.as_ref().join("devices/system/node");
+        let online = match fs::read_to_string(dir.join("online")) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(NumaTopology::default()),
+            Err(err) => return Err(Error::Io(err)),
+        };
+        let online = parse_list(&online, &dir.join("online"))?;
+        
+        let mut nodes = Vec::with_capacity(online.len());
+        for &id in &online {
+            let node_dir = dir.join(format!("node{}", id));
+            
+            let path = node_dir.join("cpulist");
+            let cpus = parse_list(&fs::read_to_string(&path)?, &path)?;
+            
+            let path = node_dir.join("meminfo");
+            let meminfo = fs::read_to_string(&path)?;
+            let memory_total = read_meminfo(&meminfo, "MemTotal:", &path)?;
+            let memory_free = read_meminfo(&meminfo, "MemFree:", &path)?;
+            
+            let path = node_dir.join("distance");
+            let distances = fs::read_to_string(&path)?
+                .split_whitespace()
+                .map(|distance| distance.parse().map_err(|_| invalid_data(&path)))
+                .collect::<Result<Vec<u32>>>()?;
+            if distances.len() != online.len() {
+                return Err(invalid_data(&path));
+            }
+            
+            nodes.push(NumaNode {
+                id,
+                cpus,
+                memory_total,
+                memory_free,
+                distances,
+            });
+        }
+        Ok(NumaTopology { nodes })
+    }
+    
+    /// Return every online node, sorted by node number.
+    #[inline]
+    pub fn nodes(&self) -> &[NumaNode] {
+        &self.nodes
+    }
+    
+    /// Return node `id`, if it is online.
+    #[inline]
+    pub fn node(&self, id: u32) -> Option<&NumaNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+    
+    /// Return the online nodes as a mask, for use in a `NumaPolicy`.
+    #[inline]
+    pub fn online(&self) -> NodeMask {
+        self.nodes.iter().map(|node| node.id).collect()
+    }
+    
+    /// Return the distance from node `from` to node `to`, if both are online.
+    pub fn distance(&self, from: u32, to: u32) -> Option<u32> {
+        let index = self.nodes.iter().position(|node| node.id == to)?;
+        self.node(from)?.distances.get(index).copied()
+    }
+    
+    /// Return the node that CPU `cpu` belongs to.
+    #[inline]
+    pub fn node_of_cpu(&self, cpu: u32) -> Option<u32> {
+        self.nodes
+            .iter()
+            .find(|node| node.cpus.binary_search(&cpu).is_ok())
+            .map(|node| node.id)
+    }
+    
+    /// Return the online nodes ordered by distance from node `from`, nearest
+    /// first, starting with `from` itself.
+    ///
+    /// Nodes at equal distance are ordered by node number. Returns an empty
+    /// list if `from` is not online.
+    pub fn nodes_by_distance(&self, from: u32) -> Vec<u32> {
+        let node = match self.node(from) {
+            Some(node) => node,
+            None => return Vec::new(),
+        };
+        let mut nodes: Vec<(u32, u32)> = self
+            .nodes
+            .iter()
+            .zip(&node.distances)
+            .map(|(other, &distance)| (distance, other.id))
+            .collect();
+        nodes.sort_unstable();
+        nodes.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+/// Parse a sysfs list such as `0-3,8,10-11` into its entries, in the order
+/// listed. An empty list has no entries.
+fn parse_list(contents: &str, path: &Path) -> Result<Vec<u32>> {
+    let mut entries = Vec::new();
+    for range in contents.trim().split(',').filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start = start.parse::<u32>().map_err(|_| invalid_data(path))?;
+        let end = end.parse::<u32>().map_err(|_| invalid_data(path))?;
+        if start > end {
+            return Err(invalid_data(path));
+        }
+        entries.extend(start..=end);
+    }
+    Ok(entries)
+}
+
+/// Read a value in kB, such as `Node 0 MemTotal: 1024 kB`, from the meminfo
+/// file of a node, in bytes.
+fn read_meminfo(contents: &str, key: &str, path: &Path) -> Result<u64> {
+    contents
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace().skip(2);
+            match (fields.next(), fields.next()) {
+                (Some(name), Some(value)) if name == key => Some(value),
+                _ => None,
+            }
+        })
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .and_then(|kb| kb.checked_mul(1024))
+        .ok_or_else(|| invalid_data(path))
+}
+
+/// Return the error for a sysfs file with unexpected contents.
+fn invalid_data(path: &Path) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} has unexpected contents", path.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Write `contents` to `path` under `root`, creating its directories.
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    
+    /// Write the files of node `id` under `root`.
+    fn write_node(root: &Path, id: u32, cpus: &str, total_kb: u64, distances: &str) {
+        let dir = format!("devices/system/node/node{}", id);
+        write(root, &format!("{}/cpulist", dir), &format!("{}\n", cpus));
+        write(
+            root,
+            &format!("{}/meminfo", dir),
+            &format!(
+                "Node {id} MemTotal:       {total} kB\nNode {id} MemFree:        {free} kB\n",
+                total = total_kb,
+                free = total_kb / 2,
+            ),
+        );
+        write(root, &format!("{}/distance", dir), &format!("{}\n", distances));
+    }
+    
+    /// Return true if `result` is an error for a file with unexpected contents.
+    fn is_invalid_data<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Io(err)) if err.kind() == io::ErrorKind::InvalidData)
+    }
+    
+    #[test]
+    fn lists_are_parsed() {
+        let path = Path::new("online");
+        assert_eq!(parse_list("0-3,8\n", path).unwrap(), [0, 1, 2, 3, 8]);
+        assert_eq!(parse_list("5", path).unwrap(), [5]);
+        assert!(parse_list("\n", path).unwrap().is_empty());
+        assert!(is_invalid_data(parse_list("3-1", path)));
+        assert!(is_invalid_data(parse_list("0-x", path)));
+    }
+    
+    #[test]
+    fn meminfo_values_are_read_in_bytes() {
+        let path = Path::new("meminfo");
+        let meminfo = "Node 0 MemTotal:        1024 kB\nNode 0 MemFree:          512 kB\n";
+        assert_eq!(read_meminfo(meminfo, "MemTotal:", path).unwrap(), 1024 * 1024);
+        assert_eq!(read_meminfo(meminfo, "MemFree:", path).unwrap(), 512 * 1024);
+        assert!(is_invalid_data(read_meminfo(meminfo, "MemUsed:", path)));
+        assert!(is_invalid_data(read_meminfo("Node 0 MemTotal: lots kB\n", "MemTotal:", path)));
+    }
+    
+    #[test]
+    fn topology_is_read_from_a_sysfs_tree() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        write(root, "devices/system/node/online", "0-1,3\n");
+        write_node(root, 0, "0-1", 4096, "10 20 30");
+        write_node(root, 1, "2-3", 2048, "20 10 20");
+        // A memory-only node
+        write_node(root, 3, "", 1024, "30 20 10");
+        
+        let topology = NumaTopology::read_from(root).unwrap();
+        let ids: Vec<u32> = topology.nodes().iter().map(|node| node.id).collect();
+        assert_eq!(ids, [0, 1, 3]);
+        assert_eq!(topology.online().iter().collect::<Vec<_>>(), [0, 1, 3]);
+        
+        let node = topology.node(1).unwrap();
+        assert_eq!(node.cpus, [2, 3]);
+        assert_eq!((node.memory_total, node.memory_free), (2048 * 1024, 1024 * 1024));
+        assert!(topology.node(3).unwrap().cpus.is_empty());
+        assert!(topology.node(2).is_none());
+        
+        assert_eq!(topology.distance(0, 3), Some(30));
+        assert_eq!(topology.distance(3, 1), Some(20));
+        assert_eq!(topology.distance(0, 2), None);
+        assert_eq!(topology.node_of_cpu(3), Some(1));
+        assert_eq!(topology.node_of_cpu(4), None);
+    }
+    
+    #[test]
+    fn nodes_are_ordered_by_distance() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        write(root, "devices/system/node/online", "0-3\n");
+        write_node(root, 0, "0", 1024, "10 21 21 12");
+        write_node(root, 1, "1", 1024, "21 10 12 21");
+        write_node(root, 2, "2", 1024, "21 12 10 21");
+        write_node(root, 3, "3", 1024, "12 21 21 10");
+        
+        let topology = NumaTopology::read_from(root).unwrap();
+        assert_eq!(topology.nodes_by_distance(0), [0, 3, 1, 2]);
+        assert_eq!(topology.nodes_by_distance(2), [2, 1, 0, 3]);
+        assert!(topology.nodes_by_distance(4).is_empty());
+    }
+    
+    #[test]
+    fn distance_row_must_cover_every_online_node() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        write(root, "devices/system/node/online", "0-1\n");
+        write_node(root, 0, "0", 1024, "10 20");
+        write_node(root, 1, "1", 1024, "20 10 30");
+        
+        assert!(is_invalid_data(NumaTopology::read_from(root)));
+    }
+    
+    #[test]
+    fn missing_sysfs_tree_has_no_nodes() {
+        let root = tempfile::tempdir().unwrap();
+        let topology = NumaTopology::read_from(root.path()).unwrap();
+        assert!(topology.nodes().is_empty());
+        assert!(topology.online().is_empty());
+    }
+}
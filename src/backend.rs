@@ -0,0 +1,969 @@
+//! Pluggable backends for the system calls behind memory maps.
+//!
+//! By default every memory map talks to the operating system through the
+//! functions in `platform`. A `Backend` given to `MmapOptions::backend` takes
+//! over the system calls of the memory maps created with those options, for
+//! their whole lifetime. `FaultInjectingBackend` uses this to fail chosen
+//! operations deterministically, so that error handling can be tested, and
+//! `HeapBackend` emulates memory maps without calling `mmap` at all.
+
+use std::alloc::{self, Layout};
+use std::any::Any;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+
+use crate::error::{Error, Result};
+use crate::mmap::{MmapOptions, MmapRaw, Placement};
+use crate::platform::{self, Advice, Protection};
+use crate::utils::alignment;
+
+/// The system calls used to create and manage memory maps.
+///
+/// Addresses passed to `unmap` and `lock` cover whole pages, and may start
+/// before the pointer returned by `map_file` if the file offset is not page
+/// aligned.
+///
+/// Operations that create, resize, flush or remove a memory map also receive
+/// its `MappingState`, where a backend can keep whatever it needs to know
+/// about each memory map.
+pub trait Backend: Send + Sync + fmt::Debug {
+    /// Map `len` bytes of `file` starting at `offset`, returning a pointer to
+    /// the byte at `offset`.
+    ///
+    /// # Safety
+    ///
+    /// See `MmapOptions::map`.
+    unsafe fn map_file(
+        &self,
+        file: &File,
+        offset: u64,
+        len: usize,
+        options: &MmapOptions,
+        state: &MappingState,
+    ) -> Result<*mut u8>;
+
+    /// Create an anonymous memory map of `len` bytes, returning a pointer to
+    /// its first byte.
+    ///
+    /// # Safety
+    ///
+    /// See `MmapOptions::map_anon`.
+    unsafe fn map_anon(&self, len: usize, options: &MmapOptions, state: &MappingState) -> Result<*mut u8>;
+
+    /// Flush a range of a memory map to disk.
+    ///
+    /// # Safety
+    ///
+    /// The range must lie within a live memory map.
+    unsafe fn flush(&self, addr: *mut u8, len: usize, async_flush: bool, state: &MappingState) -> Result<()>;
+
+    /// Unmap a memory map.
+    ///
+    /// # Safety
+    ///
+    /// The memory must not be accessed afterwards.
+    unsafe fn unmap(&self, addr: *mut u8, len: usize, state: &MappingState) -> Result<()>;
+
+    /// Resize a memory map from `old_len` to `new_len` bytes, returning its
+    /// possibly moved start address.
+    ///
+    /// # Safety
+    ///
+    /// On success, the memory must no longer be accessed through `addr`.
+    unsafe fn remap(&self, addr: *mut u8, old_len: usize, new_len: usize, state: &MappingState) -> Result<*mut u8>;
+
+    /// Advise the kernel about how a range of a memory map will be accessed.
+    ///
+    /// # Safety
+    ///
+    /// The range must lie within a live memory map. Some advice, such as
+    /// `DontNeed`, discards the contents of the range.
+    unsafe fn advise(&self, addr: *mut u8, len: usize, advice: Advice) -> Result<()>;
+
+    /// Change the protection of a range of a memory map.
+    ///
+    /// # Safety
+    ///
+    /// Removing access from memory that is still referenced causes faults on
+    /// the next access.
+    unsafe fn protect(&self, addr: *mut u8, len: usize, protection: Protection) -> Result<()>;
+
+    /// Lock a range of a memory map into RAM.
+    ///
+    /// # Safety
+    ///
+    /// The range must lie within a live memory map.
+    unsafe fn lock(&self, addr: *mut u8, len: usize, on_fault: bool) -> Result<()>;
+
+    /// Unlock a range of a memory map.
+    ///
+    /// # Safety
+    ///
+    /// The range must lie within a live memory map.
+    unsafe fn unlock(&self, addr: *mut u8, len: usize) -> Result<()>;
+
+    /// Report which pages of a range of a memory map are resident in RAM, one
+    /// entry per page.
+    ///
+    /// # Safety
+    ///
+    /// The range must lie within a live memory map and cover whole pages.
+    unsafe fn residency(&self, addr: *mut u8, len: usize) -> Result<Vec<bool>>;
+}
+
+/// Data a backend keeps about one memory map.
+///
+/// Every memory map owns one, starting out empty, and passes it to the
+/// backend calls that create, resize, flush and remove the map. The state is
+/// dropped with the memory map.
+#[derive(Default)]
+pub struct MappingState(Mutex<Option<Box<dyn Any + Send>>>);
+
+impl MappingState {
+    /// Create an empty state.
+    #[inline]
+    pub fn new() -> MappingState {
+        MappingState::default()
+    }
+
+    /// Store `value`, replacing any previous state.
+    #[inline]
+    pub fn set<T: Any + Send>(&self, value: T) {
+        *self.0.lock().unwrap() = Some(Box::new(value));
+    }
+
+    /// Call `f` with the stored state, if it is a `T`.
+    #[inline]
+    pub fn with<T: Any + Send, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        self.0.lock().unwrap().as_mut()?.downcast_mut().map(f)
+    }
+
+    /// Remove the stored state and return it, if it is a `T`.
+    pub fn take<T: Any + Send>(&self) -> Option<T> {
+        let mut state = self.0.lock().unwrap();
+        if !state.as_ref()?.is::<T>() {
+            return None;
+        }
+        state.take()?.downcast().ok().map(|value| *value)
+    }
+}
+
+impl fmt::Debug for MappingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappingState").finish_non_exhaustive()
+    }
+}
+
+/// The default backend, calling the operating system directly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsBackend;
+
+impl Backend for OsBackend {
+    unsafe fn map_file(
+        &self,
+        file: &File,
+        offset: u64,
+        len: usize,
+        options: &MmapOptions,
+        _state: &MappingState,
+    ) -> Result<*mut u8> {
+        platform::map_file(file, offset, len, options).map(MmapRaw::into_ptr)
+    }
+
+    unsafe fn map_anon(&self, len: usize, options: &MmapOptions, _state: &MappingState) -> Result<*mut u8> {
+        platform::map_anon(len, options).map(MmapRaw::into_ptr)
+    }
+
+    #[inline]
+    unsafe fn flush(&self, addr: *mut u8, len: usize, async_flush: bool, _state: &MappingState) -> Result<()> {
+        platform::flush(addr, len, async_flush)
+    }
+
+    #[inline]
+    unsafe fn unmap(&self, addr: *mut u8, len: usize, _state: &MappingState) -> Result<()> {
+        platform::unmap(addr, len)
+    }
+
+    #[inline]
+    unsafe fn remap(&self, addr: *mut u8, old_len: usize, new_len: usize, _state: &MappingState) -> Result<*mut u8> {
+        platform::remap(addr, old_len, new_len)
+    }
+
+    #[inline]
+    unsafe fn advise(&self, addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
+        platform::advise(addr, len, advice)
+    }
+
+    #[inline]
+    unsafe fn protect(&self, addr: *mut u8, len: usize, protection: Protection) -> Result<()> {
+        platform::protect(addr, len, protection)
+    }
+
+    #[inline]
+    unsafe fn lock(&self, addr: *mut u8, len: usize, on_fault: bool) -> Result<()> {
+        platform::lock(addr, len, on_fault)
+    }
+
+    #[inline]
+    unsafe fn unlock(&self, addr: *mut u8, len: usize) -> Result<()> {
+        platform::unlock(addr, len)
+    }
+
+    #[inline]
+    unsafe fn residency(&self, addr: *mut u8, len: usize) -> Result<Vec<bool>> {
+        platform::residency(addr, len)
+    }
+}
+
+/// A backend that emulates memory maps with heap buffers.
+///
+/// File maps are read into a buffer when they are created. Shared writable
+/// maps write the pages that changed back to the file when they are flushed
+/// and when they are unmapped, so their behavior matches `MAP_SHARED` closely
+/// enough for tests, as long as no other process modifies the file. This makes
+/// it possible to run code written against memory maps under Miri, in sandboxes
+/// that forbid `mmap`, and on files that cannot be mapped, such as pipes and
+/// procfs files. Such files usually report a length of zero, so the length of
+/// the map must be given explicitly.
+///
+/// Protection changes, advice and locking are accepted but have no effect, so
+/// guard pages do not fault. Every page is reported as resident, as heap
+/// buffers are not tracked page by page. Address hints are ignored, and fixed
+/// addresses are rejected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapBackend;
+
+/// A memory map emulated by `HeapBackend`, kept in its `MappingState`.
+struct HeapMapping {
+    /// Start address of the buffer.
+    base: usize,
+
+    /// Layout of the buffer.
+    layout: Layout,
+
+    /// The file backing the memory map, if any.
+    file: Option<File>,
+
+    /// Whether changes are written back to the file.
+    shared: bool,
+
+    /// Offset within the file of the first byte of the buffer.
+    file_offset: u64,
+
+    /// The contents of the file as last read or written, used to find the
+    /// pages that need writing back.
+    shadow: Vec<u8>,
+}
+
+impl HeapBackend {
+    /// Allocate a zeroed, page-aligned buffer of `len` bytes.
+    fn allocate(len: usize) -> Result<(*mut u8, Layout)> {
+        let layout = Layout::from_size_align(len, alignment::page_size()).map_err(|_| Error::SizeExceedsSystemLimit)?;
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(Error::Io(io::Error::from(io::ErrorKind::OutOfMemory)));
+        }
+        Ok((ptr, layout))
+    }
+
+    /// Reject placements that a heap allocation cannot honour.
+    fn check_placement(options: &MmapOptions) -> Result<()> {
+        match options.placement {
+            Some(Placement::Fixed(_)) => {
+                Err(Error::InvalidArgument("Fixed addresses are not supported by HeapBackend".into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the pages of `mapping` in `range` that changed since they were
+    /// last read or written back to its file.
+    ///
+    /// Bytes past the current end of the file are not written, as a shared map
+    /// never grows its file.
+    unsafe fn write_back(mapping: &mut HeapMapping, start: usize, end: usize) -> Result<()> {
+        let file = match mapping.file {
+            Some(ref file) if mapping.shared => file,
+            _ => return Ok(()),
+        };
+
+        let file_len = file.metadata()?.len();
+        let page_size = alignment::page_size();
+        let buf = slice::from_raw_parts(mapping.base as *const u8, mapping.layout.size());
+
+        let mut page = alignment::align_down(start, page_size);
+        while page < end {
+            let page_end = (page + page_size).min(mapping.layout.size());
+            if buf[page..page_end] != mapping.shadow[page..page_end] {
+                let offset = mapping.file_offset + page as u64;
+                let len = file_len.saturating_sub(offset).min((page_end - page) as u64) as usize;
+                write_all_at(file, &buf[page..page + len], offset)?;
+                mapping.shadow[page..page_end].copy_from_slice(&buf[page..page_end]);
+            }
+            page = page_end;
+        }
+
+        Ok(())
+    }
+
+    /// Move `mapping` to a new buffer of `new_len` bytes, returning its start
+    /// address.
+    unsafe fn reallocate(mapping: &mut HeapMapping, old_len: usize, new_len: usize) -> Result<*mut u8> {
+        let (new_base, layout) = HeapBackend::allocate(new_len)?;
+        let kept = old_len.min(new_len);
+        ptr::copy_nonoverlapping(mapping.base as *const u8, new_base, kept);
+
+        // Fill a grown file map with the file contents past the old end
+        if new_len > old_len {
+            if let Some(ref file) = mapping.file {
+                let tail = slice::from_raw_parts_mut(new_base.add(old_len), new_len - old_len);
+                if let Err(err) = read_full_at(file, tail, mapping.file_offset + old_len as u64) {
+                    alloc::dealloc(new_base, layout);
+                    return Err(Error::Io(err));
+                }
+                if mapping.shared {
+                    mapping.shadow.extend_from_slice(tail);
+                }
+            }
+        } else if mapping.shared {
+            mapping.shadow.truncate(new_len);
+        }
+
+        alloc::dealloc(mapping.base as *mut u8, mapping.layout);
+        mapping.base = new_base as usize;
+        mapping.layout = layout;
+
+        Ok(new_base)
+    }
+}
+
+impl Backend for HeapBackend {
+    unsafe fn map_file(
+        &self,
+        file: &File,
+        offset: u64,
+        len: usize,
+        options: &MmapOptions,
+        state: &MappingState,
+    ) -> Result<*mut u8> {
+        HeapBackend::check_placement(options)?;
+
+        // Start the buffer on a page boundary of the file, like a real map
+        let delta = (offset % alignment::page_size() as u64) as usize;
+        let file_offset = offset - delta as u64;
+        let size = len.checked_add(delta).ok_or(Error::SizeExceedsSystemLimit)?;
+        let file = file.try_clone()?;
+
+        let (base, layout) = HeapBackend::allocate(size)?;
+        let buf = slice::from_raw_parts_mut(base, size);
+        if let Err(err) = read_full_at(&file, buf, file_offset) {
+            alloc::dealloc(base, layout);
+            return Err(Error::Io(err));
+        }
+
+        let shared = options.writable && !options.copy_on_write;
+        state.set(HeapMapping {
+            base: base as usize,
+            layout,
+            file: Some(file),
+            shared,
+            file_offset,
+            shadow: if shared { buf.to_vec() } else { Vec::new() },
+        });
+
+        Ok(base.add(delta))
+    }
+
+    unsafe fn map_anon(&self, len: usize, options: &MmapOptions, state: &MappingState) -> Result<*mut u8> {
+        HeapBackend::check_placement(options)?;
+
+        let (base, layout) = HeapBackend::allocate(len)?;
+        state.set(HeapMapping {
+            base: base as usize,
+            layout,
+            file: None,
+            shared: false,
+            file_offset: 0,
+            shadow: Vec::new(),
+        });
+
+        Ok(base)
+    }
+
+    unsafe fn flush(&self, addr: *mut u8, len: usize, _async_flush: bool, state: &MappingState) -> Result<()> {
+        state
+            .with(|mapping: &mut HeapMapping| {
+                let start = (addr as usize).checked_sub(mapping.base)?;
+                if start + len > mapping.layout.size() {
+                    return None;
+                }
+                Some(HeapBackend::write_back(mapping, start, start + len))
+            })
+            .flatten()
+            .ok_or_else(|| Error::InvalidArgument("Range is not part of a heap memory map".into()))?
+    }
+
+    unsafe fn unmap(&self, addr: *mut u8, _len: usize, state: &MappingState) -> Result<()> {
+        let mut mapping = state
+            .take::<HeapMapping>()
+            .filter(|mapping| mapping.base == addr as usize)
+            .ok_or_else(|| Error::InvalidArgument("Address is not the start of a heap memory map".into()))?;
+
+        // Like the kernel, write back outstanding changes before unmapping
+        let size = mapping.layout.size();
+        let result = HeapBackend::write_back(&mut mapping, 0, size);
+        alloc::dealloc(addr, mapping.layout);
+        result
+    }
+
+    unsafe fn remap(&self, addr: *mut u8, old_len: usize, new_len: usize, state: &MappingState) -> Result<*mut u8> {
+        state
+            .with(|mapping: &mut HeapMapping| {
+                if mapping.base != addr as usize {
+                    return Err(Error::InvalidArgument("Address is not the start of a heap memory map".into()));
+                }
+                HeapBackend::reallocate(mapping, old_len, new_len)
+            })
+            .ok_or_else(|| Error::InvalidArgument("Address is not the start of a heap memory map".into()))?
+    }
+
+    #[inline]
+    unsafe fn advise(&self, _addr: *mut u8, _len: usize, _advice: Advice) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    unsafe fn protect(&self, _addr: *mut u8, _len: usize, _protection: Protection) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    unsafe fn lock(&self, _addr: *mut u8, _len: usize, _on_fault: bool) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    unsafe fn unlock(&self, _addr: *mut u8, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline]
+    unsafe fn residency(&self, _addr: *mut u8, len: usize) -> Result<Vec<bool>> {
+        Ok(vec![true; len.div_ceil(alignment::page_size())])
+    }
+}
+
+/// An operation of a `Backend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// `Backend::map_file`.
+    MapFile,
+
+    /// `Backend::map_anon`.
+    MapAnon,
+
+    /// `Backend::flush`.
+    Flush,
+
+    /// `Backend::unmap`.
+    Unmap,
+
+    /// `Backend::remap`.
+    Remap,
+
+    /// `Backend::advise`.
+    Advise,
+
+    /// `Backend::protect`.
+    Protect,
+
+    /// `Backend::lock`.
+    Lock,
+
+    /// `Backend::unlock`.
+    Unlock,
+
+    /// `Backend::residency`.
+    Residency,
+}
+
+impl Operation {
+    /// Number of operations.
+    const COUNT: usize = 10;
+}
+
+/// A planned failure of a `FaultInjectingBackend`.
+#[derive(Debug, Clone, Copy)]
+struct Fault {
+    /// The operation that fails.
+    operation: Operation,
+
+    /// The zero-based call that fails, or `None` for every call.
+    call: Option<usize>,
+
+    /// The errno the operation fails with.
+    errno: i32,
+}
+
+/// A backend that makes chosen operations fail, and forwards everything else
+/// to another backend.
+///
+/// Failures are deterministic: they are tied to the number of times each
+/// operation has been called on this backend. Injected errors take the same
+/// form as real ones, so for example a failed `Operation::Lock` with `EAGAIN`
+/// is reported as `Error::MemoryLockLimit` on Unix.
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use membase::{Error, MmapOptions};
+/// use membase::backend::{FaultInjectingBackend, Operation};
+///
+/// let backend = Arc::new(FaultInjectingBackend::new().fail_nth(Operation::Flush, 1, libc::EIO));
+/// let map = unsafe { MmapOptions::new().backend(backend.clone()).map_anon(4096)? };
+///
+/// map.flush()?;
+/// assert!(matches!(map.flush(), Err(Error::Io(_))));
+/// assert_eq!(backend.calls(Operation::Flush), 2);
+/// # Ok::<(), Error>(())
+/// ```
+#[derive(Debug)]
+pub struct FaultInjectingBackend {
+    /// The backend that operations which do not fail are forwarded to.
+    inner: Arc<dyn Backend>,
+
+    /// The planned failures.
+    faults: Mutex<Vec<Fault>>,
+
+    /// Number of calls made so far, per operation.
+    calls: [AtomicUsize; Operation::COUNT],
+}
+
+impl FaultInjectingBackend {
+    /// Create a backend that forwards to the operating system until told to
+    /// fail.
+    #[inline]
+    pub fn new() -> FaultInjectingBackend {
+        FaultInjectingBackend::wrap(Arc::new(OsBackend))
+    }
+
+    /// Create a backend that forwards to `inner` until told to fail.
+    pub fn wrap(inner: Arc<dyn Backend>) -> FaultInjectingBackend {
+        FaultInjectingBackend {
+            inner,
+            faults: Mutex::new(Vec::new()),
+            calls: Default::default(),
+        }
+    }
+
+    /// Make every call of `operation` fail with `errno`.
+    #[inline]
+    pub fn fail(self, operation: Operation, errno: i32) -> FaultInjectingBackend {
+        self.inject(Fault { operation, call: None, errno });
+        self
+    }
+
+    /// Make the `n`th call (counting from zero) of `operation` fail with
+    /// `errno`.
+    #[inline]
+    pub fn fail_nth(self, operation: Operation, n: usize, errno: i32) -> FaultInjectingBackend {
+        self.inject(Fault {
+            operation,
+            call: Some(n),
+            errno,
+        });
+        self
+    }
+
+    /// Make every call of `operation` from now on fail with `errno`.
+    ///
+    /// Unlike `fail`, this can be used while memory maps created with the
+    /// backend are alive.
+    #[inline]
+    pub fn inject_failure(&self, operation: Operation, errno: i32) {
+        self.inject(Fault { operation, call: None, errno });
+    }
+
+    /// Remove every planned failure.
+    #[inline]
+    pub fn clear(&self) {
+        self.faults.lock().unwrap().clear();
+    }
+
+    /// Return the number of times `operation` has been called, including
+    /// calls that failed.
+    #[inline]
+    pub fn calls(&self, operation: Operation) -> usize {
+        self.calls[operation as usize].load(Ordering::Relaxed)
+    }
+
+    /// Add a planned failure.
+    #[inline]
+    fn inject(&self, fault: Fault) {
+        self.faults.lock().unwrap().push(fault);
+    }
+
+    /// Count a call of `operation`, and return the error it should fail
+    /// with, if any.
+    fn check(&self, operation: Operation, len: usize) -> Result<()> {
+        let call = self.calls[operation as usize].fetch_add(1, Ordering::Relaxed);
+        let faults = self.faults.lock().unwrap();
+        let fault = faults
+            .iter()
+            .find(|fault| fault.operation == operation && fault.call.is_none_or(|n| n == call));
+
+        match fault {
+            Some(fault) => {
+                let err = io::Error::from_raw_os_error(fault.errno);
+                Err(match operation {
+                    Operation::Protect => Error::ProtectionError(err),
+                    Operation::Lock => platform::lock_error(Some(len), err),
+                    _ => Error::Io(err),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for FaultInjectingBackend {
+    #[inline]
+    fn default() -> FaultInjectingBackend {
+        FaultInjectingBackend::new()
+    }
+}
+
+impl Backend for FaultInjectingBackend {
+    unsafe fn map_file(
+        &self,
+        file: &File,
+        offset: u64,
+        len: usize,
+        options: &MmapOptions,
+        state: &MappingState,
+    ) -> Result<*mut u8> {
+        self.check(Operation::MapFile, len)?;
+        self.inner.map_file(file, offset, len, options, state)
+    }
+
+    unsafe fn map_anon(&self, len: usize, options: &MmapOptions, state: &MappingState) -> Result<*mut u8> {
+        self.check(Operation::MapAnon, len)?;
+        self.inner.map_anon(len, options, state)
+    }
+
+    unsafe fn flush(&self, addr: *mut u8, len: usize, async_flush: bool, state: &MappingState) -> Result<()> {
+        self.check(Operation::Flush, len)?;
+        self.inner.flush(addr, len, async_flush, state)
+    }
+
+    unsafe fn unmap(&self, addr: *mut u8, len: usize, state: &MappingState) -> Result<()> {
+        self.check(Operation::Unmap, len)?;
+        self.inner.unmap(addr, len, state)
+    }
+
+    unsafe fn remap(&self, addr: *mut u8, old_len: usize, new_len: usize, state: &MappingState) -> Result<*mut u8> {
+        self.check(Operation::Remap, new_len)?;
+        self.inner.remap(addr, old_len, new_len, state)
+    }
+
+    unsafe fn advise(&self, addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
+        self.check(Operation::Advise, len)?;
+        self.inner.advise(addr, len, advice)
+    }
+
+    unsafe fn protect(&self, addr: *mut u8, len: usize, protection: Protection) -> Result<()> {
+        self.check(Operation::Protect, len)?;
+        self.inner.protect(addr, len, protection)
+    }
+
+    unsafe fn lock(&self, addr: *mut u8, len: usize, on_fault: bool) -> Result<()> {
+        self.check(Operation::Lock, len)?;
+        self.inner.lock(addr, len, on_fault)
+    }
+
+    unsafe fn unlock(&self, addr: *mut u8, len: usize) -> Result<()> {
+        self.check(Operation::Unlock, len)?;
+        self.inner.unlock(addr, len)
+    }
+
+    unsafe fn residency(&self, addr: *mut u8, len: usize) -> Result<Vec<bool>> {
+        self.check(Operation::Residency, len)?;
+        self.inner.residency(addr, len)
+    }
+}
+
+/// Fill `buf` from `file` starting at `offset`, leaving bytes past the end of
+/// the file zeroed.
+///
+/// Files that cannot seek, such as pipes, are read sequentially instead when
+/// `offset` is zero.
+fn read_full_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match read_at(file, &mut buf[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(ref err) if offset == 0 && filled == 0 && is_unseekable(err) => return read_stream(file, buf),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Fill `buf` from the current position of a file that cannot seek.
+fn read_stream(mut file: &File, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Read from `file` at `offset` without moving its position.
+#[cfg(unix)]
+#[inline]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.read_at(buf, offset)
+}
+
+/// Read from `file` at `offset`.
+#[cfg(windows)]
+#[inline]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    file.seek_read(buf, offset)
+}
+
+/// Write all of `buf` to `file` at `offset` without moving its position.
+#[cfg(unix)]
+#[inline]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    file.write_all_at(buf, offset)
+}
+
+/// Write all of `buf` to `file` at `offset`.
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        let written = file.seek_write(buf, offset)?;
+        if written == 0 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero));
+        }
+        buf = &buf[written..];
+        offset += written as u64;
+    }
+    Ok(())
+}
+
+/// Return true if an error means the file does not support positional reads.
+#[inline]
+fn is_unseekable(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return err.raw_os_error() == Some(libc::ESPIPE);
+
+    #[cfg(not(unix))]
+    return err.kind() == io::ErrorKind::InvalidInput;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use super::*;
+    use crate::mmap::{self, test_lock};
+
+    /// Return the mapping counters.
+    fn counters() -> (usize, usize) {
+        (mmap::total_mapped_memory(), mmap::active_mappings())
+    }
+
+    #[test]
+    fn injected_map_anon_failure_creates_no_mapping() {
+        let _lock = test_lock();
+        let backend = Arc::new(FaultInjectingBackend::new().fail(Operation::MapAnon, libc::ENOMEM));
+        let before = counters();
+
+        let result = unsafe { MmapOptions::new().backend(backend.clone()).map_anon(4096) };
+        match result {
+            Err(Error::Io(ref err)) => assert_eq!(err.raw_os_error(), Some(libc::ENOMEM)),
+            ref result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(backend.calls(Operation::MapAnon), 1);
+        assert_eq!(backend.calls(Operation::Unmap), 0);
+        assert_eq!(counters(), before);
+    }
+
+    #[test]
+    fn injected_lock_failure_is_reported_like_a_real_one() {
+        let _lock = test_lock();
+        let backend = Arc::new(
+            FaultInjectingBackend::new()
+                .fail_nth(Operation::Lock, 0, libc::EAGAIN)
+                .fail_nth(Operation::Lock, 1, libc::EIO),
+        );
+        let len = alignment::page_size();
+        let map = unsafe { MmapOptions::new().backend(backend.clone()).map_anon(len) }.unwrap();
+        let mapped = counters();
+
+        match (map.lock(), platform::memlock_limit()) {
+            (Err(Error::MemoryLockLimit { requested, limit }), Some(expected)) => {
+                assert_eq!(requested, Some(len));
+                assert_eq!(limit, expected);
+            }
+            (Err(Error::Io(ref err)), None) => assert_eq!(err.raw_os_error(), Some(libc::EAGAIN)),
+            (result, _) => panic!("unexpected result: {:?}", result),
+        }
+        match map.lock() {
+            Err(Error::Io(ref err)) => assert_eq!(err.raw_os_error(), Some(libc::EIO)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(counters(), mapped);
+
+        map.lock().unwrap();
+        assert_eq!(backend.calls(Operation::Lock), 3);
+        drop(map);
+        assert_eq!(backend.calls(Operation::Unmap), 1);
+    }
+
+    #[test]
+    fn injected_flush_failure_keeps_the_map() {
+        let _lock = test_lock();
+        let backend = Arc::new(FaultInjectingBackend::new());
+        let before = counters();
+
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(4096).unwrap();
+        let mut map = unsafe { MmapOptions::new().backend(backend.clone()).map_mut(&file) }.unwrap();
+        let mapped = counters();
+        assert_eq!(mapped, (before.0 + 4096, before.1 + 1));
+
+        map[0] = 1;
+        backend.inject_failure(Operation::Flush, libc::EIO);
+        match map.flush() {
+            Err(Error::Io(ref err)) => assert_eq!(err.raw_os_error(), Some(libc::EIO)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(counters(), mapped);
+
+        backend.clear();
+        map.flush().unwrap();
+        assert_eq!(backend.calls(Operation::Flush), 2);
+        drop(map);
+        assert_eq!(counters(), before);
+    }
+
+    #[test]
+    fn heap_map_writes_back_on_flush_and_drop() {
+        let _lock = test_lock();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[1; 10000]).unwrap();
+        let options = MmapOptions::new().backend(Arc::new(HeapBackend));
+
+        let mut map = unsafe { options.map_mut(&file) }.unwrap();
+        assert_eq!(map.len(), 10000);
+        assert!(map.iter().all(|&byte| byte == 1));
+
+        map[..5].copy_from_slice(b"hello");
+        map.flush().unwrap();
+        let mut contents = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(&contents[..5], b"hello");
+        assert_eq!(contents.len(), 10000);
+
+        // Unflushed changes are written back when the map is dropped
+        map[9999] = 2;
+        drop(map);
+        let reread = unsafe { options.map(&file) }.unwrap();
+        assert_eq!(&reread[..5], b"hello");
+        assert_eq!(reread[9999], 2);
+    }
+
+    #[test]
+    fn heap_private_map_leaves_the_file_alone() {
+        let _lock = test_lock();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"original").unwrap();
+
+        let options = MmapOptions::new().backend(Arc::new(HeapBackend)).copy_on_write(true);
+        let mut map = unsafe { options.map_mut(&file) }.unwrap();
+        map.copy_from_slice(b"modified");
+        map.flush().unwrap();
+        drop(map);
+
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "original");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn heap_map_reads_a_pipe() {
+        use std::os::unix::io::{FromRawFd, OwnedFd};
+
+        let _lock = test_lock();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (reader, mut writer) = unsafe {
+            (
+                File::from(OwnedFd::from_raw_fd(fds[0])),
+                File::from(OwnedFd::from_raw_fd(fds[1])),
+            )
+        };
+        writer.write_all(b"through a pipe").unwrap();
+        drop(writer);
+
+        // The OS cannot map a pipe at all
+        assert!(unsafe { MmapOptions::new().len(14).map(&reader) }.is_err());
+
+        let map = unsafe { MmapOptions::new().backend(Arc::new(HeapBackend)).len(20).map(&reader) }.unwrap();
+        assert_eq!(&map[..14], b"through a pipe");
+        assert_eq!(&map[14..], &[0; 6]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn heap_map_resizes_with_its_file() {
+        let _lock = test_lock();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&[3; 100]).unwrap();
+
+        let mut map = unsafe { MmapOptions::new().backend(Arc::new(HeapBackend)).map_mut(&file) }.unwrap();
+        map.resize(20000).unwrap();
+        assert!(map[..100].iter().all(|&byte| byte == 3));
+        assert!(map[100..].iter().all(|&byte| byte == 0));
+
+        map[19999] = 4;
+        map.flush().unwrap();
+        drop(map);
+        let reread = unsafe { MmapOptions::new().map(&file) }.unwrap();
+        assert_eq!(reread.len(), 20000);
+        assert_eq!(reread[19999], 4);
+    }
+
+    #[test]
+    fn heap_residency_reports_every_page_resident() {
+        let _lock = test_lock();
+        let len = alignment::page_size() * 3;
+        let map = unsafe { MmapOptions::new().backend(Arc::new(HeapBackend)).map_anon(len) }.unwrap();
+
+        let residency = map.residency().unwrap();
+        assert_eq!(residency.resident_bytes(), len);
+        assert!(map.is_resident(0, len).unwrap());
+    }
+}
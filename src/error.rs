@@ -4,6 +4,8 @@ use std::fmt;
 use std::io;
 use std::result;
 
+use crate::advanced::PageSize;
+
 /// A specialized `Result` type for memory mapping operations.
 pub type Result<T> = result::Result<T, Error>;
 
@@ -20,22 +22,82 @@ pub enum Error {
     SizeExceedsSystemLimit,
     
     /// Failed to allocate huge pages.
-    HugePageAllocationFailed,
+    HugePageAllocationFailed {
+        /// Each kind of page that was tried, in order, with the reason it
+        /// could not be used.
+        attempts: Vec<(PageSize, io::Error)>,
+    },
+    
+    /// No writable hugetlbfs file system with pages of the requested size is
+    /// mounted.
+    HugetlbfsNotMounted {
+        /// The requested page size.
+        page_size: PageSize,
+    },
     
-    /// NUMA allocation failed.
-    NumaAllocationFailed,
+    /// Applying a NUMA policy failed, for example because it names a node
+    /// that does not exist or has no memory.
+    NumaAllocationFailed(io::Error),
     
-    /// Memory protection error.
-    ProtectionError,
+    /// Changing the protection of a memory map failed.
+    ProtectionError(io::Error),
     
     /// Memory alignment error.
     AlignmentError,
     
+    /// A requested memory map address is not suitably aligned.
+    UnalignedAddress {
+        /// The requested address.
+        address: usize,
+        /// The alignment the address must have.
+        alignment: usize,
+    },
+    
+    /// A memory map could not be placed at the requested address because
+    /// another mapping already occupies it.
+    AddressInUse {
+        /// The requested address.
+        address: usize,
+    },
+    
     /// Invalid argument provided.
     InvalidArgument(String),
     
     /// Platform-specific error with error code.
     PlatformError(i32),
+    
+    /// A shared memory segment with this name already exists.
+    SharedMemoryExists(String),
+    
+    /// No shared memory segment with this name exists.
+    SharedMemoryNotFound(String),
+    
+    /// A shared memory segment with this name exists, but the process that
+    /// created it has not set its size in time.
+    SharedMemoryNotSized(String),
+    
+    /// A read from a file mapping reached pages past the end of the file,
+    /// typically because another process truncated it.
+    TruncatedMapping {
+        /// Offset within the memory map of the first byte that could not be read.
+        offset: usize,
+    },
+    
+    /// Locking memory failed because it would exceed `RLIMIT_MEMLOCK`.
+    MemoryLockLimit {
+        /// The number of bytes that were to be locked, if known.
+        requested: Option<usize>,
+        /// The soft `RLIMIT_MEMLOCK` limit in bytes.
+        limit: u64,
+    },
+    
+    /// An existing object does not have the expected size.
+    SizeMismatch {
+        /// The size that was requested.
+        expected: usize,
+        /// The size that was found.
+        actual: usize,
+    },
 }
 
 impl fmt::Display for Error {
@@ -44,12 +106,46 @@ impl fmt::Display for Error {
             Error::Io(err) => write!(f, "I/O error: {}", err),
             Error::ZeroSizedMapping => write!(f, "Memory map size cannot be zero"),
             Error::SizeExceedsSystemLimit => write!(f, "Requested memory map size exceeds system limits"),
-            Error::HugePageAllocationFailed => write!(f, "Failed to allocate huge pages"),
-            Error::NumaAllocationFailed => write!(f, "NUMA memory allocation failed"),
-            Error::ProtectionError => write!(f, "Memory protection error"),
+            Error::HugePageAllocationFailed { attempts } => {
+                write!(f, "Failed to allocate huge pages")?;
+                for (i, (size, err)) in attempts.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i == 0 { ":" } else { ";" }, size, err)?;
+                }
+                Ok(())
+            }
+            Error::HugetlbfsNotMounted { page_size } => write!(
+                f,
+                "No writable hugetlbfs file system with {} pages is mounted (see /proc/mounts)",
+                page_size
+            ),
+            Error::NumaAllocationFailed(err) => write!(f, "NUMA memory allocation failed: {}", err),
+            Error::ProtectionError(err) => write!(f, "Memory protection error: {}", err),
             Error::AlignmentError => write!(f, "Memory alignment error"),
+            Error::UnalignedAddress { address, alignment } => {
+                write!(f, "Address {:#x} is not aligned to {} bytes", address, alignment)
+            }
+            Error::AddressInUse { address } => write!(f, "Address {:#x} is already in use", address),
             Error::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
             Error::PlatformError(code) => write!(f, "Platform-specific error code: {}", code),
+            Error::SharedMemoryExists(name) => write!(f, "Shared memory segment already exists: {}", name),
+            Error::SharedMemoryNotFound(name) => write!(f, "Shared memory segment not found: {}", name),
+            Error::SharedMemoryNotSized(name) => {
+                write!(f, "Shared memory segment has not been sized by its creator: {}", name)
+            }
+            Error::TruncatedMapping { offset } => {
+                write!(f, "Memory map is truncated: offset {} lies past the end of the file", offset)
+            }
+            Error::MemoryLockLimit { requested: Some(requested), limit } => write!(
+                f,
+                "Cannot lock {} bytes of memory: RLIMIT_MEMLOCK is {} bytes",
+                requested, limit
+            ),
+            Error::MemoryLockLimit { requested: None, limit } => {
+                write!(f, "Cannot lock memory: RLIMIT_MEMLOCK is {} bytes", limit)
+            }
+            Error::SizeMismatch { expected, actual } => {
+                write!(f, "Size mismatch: expected {} bytes, found {} bytes", expected, actual)
+            }
         }
     }
 }
@@ -58,6 +154,8 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::Io(err) => Some(err),
+            Error::ProtectionError(err) => Some(err),
+            Error::NumaAllocationFailed(err) => Some(err),
             _ => None,
         }
     }
@@ -68,3 +166,71 @@ impl From<io::Error> for Error {
         Error::Io(err)
     }
 }
+
+/// The error returned when changing the protection of a whole memory map
+/// fails, holding the memory map so that it is not lost.
+#[derive(Debug)]
+pub struct ProtectError<T> {
+    /// Why the protection could not be changed.
+    error: Error,
+
+    /// The memory map whose protection was to be changed, boxed to keep
+    /// results small.
+    map: Box<T>,
+}
+
+impl<T> ProtectError<T> {
+    /// Create an error for `map`.
+    #[inline]
+    pub(crate) fn new(error: Error, map: T) -> ProtectError<T> {
+        ProtectError { error, map: Box::new(map) }
+    }
+
+    /// Return why the protection could not be changed.
+    #[inline]
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Return the memory map, discarding the error.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        *self.map
+    }
+
+    /// Split into the error and the memory map.
+    #[inline]
+    pub fn into_parts(self) -> (Error, T) {
+        (self.error, *self.map)
+    }
+
+    /// Convert the memory map held by the error.
+    #[inline]
+    pub(crate) fn map<U, F: FnOnce(T) -> U>(self, f: F) -> ProtectError<U> {
+        ProtectError {
+            error: self.error,
+            map: Box::new(f(*self.map)),
+        }
+    }
+}
+
+impl<T> fmt::Display for ProtectError<T> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for ProtectError<T> {
+    #[inline]
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<T> From<ProtectError<T>> for Error {
+    #[inline]
+    fn from(err: ProtectError<T>) -> Error {
+        err.error
+    }
+}
@@ -0,0 +1,191 @@
+//! Huge-page-backed files on hugetlbfs.
+//!
+//! This module provides `HugeFile`, a file on a mounted hugetlbfs whose pages
+//! are huge pages from the hugetlb pool. Unlike huge-page anonymous maps, such
+//! files have a path, so they persist after the creating process exits and
+//! unrelated processes can map the same huge pages.
+
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use crate::advanced::huge_pages::HugetlbfsMount;
+use crate::advanced::HugePageSize;
+use crate::error::{Error, Result};
+use crate::mmap::{Mmap, MmapMut, MmapOptions};
+use crate::utils::alignment;
+
+/// A file on a mounted hugetlbfs, backed by huge pages.
+///
+/// Files are found by name on the first writable hugetlbfs mounted with the
+/// requested page size. Dropping the handle does not remove the file; files
+/// persist until `HugeFile::remove` is called or the file system is
+/// unmounted.
+#[derive(Debug)]
+pub struct HugeFile {
+    /// Path of the file.
+    path: PathBuf,
+
+    /// The open file.
+    file: File,
+
+    /// Size of the pages backing the file.
+    page_size: HugePageSize,
+}
+
+impl HugeFile {
+    /// Create a new file of at least `len` bytes backed by huge pages of
+    /// `size`.
+    ///
+    /// The length is rounded up to a multiple of the huge page size. Returns
+    /// `Error::HugetlbfsNotMounted` if no suitable hugetlbfs is mounted, and
+    /// fails if a file with this name already exists.
+    pub fn create(name: &str, len: usize, size: HugePageSize) -> Result<HugeFile> {
+        if len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+        let len = len
+            .checked_add(size.bytes() - 1)
+            .map(|len| alignment::align_down(len, size.bytes()))
+            .ok_or(Error::SizeExceedsSystemLimit)?;
+
+        let path = HugeFile::path_of(name, size)?;
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+
+        if let Err(err) = file.set_len(len as u64) {
+            let _ = fs::remove_file(&path);
+            return Err(Error::Io(err));
+        }
+
+        Ok(HugeFile {
+            path,
+            file,
+            page_size: size,
+        })
+    }
+
+    /// Open an existing file backed by huge pages of `size` for reading and
+    /// writing.
+    pub fn open(name: &str, size: HugePageSize) -> Result<HugeFile> {
+        let path = HugeFile::path_of(name, size)?;
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+        Ok(HugeFile {
+            path,
+            file,
+            page_size: size,
+        })
+    }
+
+    /// Remove the file with this name backed by huge pages of `size`.
+    ///
+    /// Its huge pages return to the pool once every handle and memory map of
+    /// the file is gone.
+    #[inline]
+    pub fn remove(name: &str, size: HugePageSize) -> Result<()> {
+        let path = HugeFile::path_of(name, size)?;
+        fs::remove_file(path).map_err(Error::Io)
+    }
+
+    /// Return the path of the file.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the size of the pages backing the file.
+    #[inline]
+    pub fn page_size(&self) -> HugePageSize {
+        self.page_size
+    }
+
+    /// Return the length of the file in bytes.
+    #[inline]
+    pub fn len(&self) -> Result<usize> {
+        let len = self.file.metadata()?.len();
+        len.try_into().map_err(|_| Error::SizeExceedsSystemLimit)
+    }
+
+    /// Return true if the file is empty.
+    #[inline]
+    pub fn is_empty(&self) -> Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Return the open file.
+    #[inline]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Create a read-only memory map of the file.
+    ///
+    /// The map is always shared with the file. An `offset` in `options` must
+    /// be a multiple of the huge page size. Mapping fails if the hugetlb pool
+    /// does not have enough free pages for the map.
+    ///
+    /// # Safety
+    ///
+    /// Other processes mapping the file may modify the memory at any time,
+    /// which might lead to undefined behavior.
+    #[inline]
+    pub unsafe fn map(&self, options: &MmapOptions) -> Result<Mmap> {
+        self.options(options)?.map(&self.file)
+    }
+
+    /// Create a writable memory map of the file.
+    ///
+    /// See `map` for the requirements on `options`.
+    ///
+    /// # Safety
+    ///
+    /// Other processes mapping the file may access the memory at any time,
+    /// which might lead to undefined behavior.
+    #[inline]
+    pub unsafe fn map_mut(&self, options: &MmapOptions) -> Result<MmapMut> {
+        self.options(options)?.map_mut(&self.file)
+    }
+
+    /// Return the options to map the file with.
+    fn options(&self, options: &MmapOptions) -> Result<MmapOptions> {
+        if !options.offset.is_multiple_of(self.page_size.bytes() as u64) {
+            return Err(Error::InvalidArgument(format!(
+                "Offset {} is not a multiple of the huge page size",
+                options.offset
+            )));
+        }
+        Ok(options.clone().copy_on_write(false).huge_pages(self.page_size))
+    }
+
+    /// Return the path of the file called `name` on a hugetlbfs with pages of
+    /// `size`.
+    fn path_of(name: &str, size: HugePageSize) -> Result<PathBuf> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(Error::InvalidArgument(format!("Invalid huge page file name: {:?}", name)));
+        }
+
+        let mount = HugetlbfsMount::find(size)?;
+        Ok(mount.path.join(name))
+    }
+}
+
+impl AsFd for HugeFile {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl AsRawFd for HugeFile {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl From<HugeFile> for File {
+    #[inline]
+    fn from(file: HugeFile) -> File {
+        file.file
+    }
+}
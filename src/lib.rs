@@ -33,24 +33,53 @@
 //! let map = unsafe { MmapOptions::new().map(&file)? };
 //!
 //! // Access the memory map
-//! if map.len() >= 8 {
-//!     let value = unsafe { *(map.as_ptr() as *const u64) };
+//! if let Ok(value) = map.read_at::<u64>(0) {
 //!     println!("First 8 bytes as u64: {}", value);
 //! }
 //! # Ok(())
 //! # }
 //! ```
 
+pub mod backend;
 pub mod error;
+#[cfg(target_os = "linux")]
+pub mod hugetlbfs;
 pub mod mmap;
+#[cfg(target_os = "linux")]
+pub mod memfd;
+pub mod pod;
+pub mod region;
+pub mod remap;
+pub mod reservation;
+pub mod residency;
+pub mod ring;
+#[cfg(unix)]
+pub mod shm;
+pub mod source;
+pub mod stack;
 pub mod platform;
 pub mod advanced;
 pub mod columnar;
 pub mod utils;
 
-pub use error::{Error, Result};
-pub use mmap::{Mmap, MmapMut, MmapOptions, MmapRaw};
-pub use advanced::{HugePageSize, NumaPolicy, PrefetchStrategy};
+pub use backend::{Backend, FaultInjectingBackend, HeapBackend, MappingState, OsBackend};
+pub use error::{Error, ProtectError, Result};
+#[cfg(target_os = "linux")]
+pub use hugetlbfs::HugeFile;
+pub use mmap::{Mmap, MmapMut, MmapOptions, MmapRaw, Placement};
+#[cfg(target_os = "linux")]
+pub use memfd::{MemFd, MemFdMmap, Seals};
+pub use pod::Pod;
+pub use region::MmapRegion;
+pub use remap::{MmapSnapshot, RemappingMmap};
+pub use reservation::Reservation;
+pub use residency::Residency;
+pub use ring::{Consumer, Producer, RingBuffer};
+#[cfg(unix)]
+pub use shm::SharedMemory;
+pub use source::{MmapOrBuffer, Source};
+pub use stack::Stack;
+pub use advanced::{HugePagePolicy, HugePageSize, NodeMask, NumaFlags, NumaPolicy, PageSize, PrefetchStrategy, ThpMode};
 
 /// Version information
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
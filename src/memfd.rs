@@ -0,0 +1,312 @@
+//! Shareable anonymous memory backed by `memfd_create`.
+//!
+//! This module provides `MemFd`, an anonymous memory-backed file that can be
+//! mapped by several processes, and the ability to pass it between processes
+//! over a Unix socket. Seals let the sender restrict what receivers may do
+//! with the memory.
+
+use std::fs::File;
+use std::ops::{BitOr, BitOrAssign, Deref, DerefMut};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use crate::advanced::HugePageSize;
+use crate::error::{Error, Result};
+use crate::mmap::{Mmap, MmapMut, MmapOptions};
+use crate::platform;
+
+/// A set of seals restricting how a `MemFd` may be modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Seals(libc::c_int);
+
+impl Seals {
+    /// Prevent any further seals from being added.
+    pub const SEAL: Seals = Seals(libc::F_SEAL_SEAL);
+
+    /// Prevent the file from shrinking.
+    pub const SHRINK: Seals = Seals(libc::F_SEAL_SHRINK);
+
+    /// Prevent the file from growing.
+    pub const GROW: Seals = Seals(libc::F_SEAL_GROW);
+
+    /// Prevent any writes to the file, including through shared writable maps.
+    pub const WRITE: Seals = Seals(libc::F_SEAL_WRITE);
+
+    /// Prevent new writable maps and writes, while existing writable maps stay
+    /// usable.
+    pub const FUTURE_WRITE: Seals = Seals(libc::F_SEAL_FUTURE_WRITE);
+
+    /// Return an empty set of seals.
+    #[inline]
+    pub const fn empty() -> Seals {
+        Seals(0)
+    }
+
+    /// Return true if every seal in `other` is also in `self`.
+    #[inline]
+    pub const fn contains(self, other: Seals) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Return true if no seals are set.
+    #[inline]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Return the raw seal bits.
+    #[inline]
+    pub const fn bits(self) -> libc::c_int {
+        self.0
+    }
+}
+
+impl BitOr for Seals {
+    type Output = Seals;
+
+    #[inline]
+    fn bitor(self, rhs: Seals) -> Seals {
+        Seals(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Seals {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Seals) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// An anonymous memory-backed file that can be shared between processes.
+#[derive(Debug)]
+pub struct MemFd {
+    file: File,
+}
+
+impl MemFd {
+    /// Create a memfd of `len` bytes.
+    ///
+    /// `name` is only used for debugging, and shows up in `/proc/self/fd`. The
+    /// memfd allows sealing.
+    pub fn create(name: &str, len: usize, huge_pages: Option<HugePageSize>) -> Result<MemFd> {
+        if len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+
+        let file = platform::memfd_create(name, huge_pages, true)?;
+        file.set_len(len as u64)?;
+
+        Ok(MemFd { file })
+    }
+
+    /// Wrap a file descriptor received from elsewhere.
+    #[inline]
+    pub fn from_fd(fd: OwnedFd) -> MemFd {
+        MemFd { file: File::from(fd) }
+    }
+
+    /// Receive a memfd sent with `send` over a Unix socket.
+    ///
+    /// The length and seals of the memfd are those set by the sender, and can
+    /// be inspected with `len` and `seals`.
+    #[inline]
+    pub fn receive(socket: &UnixStream) -> Result<MemFd> {
+        platform::receive_fd(socket).map(MemFd::from_fd)
+    }
+
+    /// Send the memfd over a Unix socket.
+    ///
+    /// The receiving process gets its own descriptor for the same memory.
+    #[inline]
+    pub fn send(&self, socket: &UnixStream) -> Result<()> {
+        platform::send_fd(socket, self.file.as_fd())
+    }
+
+    /// Return the length of the memfd in bytes.
+    #[inline]
+    pub fn len(&self) -> Result<usize> {
+        let len = self.file.metadata()?.len();
+        len.try_into().map_err(|_| Error::SizeExceedsSystemLimit)
+    }
+
+    /// Return true if the memfd is empty.
+    #[inline]
+    pub fn is_empty(&self) -> Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Return the seals applied to the memfd.
+    #[inline]
+    pub fn seals(&self) -> Result<Seals> {
+        platform::get_seals(&self.file).map(Seals)
+    }
+
+    /// Add seals to the memfd.
+    ///
+    /// Seals can never be removed. Adding `Seals::WRITE` fails while any shared
+    /// writable map of the memfd exists.
+    #[inline]
+    pub fn add_seals(&self, seals: Seals) -> Result<()> {
+        platform::add_seals(&self.file, seals.bits())
+    }
+
+    /// Return the file backing the memfd.
+    #[inline]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Create a read-only memory map of the whole memfd.
+    ///
+    /// # Safety
+    ///
+    /// Other processes sharing the memfd may modify the memory at any time,
+    /// which might lead to undefined behavior.
+    #[inline]
+    pub unsafe fn map(&self, options: &MmapOptions) -> Result<Mmap> {
+        options.clone().copy_on_write(false).map(&self.file)
+    }
+
+    /// Create a writable memory map of the whole memfd.
+    ///
+    /// Fails if the memfd is sealed against writes.
+    ///
+    /// # Safety
+    ///
+    /// Other processes sharing the memfd may access the memory at any time,
+    /// which might lead to undefined behavior.
+    #[inline]
+    pub unsafe fn map_mut(&self, options: &MmapOptions) -> Result<MmapMut> {
+        options.clone().copy_on_write(false).map_mut(&self.file)
+    }
+}
+
+impl AsFd for MemFd {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl AsRawFd for MemFd {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl From<MemFd> for OwnedFd {
+    #[inline]
+    fn from(memfd: MemFd) -> OwnedFd {
+        OwnedFd::from(memfd.file)
+    }
+}
+
+/// A writable memory map of a `MemFd`.
+///
+/// Dereferences to the underlying `MmapMut`.
+#[derive(Debug)]
+pub struct MemFdMmap {
+    memfd: MemFd,
+    map: MmapMut,
+}
+
+impl MemFdMmap {
+    /// Create a shareable anonymous memory map of `len` bytes.
+    ///
+    /// # Safety
+    ///
+    /// Once the memfd has been shared, other processes may access the memory at
+    /// any time, which might lead to undefined behavior.
+    pub(crate) unsafe fn new(options: &MmapOptions, len: usize) -> Result<MemFdMmap> {
+        let memfd = MemFd::create("membase-shared", len, options.huge_pages)?;
+        let map = memfd.map_mut(&options.clone().offset(0).len(len))?;
+
+        Ok(MemFdMmap { memfd, map })
+    }
+
+    /// Return the memfd backing the memory map.
+    #[inline]
+    pub fn memfd(&self) -> &MemFd {
+        &self.memfd
+    }
+
+    /// Return the file descriptor of the memfd backing the memory map.
+    #[inline]
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.memfd.as_fd()
+    }
+
+    /// Send the memfd backing the memory map over a Unix socket.
+    #[inline]
+    pub fn send(&self, socket: &UnixStream) -> Result<()> {
+        self.memfd.send(socket)
+    }
+
+    /// Split into the memfd and its memory map.
+    #[inline]
+    pub fn into_parts(self) -> (MemFd, MmapMut) {
+        (self.memfd, self.map)
+    }
+}
+
+impl Deref for MemFdMmap {
+    type Target = MmapMut;
+
+    #[inline]
+    fn deref(&self) -> &MmapMut {
+        &self.map
+    }
+}
+
+impl DerefMut for MemFdMmap {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut MmapMut {
+        &mut self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmap::test_lock;
+
+    #[test]
+    fn memfd_round_trips_over_a_socket() {
+        let _lock = test_lock();
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let memfd = MemFd::create("membase-test", 8192, None).unwrap();
+        let mut map = unsafe { memfd.map_mut(&MmapOptions::new()) }.unwrap();
+        map[..5].copy_from_slice(b"hello");
+        memfd.add_seals(Seals::GROW | Seals::SHRINK).unwrap();
+
+        memfd.send(&sender).unwrap();
+        let received = MemFd::receive(&receiver).unwrap();
+        assert_eq!(received.len().unwrap(), 8192);
+        let seals = received.seals().unwrap();
+        assert!(seals.contains(Seals::GROW | Seals::SHRINK));
+        assert!(!seals.contains(Seals::WRITE));
+        assert!(received.file().set_len(4096).is_err());
+
+        // Both descriptors refer to the same memory
+        let received_map = unsafe { received.map(&MmapOptions::new()) }.unwrap();
+        assert_eq!(&received_map[..5], b"hello");
+        map[0] = b'j';
+        assert_eq!(&received_map[..5], b"jello");
+    }
+
+    #[test]
+    fn sealed_memfd_cannot_be_mapped_writable_by_the_receiver() {
+        let _lock = test_lock();
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let memfd = MemFd::create("membase-test", 4096, None).unwrap();
+        memfd.add_seals(Seals::WRITE | Seals::SEAL).unwrap();
+
+        memfd.send(&sender).unwrap();
+        let received = MemFd::receive(&receiver).unwrap();
+        assert!(received.seals().unwrap().contains(Seals::WRITE | Seals::SEAL));
+        assert!(unsafe { received.map_mut(&MmapOptions::new()) }.is_err());
+        assert!(received.add_seals(Seals::GROW).is_err());
+        assert!(unsafe { received.map(&MmapOptions::new()) }.is_ok());
+    }
+}
@@ -3,26 +3,73 @@
 //! This module provides the primary interfaces for memory mapping operations,
 //! with a focus on performance and safety.
 
-use std::fs::File;
-use std::ops::{Deref, DerefMut};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::ops::{Deref, DerefMut, Range};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::result;
 use std::slice;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use crate::error::{Error, Result};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::io::AsFd;
+
+use crate::backend::{Backend, MappingState, OsBackend};
+use crate::error::{Error, ProtectError, Result};
 use crate::platform;
-use crate::advanced::{HugePageSize, NumaPolicy, PrefetchStrategy};
+use crate::pod::Pod;
+use crate::region::MmapRegion;
+use crate::residency::Residency;
+#[cfg(target_os = "linux")]
+use crate::memfd::MemFdMmap;
+use crate::advanced::{
+    huge_pages, HugePagePolicy, HugePageSize, NumaFlags, NumaPolicy, PageSize, PrefetchStrategy, ThpMode,
+};
 use crate::utils::alignment;
 
+/// Size of the bounce buffer used by `try_copy_to`.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Statistics for memory mapping operations
 static TOTAL_MAPPED_MEMORY: AtomicUsize = AtomicUsize::new(0);
 static ACTIVE_MAPPINGS: AtomicUsize = AtomicUsize::new(0);
 
+/// Where in the address space a memory map should be placed.
+///
+/// Addresses are plain integers rather than pointers, since they usually come
+/// from a previous run of the program rather than from a live allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Map exactly at this address, failing with `Error::AddressInUse` if any
+    /// part of the range is already mapped.
+    Fixed(usize),
+
+    /// Prefer this address, but let the operating system choose another one if
+    /// it is unavailable.
+    Hint(usize),
+}
+
+impl Placement {
+    /// Return the requested address.
+    #[inline]
+    pub fn address(self) -> usize {
+        match self {
+            Placement::Fixed(address) | Placement::Hint(address) => address,
+        }
+    }
+}
+
 /// Configuration options for memory mapping.
 #[derive(Debug, Clone)]
 pub struct MmapOptions {
     /// The offset within the file at which the memory map will start.
-    offset: u64,
+    pub(crate) offset: u64,
     
     /// The length of the memory map.
     len: Option<usize>,
@@ -39,9 +86,18 @@ pub struct MmapOptions {
     /// Whether the memory map should be backed by huge pages.
     pub huge_pages: Option<HugePageSize>,
     
+    /// How the memory map should use transparent huge pages.
+    pub transparent_huge_pages: Option<ThpMode>,
+    
+    /// Which pages to try for anonymous memory maps, overriding `huge_pages`.
+    pub huge_page_policy: Option<HugePagePolicy>,
+    
     /// NUMA policy for the memory map.
     pub numa_policy: Option<NumaPolicy>,
     
+    /// Flags for applying the NUMA policy.
+    pub numa_flags: NumaFlags,
+    
     /// Prefetching strategy for the memory map.
     pub prefetch: Option<PrefetchStrategy>,
     
@@ -56,6 +112,24 @@ pub struct MmapOptions {
     
     /// Custom alignment for the memory map.
     pub alignment: Option<usize>,
+    
+    /// Requested address of the memory map.
+    pub placement: Option<Placement>,
+    
+    /// Whether to fsync the parent directory after creating a file.
+    pub sync_parent_dir: bool,
+    
+    /// Number of guard pages before and after an anonymous memory map.
+    pub guard_pages: (usize, usize),
+    
+    /// Whether to lock the memory map into RAM.
+    pub locked: bool,
+    
+    /// Whether locking is deferred until pages are first touched.
+    pub lock_on_fault: bool,
+    
+    /// Backend performing the system calls, or `None` for the operating system.
+    pub backend: Option<Arc<dyn Backend>>,
 }
 
 impl Default for MmapOptions {
@@ -67,12 +141,21 @@ impl Default for MmapOptions {
             writable: false,
             executable: false,
             huge_pages: None,
+            transparent_huge_pages: None,
+            huge_page_policy: None,
             numa_policy: None,
+            numa_flags: NumaFlags::empty(),
             prefetch: None,
             stack: false,
             copy_on_write: false,
             populate: false,
             alignment: None,
+            placement: None,
+            sync_parent_dir: false,
+            guard_pages: (0, 0),
+            locked: false,
+            lock_on_fault: false,
+            backend: None,
         }
     }
 }
@@ -122,19 +205,66 @@ impl MmapOptions {
     }
 
     /// Configure the memory map to use huge pages.
+    ///
+    /// Anonymous maps take their pages from the hugetlb pool directly. File
+    /// maps need a file on a hugetlbfs mounted with this page size, such as a
+    /// `HugeFile`, and fail with `Error::InvalidArgument` for any other file.
     #[inline]
     pub fn huge_pages(mut self, size: HugePageSize) -> MmapOptions {
         self.huge_pages = Some(size);
         self
     }
 
+    /// Configure which pages back anonymous memory maps.
+    ///
+    /// With `HugePagePolicy::BestEffort`, each kind of page is tried in turn,
+    /// so one configuration works whether or not hugetlb pools are set up:
+    /// `page_size_used` tells which was obtained. If none can be used, mapping
+    /// fails with `Error::HugePageAllocationFailed`, listing why each failed.
+    /// Overrides `huge_pages` and `transparent_huge_pages` for anonymous maps,
+    /// and is ignored for file maps.
+    #[inline]
+    pub fn huge_page_policy(mut self, policy: HugePagePolicy) -> MmapOptions {
+        self.huge_page_policy = Some(policy);
+        self
+    }
+
+    /// Configure how the memory map uses transparent huge pages.
+    ///
+    /// Unlike `huge_pages`, this needs no preconfigured hugetlb pool and works
+    /// for page-cache files, but is only advice: see
+    /// `advanced::huge_pages::transparent_huge_pages` for what the system will
+    /// honour. On Linux, memory maps of at least one huge page that request
+    /// huge pages are aligned to 2MB so they can be backed by them, unless an
+    /// address is requested or guard pages are used; file maps also need a
+    /// file offset that is a multiple of 2MB. Collapsing is best effort, and
+    /// memory that cannot be collapsed right away is left to khugepaged.
+    #[inline]
+    pub fn transparent_huge_pages(mut self, mode: ThpMode) -> MmapOptions {
+        self.transparent_huge_pages = Some(mode);
+        self
+    }
+
     /// Configure the memory map with a NUMA policy.
+    ///
+    /// On Linux, mapping fails with `Error::NumaAllocationFailed` if the
+    /// policy cannot be applied, such as when it names a node that does not
+    /// exist. With `populate`, the map is populated after the policy is
+    /// applied, so the prefaulted pages follow it.
     #[inline]
     pub fn numa_policy(mut self, policy: NumaPolicy) -> MmapOptions {
         self.numa_policy = Some(policy);
         self
     }
 
+    /// Configure how the NUMA policy is applied to pages that are already
+    /// allocated, such as cached pages of a file.
+    #[inline]
+    pub fn numa_flags(mut self, flags: NumaFlags) -> MmapOptions {
+        self.numa_flags = flags;
+        self
+    }
+
     /// Configure the prefetching strategy.
     #[inline]
     pub fn prefetch(mut self, strategy: PrefetchStrategy) -> MmapOptions {
@@ -170,6 +300,83 @@ impl MmapOptions {
         self
     }
 
+    /// Place the memory map exactly at `address`, which must be page-aligned.
+    ///
+    /// Mapping fails with `Error::AddressInUse` instead of replacing whatever is
+    /// already mapped there (`MAP_FIXED_NOREPLACE` on Linux), so structures
+    /// holding absolute pointers can be remapped where they were created. For
+    /// file maps with an unaligned offset, the address is that of the page
+    /// containing the first byte.
+    #[inline]
+    pub fn at_address(mut self, address: usize) -> MmapOptions {
+        self.placement = Some(Placement::Fixed(address));
+        self
+    }
+
+    /// Ask for the memory map to be placed at `address`, which must be
+    /// page-aligned, and fall back to any free address if it is taken.
+    ///
+    /// Compare `as_ptr` against the hint to find out whether it was honoured.
+    #[inline]
+    pub fn hint_address(mut self, address: usize) -> MmapOptions {
+        self.placement = Some(Placement::Hint(address));
+        self
+    }
+
+    /// Surround anonymous memory maps with inaccessible guard pages.
+    ///
+    /// `before` and `after` pages are reserved with no access rights around the
+    /// usable memory, so that overruns in either direction fault instead of
+    /// silently corrupting neighbouring memory. Guard pages do not count towards
+    /// the length of the memory map, and are ignored for file-backed maps.
+    #[inline]
+    pub fn guard_pages(mut self, before: usize, after: usize) -> MmapOptions {
+        self.guard_pages = (before, after);
+        self
+    }
+
+    /// Configure the memory map to be locked into RAM, so that accessing it
+    /// never causes a major page fault.
+    ///
+    /// The lock is subject to `RLIMIT_MEMLOCK`; exceeding it fails the mapping
+    /// with `Error::MemoryLockLimit`.
+    #[inline]
+    pub fn locked(mut self, locked: bool) -> MmapOptions {
+        self.locked = locked;
+        self
+    }
+
+    /// Configure locked memory maps to lock pages as they are first touched
+    /// (`MLOCK_ONFAULT` on Linux), instead of faulting in the whole map up
+    /// front.
+    ///
+    /// Only has an effect together with `locked`, and only on Linux.
+    #[inline]
+    pub fn lock_on_fault(mut self, on_fault: bool) -> MmapOptions {
+        self.lock_on_fault = on_fault;
+        self
+    }
+
+    /// Configure the backend that performs the system calls for memory maps
+    /// created with these options, for their whole lifetime.
+    ///
+    /// Defaults to the operating system. See `backend::FaultInjectingBackend`
+    /// for testing error handling, and `backend::HeapBackend` for emulating
+    /// memory maps where `mmap` is unavailable.
+    #[inline]
+    pub fn backend(mut self, backend: Arc<dyn Backend>) -> MmapOptions {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Configure `create` to fsync the new file and its parent directory, so the
+    /// file survives a crash.
+    #[inline]
+    pub fn sync_parent_dir(mut self, sync: bool) -> MmapOptions {
+        self.sync_parent_dir = sync;
+        self
+    }
+
     /// Create a read-only memory map backed by a file.
     ///
     /// # Safety
@@ -179,7 +386,7 @@ impl MmapOptions {
     /// undefined behavior.
     #[inline]
     pub unsafe fn map(&self, file: &File) -> Result<Mmap> {
-        self.map_impl(file).map(|raw| Mmap { inner: raw })
+        self.map_impl(file.try_clone()?).map(|raw| Mmap { inner: raw })
     }
 
     /// Create a writable memory map backed by a file.
@@ -191,11 +398,132 @@ impl MmapOptions {
     /// by other processes.
     #[inline]
     pub unsafe fn map_mut(&self, file: &File) -> Result<MmapMut> {
+        let mut options = self.clone();
+        options.writable = true;
+        options.map_impl(file.try_clone()?).map(|raw| MmapMut { inner: raw })
+    }
+
+    /// Create a read-only memory map backed by any file descriptor.
+    ///
+    /// The descriptor is duplicated, so the caller keeps ownership of `fd`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons as `map`.
+    #[cfg(unix)]
+    #[inline]
+    pub unsafe fn map_fd<F: AsFd>(&self, fd: F) -> Result<Mmap> {
+        let file = File::from(fd.as_fd().try_clone_to_owned()?);
+        self.map_impl(file).map(|raw| Mmap { inner: raw })
+    }
+
+    /// Create a writable memory map backed by any file descriptor.
+    ///
+    /// The descriptor is duplicated, so the caller keeps ownership of `fd`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons as `map_mut`.
+    #[cfg(unix)]
+    #[inline]
+    pub unsafe fn map_mut_fd<F: AsFd>(&self, fd: F) -> Result<MmapMut> {
+        let file = File::from(fd.as_fd().try_clone_to_owned()?);
         let mut options = self.clone();
         options.writable = true;
         options.map_impl(file).map(|raw| MmapMut { inner: raw })
     }
 
+    /// Open the file at `path` and create a read-only memory map of it.
+    ///
+    /// The memory map keeps the file open for as long as it lives.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons as `map`.
+    #[inline]
+    pub unsafe fn open<P: AsRef<Path>>(&self, path: P) -> Result<Mmap> {
+        let path = path.as_ref();
+        self.map_opened(File::open(path)?, path)
+    }
+
+    /// Create a read-only memory map of `file`, which was opened from `path`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons as `map`.
+    #[inline]
+    pub(crate) unsafe fn map_opened(&self, file: File, path: &Path) -> Result<Mmap> {
+        let mut raw = self.map_impl(file)?;
+        raw.path = Some(path.to_path_buf());
+        Ok(Mmap { inner: raw })
+    }
+
+    /// Open the file at `path` for reading and writing and create a writable
+    /// memory map of it.
+    ///
+    /// The memory map keeps the file open for as long as it lives.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons as `map_mut`.
+    #[inline]
+    pub unsafe fn open_mut<P: AsRef<Path>>(&self, path: P) -> Result<MmapMut> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut options = self.clone();
+        options.writable = true;
+        let mut raw = options.map_impl(file)?;
+        raw.path = Some(path.to_path_buf());
+        Ok(MmapMut { inner: raw })
+    }
+
+    /// Create a file of `len` bytes at `path` and create a writable memory map
+    /// of it.
+    ///
+    /// An existing file is truncated. The file is preallocated (with
+    /// `fallocate` on Linux) rather than left sparse, so writes through the map
+    /// cannot fail for lack of disk space. On file systems that cannot
+    /// preallocate, creation fails with the error of the operating system
+    /// (`EOPNOTSUPP` on Linux) and the empty file is left behind. If `sync_parent_dir` is set, the file
+    /// and its parent directory are fsynced before mapping, making the new file
+    /// durable. The memory map keeps the file open for as long as it lives.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe for the same reasons as `map_mut`.
+    pub unsafe fn create<P: AsRef<Path>>(&self, path: P, len: usize) -> Result<MmapMut> {
+        if len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let file_len = self.offset.checked_add(len as u64).ok_or(Error::SizeExceedsSystemLimit)?;
+        platform::preallocate(&file, file_len)?;
+
+        if self.sync_parent_dir {
+            file.sync_all()?;
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            platform::sync_dir(parent)?;
+        }
+
+        let mut options = self.clone();
+        options.writable = true;
+        options.len = Some(len);
+        let mut raw = options.map_impl(file)?;
+        raw.path = Some(path.to_path_buf());
+        Ok(MmapMut { inner: raw })
+    }
+
     /// Create an anonymous memory map not backed by a file.
     ///
     /// # Safety
@@ -209,8 +537,23 @@ impl MmapOptions {
         options.map_anon_impl().map(|raw| MmapMut { inner: raw })
     }
 
+    /// Create an anonymous memory map that can be shared with other processes.
+    ///
+    /// The memory is backed by a `memfd`, whose descriptor can be inherited by
+    /// child processes or sent to unrelated ones with `MemFdMmap::send`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because other processes sharing the memory may
+    /// access it at any time, which might lead to undefined behavior.
+    #[cfg(target_os = "linux")]
+    #[inline]
+    pub unsafe fn map_shared_anon(&self, len: usize) -> Result<MemFdMmap> {
+        MemFdMmap::new(self, len)
+    }
+
     /// Implementation of file-backed memory mapping.
-    unsafe fn map_impl(&self, file: &File) -> Result<MmapRaw> {
+    unsafe fn map_impl(&self, file: File) -> Result<MmapRaw> {
         // Validate options
         if let Some(len) = self.len {
             if len == 0 {
@@ -218,35 +561,51 @@ impl MmapOptions {
             }
         }
 
+        self.check_placement()?;
+
         // Get file length if not specified
+        let metadata = file.metadata()?;
         let len = match self.len {
             Some(len) => len,
-            None => {
-                let metadata = file.metadata()?;
-                metadata.len().try_into().map_err(|_| Error::SizeExceedsSystemLimit)?
-            }
+            None => metadata.len().try_into().map_err(|_| Error::SizeExceedsSystemLimit)?,
         };
 
+        // Align the mapping so transparent huge pages can back it
+        let aligned;
+        let mut options = self;
+        if let Some(align) = self.thp_alignment(len) {
+            aligned = MmapOptions { alignment: Some(align), ..self.clone() };
+            options = &aligned;
+        }
+
         // Perform platform-specific mapping
-        let raw = platform::map_file(
-            file,
-            self.offset,
-            len,
-            self.readable,
-            self.writable,
-            self.executable,
-            self.huge_pages,
-            self.numa_policy.clone(),  // Clone here
-            self.stack,
-            self.copy_on_write,
-            self.populate,
-            self.alignment,
-        )?;
+        let state = MappingState::new();
+        let ptr = backend_or_os(&self.backend).map_file(&file, self.offset, len, options, &state)?;
+        let mut raw = MmapRaw::new(ptr, len);
+        raw.backend = self.backend.clone();
+        raw.state = state;
+
+        // Keep the file open so the mapping can be resized later, and remember
+        // its state so changes by other processes can be detected
+        raw.file = Some(file);
+        raw.offset = self.offset;
+        raw.stamp = Some(FileStamp::new(&metadata));
+        raw.page_size = self.page_size();
 
         // Update statistics
         TOTAL_MAPPED_MEMORY.fetch_add(len, Ordering::Relaxed);
         ACTIVE_MAPPINGS.fetch_add(1, Ordering::Relaxed);
 
+        // Apply transparent huge page advice if requested
+        if let Some(mode) = self.transparent_huge_pages {
+            raw.advise_huge_pages(mode)?;
+        }
+
+        // Lock the pages into RAM if requested
+        if self.locked {
+            raw.lock_pages(self.lock_on_fault)?;
+        }
+
         // Apply prefetching if requested
         if let Some(strategy) = self.prefetch {
             crate::advanced::prefetch::apply_strategy(raw.ptr, len, strategy);
@@ -257,29 +616,149 @@ impl MmapOptions {
 
     /// Implementation of anonymous memory mapping.
     unsafe fn map_anon_impl(&self) -> Result<MmapRaw> {
+        let sizes = match (&self.huge_page_policy, self.huge_pages) {
+            (Some(HugePagePolicy::Exact(size)), _) => vec![*size],
+            (Some(HugePagePolicy::BestEffort(sizes)), _) => sizes.clone(),
+            (None, Some(size)) => vec![size.into()],
+            (None, None) => return self.map_anon_pages(),
+        };
+
+        // Fall through the page sizes until one can be allocated
+        let mut attempts = Vec::with_capacity(sizes.len());
+        for size in sizes {
+            if let Err(err) = check_available(size, self.transparent_huge_pages) {
+                attempts.push((size, err));
+                continue;
+            }
+
+            let mut options = self.clone();
+            options.huge_page_policy = None;
+            match size {
+                PageSize::OneGB => options.huge_pages = Some(HugePageSize::OneGB),
+                PageSize::TwoMB => options.huge_pages = Some(HugePageSize::TwoMB),
+                PageSize::Transparent => {
+                    options.huge_pages = None;
+                    if options.transparent_huge_pages != Some(ThpMode::Collapse) {
+                        options.transparent_huge_pages = Some(ThpMode::Enable);
+                    }
+                }
+                PageSize::Normal => {
+                    options.huge_pages = None;
+                    options.transparent_huge_pages = None;
+                }
+            }
+
+            match options.map_anon_pages() {
+                Ok(raw) => return Ok(raw),
+                Err(Error::Io(err)) => attempts.push((size, err)),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(Error::HugePageAllocationFailed { attempts })
+    }
+
+    /// Create an anonymous memory map backed by the pages these options
+    /// request.
+    unsafe fn map_anon_pages(&self) -> Result<MmapRaw> {
         let len = self.len.ok_or(Error::InvalidArgument("Length must be specified for anonymous mapping".into()))?;
         
         if len == 0 {
             return Err(Error::ZeroSizedMapping);
         }
+        self.check_placement()?;
+
+        let page_size = alignment::page_size();
+        let (guard_before, guard_after) = match self.guard_pages {
+            (0, 0) => (0, 0),
+            (before, after) => {
+                if self.alignment.is_some_and(|align| align > page_size) {
+                    return Err(Error::InvalidArgument("Guard pages cannot be combined with custom alignment".into()));
+                }
+                let before = before.checked_mul(page_size).ok_or(Error::SizeExceedsSystemLimit)?;
+                let after = after.checked_mul(page_size).ok_or(Error::SizeExceedsSystemLimit)?;
+                (before, after)
+            }
+        };
+        let map_len = if guard_before == 0 && guard_after == 0 {
+            len
+        } else {
+            alignment::align_up(len, page_size)
+                .checked_add(guard_before + guard_after)
+                .ok_or(Error::SizeExceedsSystemLimit)?
+        };
+
+        // hugetlb maps consist of whole huge pages, and can only be unmapped
+        // as such
+        let map_len = match self.huge_pages {
+            Some(size) => map_len
+                .checked_add(size.bytes() - 1)
+                .map(|map_len| alignment::align_down(map_len, size.bytes()))
+                .ok_or(Error::SizeExceedsSystemLimit)?,
+            None => map_len,
+        };
+
+        // Align the mapping so transparent huge pages can back it
+        let aligned;
+        let mut options = self;
+        if let Some(align) = self.thp_alignment(len) {
+            aligned = MmapOptions { alignment: Some(align), ..self.clone() };
+            options = &aligned;
+        }
+
+        // The requested address is that of the usable memory, so move it down
+        // past the leading guard pages
+        let shifted;
+        if let Some(placement) = self.placement.filter(|_| guard_before != 0) {
+            let address = placement
+                .address()
+                .checked_sub(guard_before)
+                .ok_or(Error::InvalidArgument("Requested address leaves no room for guard pages".into()))?;
+            let placement = match placement {
+                Placement::Fixed(_) => Placement::Fixed(address),
+                Placement::Hint(_) => Placement::Hint(address),
+            };
+            shifted = MmapOptions { placement: Some(placement), ..self.clone() };
+            options = &shifted;
+        }
 
         // Perform platform-specific anonymous mapping
-        let raw = platform::map_anon(
-            len,
-            self.readable,
-            self.writable,
-            self.executable,
-            self.huge_pages,
-            self.numa_policy,
-            self.stack,
-            self.populate,
-            self.alignment,
-        )?;
+        let state = MappingState::new();
+        let ptr = backend_or_os(&self.backend).map_anon(map_len, options, &state)?;
+        let mut raw = MmapRaw::new(ptr, len);
+        raw.backend = self.backend.clone();
+        raw.state = state;
+        raw.page_size = self.page_size();
 
         // Update statistics
         TOTAL_MAPPED_MEMORY.fetch_add(len, Ordering::Relaxed);
         ACTIVE_MAPPINGS.fetch_add(1, Ordering::Relaxed);
 
+        // Carve the guard pages out of either end of the mapping
+        if guard_before != 0 || guard_after != 0 {
+            raw.ptr = raw.ptr.add(guard_before);
+            raw.guard_before = guard_before;
+            raw.guard_after = guard_after;
+
+            let guard_end = raw.ptr.add(alignment::align_up(len, page_size));
+            if guard_before != 0 {
+                raw.backend().protect(raw.ptr.sub(guard_before), guard_before, platform::Protection::None)?;
+            }
+            if guard_after != 0 {
+                raw.backend().protect(guard_end, guard_after, platform::Protection::None)?;
+            }
+        }
+
+        // Apply transparent huge page advice if requested
+        if let Some(mode) = self.transparent_huge_pages {
+            raw.advise_huge_pages(mode)?;
+        }
+
+        // Lock the pages into RAM if requested
+        if self.locked {
+            raw.lock_pages(self.lock_on_fault)?;
+        }
+
         // Apply prefetching if requested
         if let Some(strategy) = self.prefetch {
             crate::advanced::prefetch::apply_strategy(raw.ptr, len, strategy);
@@ -287,6 +766,55 @@ impl MmapOptions {
 
         Ok(raw)
     }
+
+    /// Return the NUMA policy with its flags, if a policy is set.
+    #[inline]
+    pub(crate) fn numa(&self) -> Option<(&NumaPolicy, NumaFlags)> {
+        self.numa_policy.as_ref().map(|policy| (policy, self.numa_flags))
+    }
+
+    /// Return the kind of pages these options request.
+    fn page_size(&self) -> PageSize {
+        match (self.huge_pages, self.transparent_huge_pages) {
+            (Some(size), _) => size.into(),
+            (None, Some(ThpMode::Enable)) | (None, Some(ThpMode::Collapse)) => PageSize::Transparent,
+            (None, _) => PageSize::Normal,
+        }
+    }
+
+    /// Return the alignment needed for transparent huge pages to back a memory
+    /// map of `len` bytes, if it should be aligned automatically.
+    fn thp_alignment(&self, len: usize) -> Option<usize> {
+        let huge_page_size = HugePageSize::TwoMB.bytes();
+        let wanted = matches!(self.transparent_huge_pages, Some(ThpMode::Enable) | Some(ThpMode::Collapse));
+        if !cfg!(target_os = "linux")
+            || !wanted
+            || len < huge_page_size
+            || self.huge_pages.is_some()
+            || self.placement.is_some()
+            || self.guard_pages != (0, 0)
+        {
+            return None;
+        }
+
+        Some(self.alignment.map_or(huge_page_size, |align| align.max(huge_page_size)))
+    }
+
+    /// Check that a requested address is usable before mapping.
+    fn check_placement(&self) -> Result<()> {
+        let address = match self.placement {
+            Some(placement) => placement.address(),
+            None => return Ok(()),
+        };
+
+        // Custom alignment applies to the requested address as well
+        let align = self.alignment.unwrap_or(0).max(alignment::page_size());
+        if address == 0 || !align.is_power_of_two() || !alignment::is_aligned(address, align) {
+            return Err(Error::UnalignedAddress { address, alignment: align });
+        }
+
+        Ok(())
+    }
 }
 
 /// Raw memory map handle.
@@ -297,95 +825,645 @@ pub struct MmapRaw {
     
     /// Length of the mapped memory.
     pub(crate) len: usize,
+    
+    /// The file backing the memory map, if any.
+    pub(crate) file: Option<File>,
+    
+    /// The offset within the file at which the memory map starts.
+    pub(crate) offset: u64,
+    
+    /// Size in bytes of the inaccessible guard region before the memory map.
+    pub(crate) guard_before: usize,
+    
+    /// Size in bytes of the inaccessible guard region after the memory map.
+    pub(crate) guard_after: usize,
+    
+    /// Whether any part of the memory map has been locked into RAM.
+    pub(crate) locked: AtomicBool,
+    
+    /// The path the backing file was opened from, if known.
+    pub(crate) path: Option<PathBuf>,
+    
+    /// The state of the backing file when it was mapped.
+    pub(crate) stamp: Option<FileStamp>,
+    
+    /// Backend performing the system calls, or `None` for the operating system.
+    pub(crate) backend: Option<Arc<dyn Backend>>,
+    
+    /// Data the backend keeps about this mapping.
+    pub(crate) state: MappingState,
+    
+    /// The kind of pages backing the memory map.
+    pub(crate) page_size: PageSize,
 }
 
 impl MmapRaw {
-    /// Flush the memory map to disk.
-    ///
-    /// This function will flush the entire memory map to disk, ensuring that all
-    /// changes are persisted.
+    /// Create a raw handle for a freshly created memory map.
     #[inline]
-    pub fn flush(&self) -> Result<()> {
-        unsafe { platform::flush(self.ptr, self.len, false) }
+    pub(crate) fn new(ptr: *mut u8, len: usize) -> MmapRaw {
+        MmapRaw {
+            ptr,
+            len,
+            file: None,
+            offset: 0,
+            guard_before: 0,
+            guard_after: 0,
+            locked: AtomicBool::new(false),
+            path: None,
+            stamp: None,
+            backend: None,
+            state: MappingState::new(),
+            page_size: PageSize::Normal,
+        }
     }
 
-    /// Flush the memory map to disk asynchronously.
-    ///
-    /// This function will initiate an asynchronous flush of the entire memory map
-    /// to disk, but may return before the flush is complete.
+    /// Give up ownership of the mapping without unmapping it, returning its
+    /// pointer.
     #[inline]
-    pub fn flush_async(&self) -> Result<()> {
-        unsafe { platform::flush(self.ptr, self.len, true) }
+    pub(crate) fn into_ptr(mut self) -> *mut u8 {
+        mem::replace(&mut self.ptr, ptr::null_mut())
     }
 
-    /// Advise the kernel about how the memory map will be accessed.
+    /// Return the backend performing the system calls for this mapping.
     #[inline]
-    pub fn advise(&self, advice: platform::Advice) -> Result<()> {
-        unsafe { platform::advise(self.ptr, self.len, advice) }
+    pub(crate) fn backend(&self) -> &dyn Backend {
+        backend_or_os(&self.backend)
     }
-}
 
-impl Drop for MmapRaw {
-    fn drop(&mut self) {
-        // Safety: We're ensuring proper cleanup of the memory map
-        unsafe {
-            if !self.ptr.is_null() {
-                // Update statistics
-                ACTIVE_MAPPINGS.fetch_sub(1, Ordering::Relaxed);
-                TOTAL_MAPPED_MEMORY.fetch_sub(self.len, Ordering::Relaxed);
-                
-                // Unmap the memory
-                let _ = platform::unmap(self.ptr, self.len);
-            }
-        }
+    /// Return the page-aligned start address and length of the accessible part
+    /// of the mapping.
+    ///
+    /// File mappings with an unaligned offset start partway into their first page,
+    /// so `ptr` is not necessarily the address returned by the kernel.
+    #[inline]
+    fn page_bounds(&self) -> (*mut u8, usize) {
+        let delta = self.ptr as usize - alignment::align_down(self.ptr as usize, alignment::page_size());
+        (unsafe { self.ptr.sub(delta) }, self.len + delta)
     }
-}
-
-/// A read-only memory map.
-#[derive(Debug)]
-pub struct Mmap {
-    inner: MmapRaw,
-}
 
-impl Mmap {
-    /// Create a read-only memory map backed by a file.
-    ///
-    /// # Safety
+    /// Return the start address and length of the whole underlying mapping,
+    /// including any guard pages.
     ///
-    /// This function is unsafe because it creates a memory map that can be accessed
-    /// while the file is being modified by external processes, which might lead to
-    /// undefined behavior.
+    /// hugetlb mappings always span whole huge pages, even if their length is
+    /// not a multiple of the huge page size.
     #[inline]
-    pub unsafe fn map(file: &File) -> Result<Mmap> {
-        MmapOptions::new().map(file)
+    fn mapping_bounds(&self) -> (*mut u8, usize) {
+        let (base, len) = self.page_bounds();
+        let len = match self.page_size {
+            PageSize::OneGB | PageSize::TwoMB => alignment::align_up(len, self.page_size.bytes()),
+            PageSize::Transparent | PageSize::Normal => len,
+        };
+        (unsafe { base.sub(self.guard_before) }, self.guard_before + len + self.guard_after)
     }
 
     /// Return the length of the memory map.
     #[inline]
     pub fn len(&self) -> usize {
-        self.inner.len
+        self.len
     }
 
     /// Return true if the memory map is empty.
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.inner.len == 0
+        self.len == 0
     }
 
-    /// Return a pointer to the memory map.
+    /// Return the kind of pages backing the memory map.
     ///
-    /// # Safety
+    /// This reports what was obtained, which can differ from what was asked
+    /// for with `HugePagePolicy::BestEffort`. `PageSize::Transparent` means
+    /// the map was advised to use transparent huge pages, which the kernel
+    /// grants page by page.
+    #[inline]
+    pub fn page_size_used(&self) -> PageSize {
+        self.page_size
+    }
+
+    /// Return a pointer to the memory map.
     ///
-    /// The caller must ensure that the memory map outlives any pointers derived
-    /// from this function.
+    /// The memory behind the pointer may not be accessible, depending on the
+    /// protection of the memory map.
     #[inline]
     pub fn as_ptr(&self) -> *const u8 {
-        self.inner.ptr
+        self.ptr
     }
 
-    /// Flush the memory map to disk.
-    #[inline]
-    pub fn flush(&self) -> Result<()> {
+    /// Change the protection of a range of the memory map.
+    ///
+    /// The range is widened to page boundaries.
+    ///
+    /// # Safety
+    ///
+    /// Removing read or write access from part of the memory map makes
+    /// dereferencing it fault, including through slices of a `Mmap` or
+    /// `MmapMut` that share the mapping. The caller must not access the range
+    /// in ways the new protection forbids until access has been restored.
+    #[inline]
+    pub unsafe fn protect_range(&self, offset: usize, len: usize, protection: platform::Protection) -> Result<()> {
+        let (addr, len) = self.page_range(offset, len)?;
+        self.backend().protect(addr, len, protection)
+    }
+
+    /// Make the memory map read-only.
+    ///
+    /// If the protection cannot be changed, the memory map is returned in the
+    /// error.
+    #[inline]
+    pub fn make_read_only(self) -> result::Result<Mmap, ProtectError<MmapRaw>> {
+        self.into_protected(platform::Protection::Read).map(|inner| Mmap { inner })
+    }
+
+    /// Make the memory map readable and writable.
+    ///
+    /// For shared file maps the file must have been opened for writing. Copy-on-write
+    /// maps can always be made writable, since writes never reach the file. If
+    /// the protection cannot be changed, the memory map is returned in the
+    /// error.
+    #[inline]
+    pub fn make_mut(self) -> result::Result<MmapMut, ProtectError<MmapRaw>> {
+        self.into_protected(platform::Protection::ReadWrite).map(|inner| MmapMut { inner })
+    }
+
+    /// Make the memory map readable and executable.
+    ///
+    /// If the protection cannot be changed, the memory map is returned in the
+    /// error.
+    #[inline]
+    pub fn make_exec(self) -> result::Result<Mmap, ProtectError<MmapRaw>> {
+        self.into_protected(platform::Protection::ReadExec).map(|inner| Mmap { inner })
+    }
+
+    /// Change the protection of the whole memory map, handing it back in the
+    /// error if that fails.
+    #[inline]
+    fn into_protected(self, protection: platform::Protection) -> result::Result<MmapRaw, ProtectError<MmapRaw>> {
+        match self.protect(protection) {
+            Ok(()) => Ok(self),
+            Err(err) => Err(ProtectError::new(err, self)),
+        }
+    }
+
+    /// Change the protection of the whole memory map.
+    #[inline]
+    fn protect(&self, protection: platform::Protection) -> Result<()> {
+        let (base, len) = self.page_bounds();
+        unsafe { self.backend().protect(base, len, protection) }
+    }
+
+    /// Resize the memory map, growing or shrinking the backing file with it.
+    ///
+    /// The mapping may move to a new address, so every pointer previously
+    /// obtained from it is invalidated.
+    pub(crate) fn resize(&mut self, new_len: usize) -> Result<()> {
+        if new_len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+        if new_len == self.len {
+            return Ok(());
+        }
+        if self.guard_before != 0 || self.guard_after != 0 {
+            return Err(Error::InvalidArgument("Memory maps with guard pages cannot be resized".into()));
+        }
+
+        // hugetlb mappings span whole huge pages, and mremap needs both lengths
+        // rounded to them
+        let (base, old_map_len) = self.mapping_bounds();
+        let delta = self.ptr as usize - base as usize;
+        let new_map_len = new_len.checked_add(delta).ok_or(Error::SizeExceedsSystemLimit)?;
+        let new_map_len = match self.page_size {
+            PageSize::OneGB | PageSize::TwoMB => new_map_len
+                .checked_add(self.page_size.bytes() - 1)
+                .map(|len| alignment::align_down(len, self.page_size.bytes()))
+                .ok_or(Error::SizeExceedsSystemLimit)?,
+            PageSize::Transparent | PageSize::Normal => new_map_len,
+        };
+        let new_file_len = self.offset.checked_add(new_len as u64).ok_or(Error::SizeExceedsSystemLimit)?;
+
+        // Grow the file before the mapping so the new pages are backed
+        let old_file_len = match self.file {
+            Some(ref file) => {
+                let old_file_len = file.metadata()?.len();
+                if new_file_len > old_file_len {
+                    file.set_len(new_file_len)?;
+                }
+                Some(old_file_len)
+            }
+            None => None,
+        };
+
+        let new_base = match unsafe { self.backend().remap(base, old_map_len, new_map_len, &self.state) } {
+            Ok(new_base) => new_base,
+            Err(err) => {
+                // Best effort: put the file back the way we found it
+                if let (Some(file), Some(old_file_len)) = (self.file.as_ref(), old_file_len) {
+                    if new_file_len > old_file_len {
+                        let _ = file.set_len(old_file_len);
+                    }
+                }
+                return Err(err);
+            }
+        };
+
+        // The mapping has been resized, so describe it before anything else
+        // can fail
+        if new_len > self.len {
+            TOTAL_MAPPED_MEMORY.fetch_add(new_len - self.len, Ordering::Relaxed);
+        } else {
+            TOTAL_MAPPED_MEMORY.fetch_sub(self.len - new_len, Ordering::Relaxed);
+        }
+        self.ptr = unsafe { new_base.add(delta) };
+        self.len = new_len;
+
+        // Shrink the file only once nothing maps the truncated pages. If this
+        // fails the file is left longer than the mapping, which is harmless.
+        let truncated = match (self.file.as_ref(), old_file_len) {
+            (Some(file), Some(old_file_len)) if new_file_len < old_file_len => file.set_len(new_file_len),
+            _ => Ok(()),
+        };
+
+        // Our own resize does not make the mapping stale
+        if let Some(ref file) = self.file {
+            self.stamp = Some(FileStamp::new(&file.metadata()?));
+        }
+
+        truncated.map_err(Error::Io)
+    }
+
+    /// Flush the memory map to disk.
+    ///
+    /// This function will flush the entire memory map to disk, ensuring that all
+    /// changes are persisted.
+    #[inline]
+    pub fn flush(&self) -> Result<()> {
+        unsafe { self.backend().flush(self.ptr, self.len, false, &self.state) }
+    }
+
+    /// Flush the memory map to disk asynchronously.
+    ///
+    /// This function will initiate an asynchronous flush of the entire memory map
+    /// to disk, but may return before the flush is complete.
+    #[inline]
+    pub fn flush_async(&self) -> Result<()> {
+        unsafe { self.backend().flush(self.ptr, self.len, true, &self.state) }
+    }
+
+    /// Advise the kernel about how the memory map will be accessed.
+    #[inline]
+    pub fn advise(&self, advice: platform::Advice) -> Result<()> {
+        unsafe { self.backend().advise(self.ptr, self.len, advice) }
+    }
+
+    /// Flush a range of the memory map to disk.
+    ///
+    /// The range is widened to page boundaries, so neighbouring bytes on the
+    /// same pages are flushed as well.
+    #[inline]
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<()> {
+        let (addr, len) = self.page_range(offset, len)?;
+        unsafe { self.backend().flush(addr, len, false, &self.state) }
+    }
+
+    /// Flush a range of the memory map to disk asynchronously.
+    ///
+    /// The range is widened to page boundaries, so neighbouring bytes on the
+    /// same pages are flushed as well.
+    #[inline]
+    pub fn flush_async_range(&self, offset: usize, len: usize) -> Result<()> {
+        let (addr, len) = self.page_range(offset, len)?;
+        unsafe { self.backend().flush(addr, len, true, &self.state) }
+    }
+
+    /// Advise the kernel about how a range of the memory map will be accessed.
+    ///
+    /// The range is widened to page boundaries.
+    #[inline]
+    pub fn advise_range(&self, offset: usize, len: usize, advice: platform::Advice) -> Result<()> {
+        let (addr, len) = self.page_range(offset, len)?;
+        unsafe { self.backend().advise(addr, len, advice) }
+    }
+
+    /// Lock the memory map into RAM.
+    ///
+    /// Every page is faulted in and stays resident until it is unlocked or the
+    /// memory map is dropped. Fails with `Error::MemoryLockLimit` if this would
+    /// exceed `RLIMIT_MEMLOCK`, and with a `PermissionDenied` I/O error if the
+    /// process may not lock memory at all.
+    #[inline]
+    pub fn lock(&self) -> Result<()> {
+        self.lock_pages(false)
+    }
+
+    /// Unlock the memory map, allowing its pages to be swapped out again.
+    #[inline]
+    pub fn unlock(&self) -> Result<()> {
+        let (base, len) = self.page_bounds();
+        unsafe { self.backend().unlock(base, len)? };
+        self.locked.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Lock a range of the memory map into RAM.
+    ///
+    /// The range is widened to page boundaries.
+    #[inline]
+    pub fn lock_range(&self, offset: usize, len: usize) -> Result<()> {
+        let (addr, len) = self.page_range(offset, len)?;
+        unsafe { self.backend().lock(addr, len, false)? };
+        self.locked.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Unlock a range of the memory map.
+    ///
+    /// The range is widened to page boundaries.
+    #[inline]
+    pub fn unlock_range(&self, offset: usize, len: usize) -> Result<()> {
+        let (addr, len) = self.page_range(offset, len)?;
+        unsafe { self.backend().unlock(addr, len) }
+    }
+
+    /// Return true if the backing file has changed since it was mapped.
+    ///
+    /// The file counts as changed if its size or modification time differ, or,
+    /// for memory maps created from a path, if the path has been removed or now
+    /// refers to a different file, for example after an atomic rename. Writes
+    /// through a shared writable map also update the modification time.
+    /// Anonymous memory maps are never stale.
+    #[inline]
+    pub fn is_stale(&self) -> Result<bool> {
+        self.check_stale(true)
+    }
+
+    /// Return true if the backing file has been resized or replaced since it
+    /// was mapped, or, if `check_modified` is set, modified in place.
+    ///
+    /// In-place modifications show through a shared map, so only resizing and
+    /// replacement require the file to be mapped again.
+    pub(crate) fn check_stale(&self, check_modified: bool) -> Result<bool> {
+        let stamp = match self.stamp {
+            Some(ref stamp) => stamp,
+            None => return Ok(false),
+        };
+
+        if let Some(ref file) = self.file {
+            if FileStamp::new(&file.metadata()?).differs(stamp, check_modified) {
+                return Ok(true);
+            }
+        }
+
+        if let Some(ref path) = self.path {
+            match fs::metadata(path) {
+                Ok(metadata) => return Ok(FileStamp::new(&metadata).differs(stamp, check_modified)),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Copy bytes starting at `offset` into `buf`, reporting a truncated
+    /// backing file as an error instead of crashing.
+    ///
+    /// If another process truncates the file, touching pages past its new end
+    /// raises SIGBUS. Here such faults are caught and turned into
+    /// `Error::TruncatedMapping`, carrying the offset of the first missing
+    /// byte; `buf` holds valid data up to that offset. The memory map is left
+    /// untouched, so direct accesses to the missing pages still fault. Remap
+    /// the file to see its new contents.
+    ///
+    /// On Unix the first call installs a process-wide SIGBUS handler, which
+    /// forwards faults outside of `try_read` to any previously installed
+    /// handler.
+    pub fn try_read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        check_range(offset, buf.len(), self.len)?;
+        unsafe { platform::try_copy(self.ptr.add(offset), buf.as_mut_ptr(), buf.len()) }.map_err(|err| match err {
+            Error::TruncatedMapping { offset: fault } => Error::TruncatedMapping { offset: offset + fault },
+            err => err,
+        })
+    }
+
+    /// Copy `len` bytes starting at `offset` into `writer`, reporting a
+    /// truncated backing file as an error instead of crashing.
+    ///
+    /// See `try_read`. On `Error::TruncatedMapping`, every byte before the
+    /// reported offset has been written.
+    pub fn try_copy_to<W: Write>(&self, offset: usize, len: usize, writer: &mut W) -> Result<()> {
+        check_range(offset, len, self.len)?;
+
+        let mut buf = vec![0u8; len.min(COPY_CHUNK_SIZE)];
+        let mut copied = 0;
+        while copied < len {
+            let chunk = &mut buf[..(len - copied).min(COPY_CHUNK_SIZE)];
+            if let Err(err) = self.try_read(offset + copied, chunk) {
+                if let Error::TruncatedMapping { offset: fault } = err {
+                    writer.write_all(&chunk[..fault - offset - copied])?;
+                }
+                return Err(err);
+            }
+            writer.write_all(chunk)?;
+            copied += chunk.len();
+        }
+
+        Ok(())
+    }
+
+    /// Report which pages of the memory map are resident in RAM.
+    pub fn residency(&self) -> Result<Residency> {
+        let (base, len) = self.page_bounds();
+        let pages = unsafe { self.backend().residency(base, len)? };
+        Ok(Residency::new(&pages, alignment::page_size(), self.ptr as usize - base as usize, self.len))
+    }
+
+    /// Return the number of bytes of the memory map on resident pages.
+    #[inline]
+    pub fn resident_bytes(&self) -> Result<usize> {
+        self.residency().map(|residency| residency.resident_bytes())
+    }
+
+    /// Return the byte ranges of the memory map that are resident in RAM.
+    #[inline]
+    pub fn resident_ranges(&self) -> Result<Vec<Range<usize>>> {
+        self.residency().map(|residency| residency.resident_ranges())
+    }
+
+    /// Return true if every page of a range of the memory map is resident, so
+    /// that accessing it will not fault.
+    pub fn is_resident(&self, offset: usize, len: usize) -> Result<bool> {
+        if len == 0 {
+            check_range(offset, len, self.len)?;
+            return Ok(true);
+        }
+        let (addr, len) = self.page_range(offset, len)?;
+        let pages = unsafe { self.backend().residency(addr, len)? };
+        Ok(pages.into_iter().all(|resident| resident))
+    }
+
+    /// Lock the whole memory map, optionally deferring until pages are touched.
+    fn lock_pages(&self, on_fault: bool) -> Result<()> {
+        let (base, len) = self.page_bounds();
+        unsafe { self.backend().lock(base, len, on_fault)? };
+        self.locked.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Advise the kernel how to use transparent huge pages for the whole
+    /// memory map.
+    fn advise_huge_pages(&self, mode: ThpMode) -> Result<()> {
+        let (base, len) = self.page_bounds();
+        let advice = match mode {
+            ThpMode::Disable => platform::Advice::NoHugePage,
+            ThpMode::Enable | ThpMode::Collapse => platform::Advice::HugePage,
+        };
+        unsafe { self.backend().advise(base, len, advice)? };
+
+        // Collapsing fails if huge pages cannot be allocated right now, or on
+        // kernels that predate it; khugepaged will collapse the memory later
+        if mode == ThpMode::Collapse {
+            let _ = unsafe { self.backend().advise(base, len, platform::Advice::Collapse) };
+        }
+        Ok(())
+    }
+
+    /// Check that `count` values of type `T` starting at `offset` lie within the
+    /// memory map and are properly aligned, returning a pointer to the first one.
+    fn typed_ptr<T: Pod>(&self, offset: usize, count: usize) -> Result<*mut T> {
+        let size = count
+            .checked_mul(mem::size_of::<T>())
+            .ok_or(Error::InvalidArgument("Typed view size overflows".into()))?;
+        check_range(offset, size, self.len)?;
+
+        let addr = self.ptr as usize + offset;
+        if !alignment::is_aligned(addr, mem::align_of::<T>()) {
+            return Err(Error::AlignmentError);
+        }
+
+        Ok(addr as *mut T)
+    }
+
+    /// Check a range against the bounds of the memory map and widen it to page
+    /// boundaries, returning the page-aligned start address and length.
+    pub(crate) fn page_range(&self, offset: usize, len: usize) -> Result<(*mut u8, usize)> {
+        check_range(offset, len, self.len)?;
+
+        let page_size = alignment::page_size();
+        let start = self.ptr as usize + offset;
+        let aligned_start = alignment::align_down(start, page_size);
+        let aligned_end = alignment::align_up(start + len, page_size);
+
+        Ok((aligned_start as *mut u8, aligned_end - aligned_start))
+    }
+}
+
+// Safety: `MmapRaw` owns its mapping exclusively, and all mutation of the mapped
+// memory goes through `&mut` access on the wrapping types.
+unsafe impl Send for MmapRaw {}
+unsafe impl Sync for MmapRaw {}
+
+impl Drop for MmapRaw {
+    fn drop(&mut self) {
+        // Safety: We're ensuring proper cleanup of the memory map
+        unsafe {
+            if !self.ptr.is_null() {
+                // Update statistics
+                ACTIVE_MAPPINGS.fetch_sub(1, Ordering::Relaxed);
+                TOTAL_MAPPED_MEMORY.fetch_sub(self.len, Ordering::Relaxed);
+                
+                // Release locked pages before unmapping them
+                if *self.locked.get_mut() {
+                    let (base, len) = self.page_bounds();
+                    let _ = self.backend().unlock(base, len);
+                }
+                
+                // Unmap the memory
+                let (base, len) = self.mapping_bounds();
+                let _ = self.backend().unmap(base, len, &self.state);
+            }
+        }
+    }
+}
+
+/// A read-only memory map.
+#[derive(Debug)]
+pub struct Mmap {
+    inner: MmapRaw,
+}
+
+impl Mmap {
+    /// Create a read-only memory map backed by a file.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because it creates a memory map that can be accessed
+    /// while the file is being modified by external processes, which might lead to
+    /// undefined behavior.
+    #[inline]
+    pub unsafe fn map(file: &File) -> Result<Mmap> {
+        MmapOptions::new().map(file)
+    }
+
+    /// Return the length of the memory map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len
+    }
+
+    /// Return true if the memory map is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.len == 0
+    }
+
+    /// Return the kind of pages backing the memory map.
+    ///
+    /// See `MmapRaw::page_size_used`.
+    #[inline]
+    pub fn page_size_used(&self) -> PageSize {
+        self.inner.page_size_used()
+    }
+
+    /// Return a pointer to the memory map.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the memory map outlives any pointers derived
+    /// from this function.
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.inner.ptr
+    }
+
+    /// Return the file backing the memory map, if any.
+    #[inline]
+    pub fn file(&self) -> Option<&File> {
+        self.inner.file.as_ref()
+    }
+
+    /// View `count` values of type `T` starting at byte `offset`.
+    ///
+    /// Returns `Error::AlignmentError` if the address at `offset` is not
+    /// aligned for `T`, and `Error::InvalidArgument` if the values do not fit
+    /// within the memory map.
+    #[inline]
+    pub fn as_slice_of<T: Pod>(&self, offset: usize, count: usize) -> Result<&[T]> {
+        let ptr = self.inner.typed_ptr::<T>(offset, count)?;
+        Ok(unsafe { slice::from_raw_parts(ptr, count) })
+    }
+
+    /// Read a value of type `T` at byte `offset`.
+    ///
+    /// Returns `Error::AlignmentError` if the address at `offset` is not
+    /// aligned for `T`, and `Error::InvalidArgument` if the value does not fit
+    /// within the memory map.
+    #[inline]
+    pub fn read_at<T: Pod>(&self, offset: usize) -> Result<T> {
+        let ptr = self.inner.typed_ptr::<T>(offset, 1)?;
+        Ok(unsafe { ptr::read(ptr) })
+    }
+
+    /// Flush the memory map to disk.
+    #[inline]
+    pub fn flush(&self) -> Result<()> {
         self.inner.flush()
     }
 
@@ -400,6 +1478,152 @@ impl Mmap {
     pub fn advise(&self, advice: platform::Advice) -> Result<()> {
         self.inner.advise(advice)
     }
+
+    /// Flush a range of the memory map to disk.
+    #[inline]
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.flush_range(offset, len)
+    }
+
+    /// Flush a range of the memory map to disk asynchronously.
+    #[inline]
+    pub fn flush_async_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.flush_async_range(offset, len)
+    }
+
+    /// Advise the kernel about how a range of the memory map will be accessed.
+    #[inline]
+    pub fn advise_range(&self, offset: usize, len: usize, advice: platform::Advice) -> Result<()> {
+        self.inner.advise_range(offset, len, advice)
+    }
+
+    /// Lock the memory map into RAM.
+    ///
+    /// Fails with `Error::MemoryLockLimit` if this would exceed
+    /// `RLIMIT_MEMLOCK`. The memory map is unlocked when it is dropped.
+    #[inline]
+    pub fn lock(&self) -> Result<()> {
+        self.inner.lock()
+    }
+
+    /// Unlock the memory map.
+    #[inline]
+    pub fn unlock(&self) -> Result<()> {
+        self.inner.unlock()
+    }
+
+    /// Lock a range of the memory map into RAM.
+    #[inline]
+    pub fn lock_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.lock_range(offset, len)
+    }
+
+    /// Unlock a range of the memory map.
+    #[inline]
+    pub fn unlock_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.unlock_range(offset, len)
+    }
+
+    /// Report which pages of the memory map are resident in RAM.
+    #[inline]
+    pub fn residency(&self) -> Result<Residency> {
+        self.inner.residency()
+    }
+
+    /// Return the number of bytes of the memory map on resident pages.
+    #[inline]
+    pub fn resident_bytes(&self) -> Result<usize> {
+        self.inner.resident_bytes()
+    }
+
+    /// Return the byte ranges of the memory map that are resident in RAM.
+    #[inline]
+    pub fn resident_ranges(&self) -> Result<Vec<Range<usize>>> {
+        self.inner.resident_ranges()
+    }
+
+    /// Return true if every page of a range of the memory map is resident.
+    #[inline]
+    pub fn is_resident(&self, offset: usize, len: usize) -> Result<bool> {
+        self.inner.is_resident(offset, len)
+    }
+
+    /// Return true if the backing file has changed since it was mapped.
+    ///
+    /// See `MmapRaw::is_stale` for what counts as a change.
+    #[inline]
+    pub fn is_stale(&self) -> Result<bool> {
+        self.inner.is_stale()
+    }
+
+    /// Return true if the backing file has been resized or replaced since it
+    /// was mapped, or, if `check_modified` is set, modified in place.
+    #[inline]
+    pub(crate) fn check_stale(&self, check_modified: bool) -> Result<bool> {
+        self.inner.check_stale(check_modified)
+    }
+
+    /// Copy bytes starting at `offset` into `buf`, returning
+    /// `Error::TruncatedMapping` instead of crashing if the backing file has
+    /// been truncated.
+    #[inline]
+    pub fn try_read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.inner.try_read(offset, buf)
+    }
+
+    /// Copy `len` bytes starting at `offset` into `writer`, returning
+    /// `Error::TruncatedMapping` instead of crashing if the backing file has
+    /// been truncated.
+    #[inline]
+    pub fn try_copy_to<W: Write>(&self, offset: usize, len: usize, writer: &mut W) -> Result<()> {
+        self.inner.try_copy_to(offset, len, writer)
+    }
+
+    /// Make the memory map readable and writable.
+    ///
+    /// For shared file maps the file must have been opened for writing, otherwise
+    /// `Error::ProtectionError` is returned. Copy-on-write maps can always be made
+    /// writable without reopening the file, since writes never reach it. If the
+    /// protection cannot be changed, the memory map is returned in the error.
+    #[inline]
+    pub fn make_mut(self) -> result::Result<MmapMut, ProtectError<Mmap>> {
+        self.inner.make_mut().map_err(|err| err.map(|inner| Mmap { inner }))
+    }
+
+    /// Make the memory map readable and executable.
+    ///
+    /// If the protection cannot be changed, the memory map is returned in the
+    /// error.
+    #[inline]
+    pub fn make_exec(self) -> result::Result<Mmap, ProtectError<Mmap>> {
+        self.inner.make_exec().map_err(|err| err.map(|inner| Mmap { inner }))
+    }
+
+    /// Remove all access to the memory map.
+    ///
+    /// The returned `MmapRaw` cannot be dereferenced, but can be turned back into
+    /// an accessible memory map with `make_read_only` or `make_mut`. If the
+    /// protection cannot be changed, the memory map is returned in the error.
+    #[inline]
+    pub fn make_none(self) -> result::Result<MmapRaw, ProtectError<Mmap>> {
+        self.inner
+            .into_protected(platform::Protection::None)
+            .map_err(|err| err.map(|inner| Mmap { inner }))
+    }
+
+    /// Change the protection of a range of the memory map.
+    ///
+    /// The range is widened to page boundaries.
+    ///
+    /// # Safety
+    ///
+    /// Removing read access from part of the memory map makes dereferencing
+    /// it fault. The caller must not access the range until read access has
+    /// been restored.
+    #[inline]
+    pub unsafe fn protect_range(&self, offset: usize, len: usize, protection: platform::Protection) -> Result<()> {
+        self.inner.protect_range(offset, len, protection)
+    }
 }
 
 impl Deref for Mmap {
@@ -460,6 +1684,14 @@ impl MmapMut {
         self.inner.len == 0
     }
 
+    /// Return the kind of pages backing the memory map.
+    ///
+    /// See `MmapRaw::page_size_used`.
+    #[inline]
+    pub fn page_size_used(&self) -> PageSize {
+        self.inner.page_size_used()
+    }
+
     /// Return a pointer to the memory map.
     ///
     /// # Safety
@@ -471,6 +1703,34 @@ impl MmapMut {
         self.inner.ptr
     }
 
+    /// Return the file backing the memory map, if any.
+    #[inline]
+    pub fn file(&self) -> Option<&File> {
+        self.inner.file.as_ref()
+    }
+
+    /// View `count` values of type `T` starting at byte `offset`.
+    ///
+    /// Returns `Error::AlignmentError` if the address at `offset` is not
+    /// aligned for `T`, and `Error::InvalidArgument` if the values do not fit
+    /// within the memory map.
+    #[inline]
+    pub fn as_slice_of<T: Pod>(&self, offset: usize, count: usize) -> Result<&[T]> {
+        let ptr = self.inner.typed_ptr::<T>(offset, count)?;
+        Ok(unsafe { slice::from_raw_parts(ptr, count) })
+    }
+
+    /// Read a value of type `T` at byte `offset`.
+    ///
+    /// Returns `Error::AlignmentError` if the address at `offset` is not
+    /// aligned for `T`, and `Error::InvalidArgument` if the value does not fit
+    /// within the memory map.
+    #[inline]
+    pub fn read_at<T: Pod>(&self, offset: usize) -> Result<T> {
+        let ptr = self.inner.typed_ptr::<T>(offset, 1)?;
+        Ok(unsafe { ptr::read(ptr) })
+    }
+
     /// Return a mutable pointer to the memory map.
     ///
     /// # Safety
@@ -482,6 +1742,80 @@ impl MmapMut {
         self.inner.ptr
     }
 
+    /// View `count` values of type `T` starting at byte `offset` mutably.
+    ///
+    /// Returns `Error::AlignmentError` if the address at `offset` is not
+    /// aligned for `T`, and `Error::InvalidArgument` if the values do not fit
+    /// within the memory map.
+    #[inline]
+    pub fn as_mut_slice_of<T: Pod>(&mut self, offset: usize, count: usize) -> Result<&mut [T]> {
+        let ptr = self.inner.typed_ptr::<T>(offset, count)?;
+        Ok(unsafe { slice::from_raw_parts_mut(ptr, count) })
+    }
+
+    /// Write a value of type `T` at byte `offset`.
+    ///
+    /// Returns `Error::AlignmentError` if the address at `offset` is not
+    /// aligned for `T`, and `Error::InvalidArgument` if the value does not fit
+    /// within the memory map.
+    #[inline]
+    pub fn write_at<T: Pod>(&mut self, offset: usize, value: T) -> Result<()> {
+        let ptr = self.inner.typed_ptr::<T>(offset, 1)?;
+        unsafe { ptr::write(ptr, value) };
+        Ok(())
+    }
+
+    /// Split the memory map into two regions at byte `mid`, like
+    /// `split_at_mut` but with owned regions.
+    ///
+    /// The first region covers `0..mid` and the second `mid..len`. The memory
+    /// map is unmapped once both regions have been dropped.
+    ///
+    /// The name differs from `split_at_mut` because this consumes the map,
+    /// while the slice method stays available through `DerefMut`.
+    #[inline]
+    pub fn into_split_at(self, mid: usize) -> Result<(MmapRegion, MmapRegion)> {
+        MmapRegion::new(self.inner).into_split_at(mid)
+    }
+
+    /// Split the memory map into regions of `chunk_size` bytes, like
+    /// `chunks_mut` but with owned regions.
+    ///
+    /// The last region is shorter if the length of the memory map is not a
+    /// multiple of `chunk_size`. The memory map is unmapped once every region
+    /// has been dropped. As with `into_split_at`, the slice method
+    /// `chunks_mut` stays available through `DerefMut`.
+    #[inline]
+    pub fn into_chunks(self, chunk_size: usize) -> Result<Vec<MmapRegion>> {
+        MmapRegion::new(self.inner).into_chunks(chunk_size)
+    }
+
+    /// Split the memory map into exactly `count` regions of nearly equal size.
+    ///
+    /// Boundaries fall on page boundaries so that no two regions share a page,
+    /// and region sizes differ by at most one page. If the map spans fewer
+    /// than `count` pages, the last regions are empty. The memory map is
+    /// unmapped once every region has been dropped.
+    #[inline]
+    pub fn split_into(self, count: usize) -> Result<Vec<MmapRegion>> {
+        MmapRegion::new(self.inner).split_into(count)
+    }
+
+    /// Resize the memory map to `new_len` bytes.
+    ///
+    /// For file-backed maps the file is extended with `set_len` before the
+    /// mapping grows, and truncated after it shrinks, so the file always covers
+    /// the mapped range. Anonymous maps grow with zero-filled pages.
+    ///
+    /// On Linux the mapping is resized with `mremap` and may move to a new
+    /// address. Any pointer previously obtained from `as_ptr` or `as_mut_ptr`
+    /// must be considered dangling once this call returns. Other platforms
+    /// return `Error::PlatformError`.
+    #[inline]
+    pub fn resize(&mut self, new_len: usize) -> Result<()> {
+        self.inner.resize(new_len)
+    }
+
     /// Flush the memory map to disk.
     #[inline]
     pub fn flush(&self) -> Result<()> {
@@ -499,6 +1833,145 @@ impl MmapMut {
     pub fn advise(&self, advice: platform::Advice) -> Result<()> {
         self.inner.advise(advice)
     }
+
+    /// Flush a range of the memory map to disk.
+    #[inline]
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.flush_range(offset, len)
+    }
+
+    /// Flush a range of the memory map to disk asynchronously.
+    #[inline]
+    pub fn flush_async_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.flush_async_range(offset, len)
+    }
+
+    /// Advise the kernel about how a range of the memory map will be accessed.
+    #[inline]
+    pub fn advise_range(&self, offset: usize, len: usize, advice: platform::Advice) -> Result<()> {
+        self.inner.advise_range(offset, len, advice)
+    }
+
+    /// Lock the memory map into RAM.
+    ///
+    /// Fails with `Error::MemoryLockLimit` if this would exceed
+    /// `RLIMIT_MEMLOCK`. The memory map is unlocked when it is dropped.
+    #[inline]
+    pub fn lock(&self) -> Result<()> {
+        self.inner.lock()
+    }
+
+    /// Unlock the memory map.
+    #[inline]
+    pub fn unlock(&self) -> Result<()> {
+        self.inner.unlock()
+    }
+
+    /// Lock a range of the memory map into RAM.
+    #[inline]
+    pub fn lock_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.lock_range(offset, len)
+    }
+
+    /// Unlock a range of the memory map.
+    #[inline]
+    pub fn unlock_range(&self, offset: usize, len: usize) -> Result<()> {
+        self.inner.unlock_range(offset, len)
+    }
+
+    /// Report which pages of the memory map are resident in RAM.
+    #[inline]
+    pub fn residency(&self) -> Result<Residency> {
+        self.inner.residency()
+    }
+
+    /// Return the number of bytes of the memory map on resident pages.
+    #[inline]
+    pub fn resident_bytes(&self) -> Result<usize> {
+        self.inner.resident_bytes()
+    }
+
+    /// Return the byte ranges of the memory map that are resident in RAM.
+    #[inline]
+    pub fn resident_ranges(&self) -> Result<Vec<Range<usize>>> {
+        self.inner.resident_ranges()
+    }
+
+    /// Return true if every page of a range of the memory map is resident.
+    #[inline]
+    pub fn is_resident(&self, offset: usize, len: usize) -> Result<bool> {
+        self.inner.is_resident(offset, len)
+    }
+
+    /// Return true if the backing file has changed since it was mapped.
+    ///
+    /// See `MmapRaw::is_stale` for what counts as a change.
+    #[inline]
+    pub fn is_stale(&self) -> Result<bool> {
+        self.inner.is_stale()
+    }
+
+    /// Copy bytes starting at `offset` into `buf`, returning
+    /// `Error::TruncatedMapping` instead of crashing if the backing file has
+    /// been truncated.
+    #[inline]
+    pub fn try_read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.inner.try_read(offset, buf)
+    }
+
+    /// Copy `len` bytes starting at `offset` into `writer`, returning
+    /// `Error::TruncatedMapping` instead of crashing if the backing file has
+    /// been truncated.
+    #[inline]
+    pub fn try_copy_to<W: Write>(&self, offset: usize, len: usize, writer: &mut W) -> Result<()> {
+        self.inner.try_copy_to(offset, len, writer)
+    }
+
+    /// Make the memory map read-only.
+    ///
+    /// If the protection cannot be changed, the memory map is returned in the
+    /// error.
+    #[inline]
+    pub fn make_read_only(self) -> result::Result<Mmap, ProtectError<MmapMut>> {
+        self.inner.make_read_only().map_err(|err| err.map(|inner| MmapMut { inner }))
+    }
+
+    /// Make the memory map readable and executable.
+    ///
+    /// Write access is removed, so code written into an anonymous map can be
+    /// executed without the map ever being writable and executable at once. If
+    /// the protection cannot be changed, the memory map is returned in the
+    /// error.
+    #[inline]
+    pub fn make_exec(self) -> result::Result<Mmap, ProtectError<MmapMut>> {
+        self.inner.make_exec().map_err(|err| err.map(|inner| MmapMut { inner }))
+    }
+
+    /// Remove all access to the memory map.
+    ///
+    /// The returned `MmapRaw` cannot be dereferenced, but can be turned back into
+    /// an accessible memory map with `make_read_only` or `make_mut`. If the
+    /// protection cannot be changed, the memory map is returned in the error.
+    #[inline]
+    pub fn make_none(self) -> result::Result<MmapRaw, ProtectError<MmapMut>> {
+        self.inner
+            .into_protected(platform::Protection::None)
+            .map_err(|err| err.map(|inner| MmapMut { inner }))
+    }
+
+    /// Change the protection of a range of the memory map.
+    ///
+    /// The range is widened to page boundaries.
+    ///
+    /// # Safety
+    ///
+    /// Removing read or write access from part of the memory map makes
+    /// dereferencing it fault. The caller must not access the range in ways
+    /// the new protection forbids until access has been restored.
+    #[inline]
+    pub unsafe fn protect_range(&self, offset: usize, len: usize, protection: platform::Protection) -> Result<()> {
+        self.inner.protect_range(offset, len, protection)
+    }
 }
 
 impl Deref for MmapMut {
@@ -531,6 +2004,131 @@ impl AsMut<[u8]> for MmapMut {
     }
 }
 
+/// The identity, size and modification time of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+    /// Device and inode number, where the platform exposes them.
+    id: Option<(u64, u64)>,
+
+    /// Length of the file in bytes.
+    len: u64,
+
+    /// Last modification time of the file.
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    /// Capture the state of a file from its metadata.
+    pub(crate) fn new(metadata: &Metadata) -> FileStamp {
+        #[cfg(unix)]
+        let id = Some((metadata.dev(), metadata.ino()));
+        #[cfg(not(unix))]
+        let id = None;
+
+        FileStamp {
+            id,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+
+    /// Return true if the file is a different file, or has a different size
+    /// or, if `check_modified` is set, modification time.
+    #[inline]
+    fn differs(&self, other: &FileStamp, check_modified: bool) -> bool {
+        self.id != other.id || self.len != other.len || (check_modified && self.modified != other.modified)
+    }
+}
+
+/// Return the backend in `backend`, or the operating system if there is none.
+#[inline]
+fn backend_or_os(backend: &Option<Arc<dyn Backend>>) -> &dyn Backend {
+    match backend {
+        Some(backend) => &**backend,
+        None => &OsBackend,
+    }
+}
+
+/// Check whether a kind of page can be used at all before trying to map it.
+///
+/// Hugetlb sizes are left to the kernel, which reports an empty pool itself,
+/// except on macOS, which silently ignores them.
+fn check_available(size: PageSize, mode: Option<ThpMode>) -> io::Result<()> {
+    match size {
+        PageSize::OneGB | PageSize::TwoMB if cfg!(target_os = "macos") => {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "hugetlb pages are not supported"));
+        }
+        PageSize::Transparent => {}
+        _ => return Ok(()),
+    }
+
+    let mode = match mode {
+        Some(ThpMode::Collapse) => ThpMode::Collapse,
+        _ => ThpMode::Enable,
+    };
+    match huge_pages::transparent_huge_pages() {
+        Some(status) if status.honours(mode) => Ok(()),
+        Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "transparent huge pages are disabled")),
+        None => Err(io::Error::new(io::ErrorKind::Unsupported, "transparent huge pages are not supported")),
+    }
+}
+
+/// Check that the range `offset..offset + len` lies within `bound` bytes.
+pub(crate) fn check_range(offset: usize, len: usize, bound: usize) -> Result<()> {
+    let end = offset.checked_add(len).ok_or(Error::InvalidArgument("Range overflows".into()))?;
+    if end > bound {
+        return Err(Error::InvalidArgument(format!(
+            "Range {}..{} is out of bounds for a memory map of length {}",
+            offset, end, bound
+        )));
+    }
+    Ok(())
+}
+
+/// Record a memory map created outside of `MmapOptions` in the statistics.
+///
+/// The matching decrement happens when the `MmapRaw` is dropped.
+#[inline]
+pub(crate) fn record_mapping(len: usize) {
+    TOTAL_MAPPED_MEMORY.fetch_add(len, Ordering::Relaxed);
+    ACTIVE_MAPPINGS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Remove a memory map unmapped outside of `MmapRaw` from the statistics.
+#[inline]
+pub(crate) fn release_mapping(len: usize) {
+    TOTAL_MAPPED_MEMORY.fetch_sub(len, Ordering::Relaxed);
+    ACTIVE_MAPPINGS.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Record memory committed into an existing mapping in the statistics.
+#[inline]
+pub(crate) fn record_commit(len: usize) {
+    TOTAL_MAPPED_MEMORY.fetch_add(len, Ordering::Relaxed);
+}
+
+/// Record memory decommitted from an existing mapping in the statistics.
+#[inline]
+pub(crate) fn record_decommit(len: usize) {
+    TOTAL_MAPPED_MEMORY.fetch_sub(len, Ordering::Relaxed);
+}
+
+/// Lock all memory of the process into RAM.
+///
+/// `current` locks every page mapped now, and `future` every page mapped from
+/// now on, including memory maps that do not use `MmapOptions::locked`. Fails
+/// with `Error::MemoryLockLimit` if this would exceed `RLIMIT_MEMLOCK`.
+#[inline]
+pub fn lock_all(current: bool, future: bool) -> Result<()> {
+    platform::lock_all(current, future)
+}
+
+/// Undo `lock_all`, unlocking all memory of the process.
+#[inline]
+pub fn unlock_all() -> Result<()> {
+    platform::unlock_all()
+}
+
 /// Get the total amount of memory currently mapped.
 #[inline]
 pub fn total_mapped_memory() -> usize {
@@ -541,4 +2139,364 @@ pub fn total_mapped_memory() -> usize {
 #[inline]
 pub fn active_mappings() -> usize {
     ACTIVE_MAPPINGS.load(Ordering::Relaxed)
-}
\ No newline at end of file
+}
+/// Serialize tests that create memory maps, so that tests can check the
+/// mapping counters.
+#[cfg(test)]
+pub(crate) fn test_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn failed_make_mut_returns_the_map() {
+        let _lock = test_lock();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"keep me").unwrap();
+        let read_only = File::open(file.path()).unwrap();
+
+        let map = unsafe { MmapOptions::new().map(&read_only) }.unwrap();
+        let err = map.make_mut().unwrap_err();
+        assert!(matches!(err.error(), Error::ProtectionError(_) | Error::Io(_)));
+
+        let map = err.into_inner();
+        assert_eq!(&map[..], b"keep me");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn try_read_reports_truncation_and_leaves_the_map_usable() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(3 * page_size as u64).unwrap();
+        let mut map = unsafe { MmapOptions::new().map_mut(&file) }.unwrap();
+        map.fill(7);
+        map.flush().unwrap();
+
+        file.set_len(page_size as u64 + 100).unwrap();
+        let mut buf = vec![0; 3 * page_size];
+        match map.try_read(0, &mut buf) {
+            Err(Error::TruncatedMapping { offset }) => assert_eq!(offset, 2 * page_size),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(buf[..page_size + 100].iter().all(|&byte| byte == 7));
+
+        // The missing pages were not replaced, so they work again once the
+        // file grows back
+        file.set_len(3 * page_size as u64).unwrap();
+        map[2 * page_size] = 9;
+        map.flush().unwrap();
+        let mut byte = [0];
+        std::os::unix::fs::FileExt::read_exact_at(&file, &mut byte, 2 * page_size as u64).unwrap();
+        assert_eq!(byte, [9]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unaligned_huge_page_map_is_unmapped_whole() {
+        let _lock = test_lock();
+        let options = MmapOptions::new().huge_pages(HugePageSize::TwoMB);
+        // Skip unless the hugetlb pool has a free 2MB page
+        let map = match unsafe { options.map_anon(4096) } {
+            Ok(map) => map,
+            Err(_) => return,
+        };
+        assert_eq!(map.len(), 4096);
+        let start = format!("{:x}-", map.as_ptr() as usize);
+        let mapped = || {
+            std::fs::read_to_string("/proc/self/maps")
+                .unwrap()
+                .lines()
+                .any(|line| line.starts_with(&start))
+        };
+        assert!(mapped());
+
+        drop(map);
+        assert!(!mapped());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resize_grows_and_shrinks_a_file_map_and_its_statistics() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(page_size as u64).unwrap();
+        let mut map = unsafe { MmapOptions::new().map_mut(&file) }.unwrap();
+        map.fill(3);
+        let (total, active) = (total_mapped_memory(), active_mappings());
+
+        map.resize(3 * page_size).unwrap();
+        assert_eq!(map.len(), 3 * page_size);
+        assert_eq!(file.metadata().unwrap().len(), 3 * page_size as u64);
+        assert!(map[..page_size].iter().all(|&byte| byte == 3));
+        assert!(map[page_size..].iter().all(|&byte| byte == 0));
+        assert_eq!(total_mapped_memory(), total + 2 * page_size);
+        assert_eq!(active_mappings(), active);
+
+        map.resize(100).unwrap();
+        assert_eq!(map.len(), 100);
+        assert_eq!(file.metadata().unwrap().len(), 100);
+        assert!(map.iter().all(|&byte| byte == 3));
+        assert_eq!(total_mapped_memory(), total - page_size + 100);
+        assert_eq!(active_mappings(), active);
+
+        drop(map);
+        assert_eq!(total_mapped_memory(), total - page_size);
+        assert_eq!(active_mappings(), active - 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resize_moves_a_map_that_cannot_grow_in_place() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        // Find two free pages, then map the first and block the second so the
+        // map has to move to grow
+        let start = unsafe { MmapMut::map_anon(2 * page_size) }.unwrap().as_ptr() as usize;
+        let options = MmapOptions::new().write(true);
+        let map = unsafe { options.clone().at_address(start).map_anon(page_size) };
+        let blocker = unsafe { options.at_address(start + page_size).map_anon(page_size) };
+        let (mut map, blocker) = match (map, blocker) {
+            (Ok(map), Ok(blocker)) => (map, blocker),
+            // Another thread took the pages in the meantime
+            (Err(Error::AddressInUse { .. }), _) | (_, Err(Error::AddressInUse { .. })) => return,
+            (Err(err), _) | (_, Err(err)) => panic!("unexpected error: {:?}", err),
+        };
+        map.fill(5);
+        let (old_ptr, total) = (map.as_ptr(), total_mapped_memory());
+
+        map.resize(4 * page_size).unwrap();
+        assert_ne!(map.as_ptr(), old_ptr);
+        assert_eq!(map.len(), 4 * page_size);
+        assert!(map[..page_size].iter().all(|&byte| byte == 5));
+        assert!(map[page_size..].iter().all(|&byte| byte == 0));
+        assert_eq!(total_mapped_memory(), total + 3 * page_size);
+        drop(blocker);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn create_preallocates_the_file() {
+        let _lock = test_lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("created");
+        let len = 5 * alignment::page_size();
+
+        let mut map = unsafe { MmapOptions::new().sync_parent_dir(true).create(&path, len) }.unwrap();
+        assert_eq!(map.len(), len);
+        assert!(map.iter().all(|&byte| byte == 0));
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.len(), len as u64);
+        assert!(metadata.blocks() * 512 >= len as u64);
+
+        map[..5].copy_from_slice(b"hello");
+        map.flush().unwrap();
+        drop(map);
+        assert_eq!(&fs::read(&path).unwrap()[..5], b"hello");
+
+        // An existing file is truncated first
+        let map = unsafe { MmapOptions::new().create(&path, 100) }.unwrap();
+        assert!(map.iter().all(|&byte| byte == 0));
+        assert_eq!(fs::metadata(&path).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn open_maps_the_file_at_a_path() {
+        let _lock = test_lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("opened");
+        fs::write(&path, b"original").unwrap();
+
+        let map = unsafe { MmapOptions::new().open(&path) }.unwrap();
+        assert_eq!(&map[..], b"original");
+
+        let mut map_mut = unsafe { MmapOptions::new().open_mut(&path) }.unwrap();
+        map_mut[..3].copy_from_slice(b"ORI");
+        map_mut.flush().unwrap();
+        assert_eq!(&map[..], b"ORIginal");
+        assert_eq!(fs::read(&path).unwrap(), b"ORIginal");
+
+        assert!(unsafe { MmapOptions::new().open(dir.path().join("missing")) }.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn map_fd_keeps_the_callers_descriptor() {
+        let _lock = test_lock();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"descriptor").unwrap();
+        let fd = std::os::unix::io::OwnedFd::from(file);
+
+        let mut map_mut = unsafe { MmapOptions::new().map_mut_fd(&fd) }.unwrap();
+        map_mut[..4].copy_from_slice(b"DESC");
+        drop(map_mut);
+
+        // The map owns a duplicate, so the descriptor outlives it
+        let map = unsafe { MmapOptions::new().map_fd(&fd) }.unwrap();
+        assert_eq!(&map[..], b"DESCriptor");
+        drop(fd);
+        assert_eq!(&map[..], b"DESCriptor");
+    }
+
+    /// Return the amount of memory locked by the process, in kB.
+    #[cfg(target_os = "linux")]
+    fn locked_kb() -> usize {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmLck:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    /// Return true if locking `len` bytes should succeed in this process.
+    #[cfg(target_os = "linux")]
+    fn can_lock(len: usize) -> bool {
+        platform::memlock_limit().is_none_or(|limit| locked_kb() as u64 * 1024 + len as u64 <= limit)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn lock_and_unlock_pin_the_pages() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let map = unsafe { MmapMut::map_anon(4 * page_size) }.unwrap();
+        if !can_lock(map.len()) {
+            return;
+        }
+        let before = locked_kb();
+
+        map.lock().unwrap();
+        assert_eq!(locked_kb(), before + 4 * page_size / 1024);
+        assert!(map.residency().unwrap().is_fully_resident());
+        map.unlock().unwrap();
+        assert_eq!(locked_kb(), before);
+
+        map.lock_range(page_size + 1, page_size).unwrap();
+        assert_eq!(locked_kb(), before + 2 * page_size / 1024);
+        map.unlock_range(page_size, 2 * page_size).unwrap();
+        assert_eq!(locked_kb(), before);
+
+        // Dropping a map releases its locked pages
+        map.lock_range(0, 1).unwrap();
+        assert_eq!(locked_kb(), before + page_size / 1024);
+        drop(map);
+        assert_eq!(locked_kb(), before);
+    }
+
+    #[test]
+    fn lock_range_rejects_ranges_out_of_bounds() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let map = unsafe { MmapMut::map_anon(page_size) }.unwrap();
+
+        assert!(matches!(map.lock_range(0, page_size + 1), Err(Error::InvalidArgument(_))));
+        assert!(matches!(map.lock_range(page_size, 1), Err(Error::InvalidArgument(_))));
+        assert!(matches!(map.lock_range(usize::MAX, 2), Err(Error::InvalidArgument(_))));
+        assert!(matches!(map.unlock_range(1, page_size), Err(Error::InvalidArgument(_))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn locked_option_locks_the_map() {
+        let _lock = test_lock();
+        let len = 2 * alignment::page_size();
+        if !can_lock(len) {
+            return;
+        }
+        let before = locked_kb();
+
+        let map = unsafe { MmapOptions::new().write(true).locked(true).map_anon(len) }.unwrap();
+        assert_eq!(locked_kb(), before + len / 1024);
+        assert!(map.residency().unwrap().is_fully_resident());
+
+        // Locking on fault leaves the pages out of RAM until they are touched
+        let options = MmapOptions::new().write(true).locked(true).lock_on_fault(true);
+        let mut lazy = unsafe { options.map_anon(len) }.unwrap();
+        assert_eq!(locked_kb(), before + 2 * len / 1024);
+        assert_eq!(lazy.residency().unwrap().resident_pages(), 0);
+        lazy[0] = 1;
+        assert_eq!(lazy.residency().unwrap().resident_pages(), 1);
+
+        drop((map, lazy));
+        assert_eq!(locked_kb(), before);
+    }
+
+    #[test]
+    fn at_address_refuses_to_replace_a_mapping() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let mut existing = unsafe { MmapMut::map_anon(page_size) }.unwrap();
+        existing[0] = 7;
+        let address = existing.as_ptr() as usize;
+
+        match unsafe { MmapOptions::new().at_address(address).map_anon(page_size) } {
+            Err(Error::AddressInUse { address: reported }) => assert_eq!(reported, address),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(existing[0], 7);
+
+        // The address can be used once it is free
+        drop(existing);
+        match unsafe { MmapOptions::new().write(true).at_address(address).map_anon(page_size) } {
+            Ok(map) => assert_eq!(map.as_ptr() as usize, address),
+            // Another thread took the address in the meantime
+            Err(Error::AddressInUse { .. }) => {}
+            Err(err) => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn placement_requires_an_aligned_address() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let mapped = (total_mapped_memory(), active_mappings());
+
+        for options in [
+            MmapOptions::new().at_address(0),
+            MmapOptions::new().at_address(page_size + 1),
+            MmapOptions::new().hint_address(page_size / 2),
+            MmapOptions::new().alignment(4 * page_size).at_address(5 * page_size),
+        ] {
+            match unsafe { options.map_anon(page_size) } {
+                Err(Error::UnalignedAddress { alignment, .. }) => assert!(alignment >= page_size),
+                result => panic!("unexpected result: {:?}", result),
+            }
+        }
+
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(page_size as u64).unwrap();
+        match unsafe { MmapOptions::new().at_address(page_size + 8).map(&file) } {
+            Err(Error::UnalignedAddress { address, alignment }) => {
+                assert_eq!((address, alignment), (page_size + 8, page_size));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!((total_mapped_memory(), active_mappings()), mapped);
+    }
+
+    #[test]
+    fn hint_address_falls_back_when_taken() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let existing = unsafe { MmapMut::map_anon(page_size) }.unwrap();
+        let taken = existing.as_ptr() as usize;
+
+        let mut map = unsafe { MmapOptions::new().write(true).hint_address(taken).map_anon(page_size) }.unwrap();
+        assert_ne!(map.as_ptr() as usize, taken);
+        map[0] = 1;
+
+        // A free hint is honoured
+        drop(existing);
+        match unsafe { MmapOptions::new().hint_address(taken).map_anon(page_size) } {
+            Ok(map) => assert_eq!(map.as_ptr() as usize, taken),
+            Err(err) => panic!("unexpected error: {:?}", err),
+        }
+    }
+}
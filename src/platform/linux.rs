@@ -1,23 +1,44 @@
 //! Linux-specific memory mapping implementation.
 
+use std::ffi::{CString, OsStr};
 use std::fs::File;
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::ptr;
+use std::time::Duration;
 
 use libc::{
-    c_int, c_void, mmap, munmap, msync, madvise, PROT_NONE, PROT_READ, PROT_WRITE, PROT_EXEC,
-    MAP_SHARED, MAP_PRIVATE, MAP_ANONYMOUS, MAP_HUGETLB, MAP_HUGE_2MB, MAP_HUGE_1GB,
+    c_int, c_ulong, c_void, mmap, munmap, msync, madvise, mprotect, PROT_NONE, PROT_READ, PROT_WRITE, PROT_EXEC,
+    MAP_SHARED, MAP_PRIVATE, MAP_ANONYMOUS, MAP_FIXED, MAP_NORESERVE, MAP_HUGETLB, MAP_HUGE_2MB, MAP_HUGE_1GB,
     MAP_STACK, MAP_POPULATE, MAP_FIXED_NOREPLACE, MS_ASYNC, MS_SYNC, MS_INVALIDATE,
     MADV_NORMAL, MADV_RANDOM, MADV_SEQUENTIAL, MADV_WILLNEED, MADV_DONTNEED, MADV_FREE,
+    MADV_HUGEPAGE, MADV_NOHUGEPAGE, MADV_POPULATE_READ, MADV_POPULATE_WRITE, MPOL_BIND, MPOL_INTERLEAVE,
+    MPOL_LOCAL, MPOL_PREFERRED,
 };
 
 use crate::error::{Error, Result};
-use crate::mmap::MmapRaw;
-use crate::advanced::{HugePageSize, NumaPolicy};
-use crate::platform::Advice;
+use crate::mmap::{MmapOptions, MmapRaw, Placement};
+use crate::advanced::{HugePageSize, NodeMask, NumaFlags, NumaPolicy};
+use crate::platform::{Advice, Protection};
 use crate::utils::alignment;
 
+/// `madvise` advice to collapse a range into transparent huge pages, which
+/// `libc` only defines for glibc targets.
+const MADV_COLLAPSE: c_int = 25;
+
+/// `mbind` flag to fail if existing pages do not follow the policy.
+const MPOL_MF_STRICT: u32 = 1 << 0;
+
+/// `mbind` flag to migrate existing pages mapped only by this process.
+const MPOL_MF_MOVE: u32 = 1 << 1;
+
+/// `get_mempolicy` flag to return the nodes the thread may allocate from.
+const MPOL_F_MEMS_ALLOWED: c_ulong = 1 << 2;
+
 /// Map a file into memory on Linux.
 ///
 /// # Safety
@@ -25,20 +46,21 @@ use crate::utils::alignment;
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and potentially modified, which might lead to undefined behavior if not used
 /// correctly.
-pub unsafe fn map_file(
-    file: &File,
-    offset: u64,
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    huge_pages: Option<HugePageSize>,
-    numa_policy: Option<NumaPolicy>,
-    stack: bool,
-    copy_on_write: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<MmapRaw> {
+pub unsafe fn map_file(file: &File, offset: u64, len: usize, options: &MmapOptions) -> Result<MmapRaw> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        huge_pages,
+        stack,
+        copy_on_write,
+        populate,
+        alignment,
+        placement,
+        ..
+    } = options;
+    let numa_policy = options.numa();
+
     // Calculate protection flags
     let mut prot = PROT_NONE;
     if readable {
@@ -63,17 +85,15 @@ pub unsafe fn map_file(
         flags |= MAP_STACK;
     }
 
-    // Add huge page support if requested
+    // Huge pages of a file map come from the file system of the file, as
+    // MAP_HUGETLB only applies to anonymous maps
     if let Some(page_size) = huge_pages {
-        flags |= MAP_HUGETLB;
-        match page_size {
-            HugePageSize::TwoMB => flags |= MAP_HUGE_2MB,
-            HugePageSize::OneGB => flags |= MAP_HUGE_1GB,
-        }
+        check_huge_page_file(file, page_size)?;
     }
 
-    // Add populate flag if requested
-    if populate {
+    // Add populate flag if requested, unless the pages have to wait for the
+    // NUMA policy
+    if populate && numa_policy.is_none() {
         flags |= MAP_POPULATE;
     }
 
@@ -83,9 +103,16 @@ pub unsafe fn map_file(
     let offset_delta = offset - aligned_offset;
     let aligned_len = len + offset_delta as usize;
 
+    // Map at the requested address if any
+    let mut aligned_addr: *mut c_void = match placement {
+        Some(placement) => {
+            map_placed(placement, aligned_len, prot, flags, file.as_raw_fd(), aligned_offset as i64, alignment)?
+        }
+        None => ptr::null_mut(),
+    };
+
     // Apply custom alignment if requested
-    let mut aligned_addr: *mut c_void = ptr::null_mut();
-    if let Some(align) = alignment {
+    if let Some(align) = alignment.filter(|_| aligned_addr.is_null()) {
         if align > page_size {
             // For custom alignment, we'll allocate extra space and then adjust the pointer
             let extra = align - 1;
@@ -146,14 +173,25 @@ pub unsafe fn map_file(
     }
 
     // Apply NUMA policy if requested
-    if let Some(policy) = numa_policy {
-        apply_numa_policy(addr, aligned_len, policy)?;
+    if let Some((policy, numa_flags)) = numa_policy {
+        // Huge page maps span whole huge pages, and mbind rejects partial ones
+        let span = match huge_pages {
+            Some(size) => alignment::align_up(aligned_len, size.bytes()),
+            None => aligned_len,
+        };
+        if let Err(err) = apply_numa_policy(addr, span, policy, numa_flags) {
+            munmap(addr, span);
+            return Err(err);
+        }
+        if populate {
+            populate_range(addr, span, writable && copy_on_write);
+        }
     }
 
     // Adjust pointer for the offset delta
     let ptr = (addr as usize + offset_delta as usize) as *mut u8;
 
-    Ok(MmapRaw { ptr, len })
+    Ok(MmapRaw::new(ptr, len))
 }
 
 /// Create an anonymous memory map on Linux.
@@ -162,17 +200,20 @@ pub unsafe fn map_file(
 ///
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and modified, which might lead to undefined behavior if not used correctly.
-pub unsafe fn map_anon(
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    huge_pages: Option<HugePageSize>,
-    numa_policy: Option<NumaPolicy>,
-    stack: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<MmapRaw> {
+pub unsafe fn map_anon(len: usize, options: &MmapOptions) -> Result<MmapRaw> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        huge_pages,
+        stack,
+        populate,
+        alignment,
+        placement,
+        ..
+    } = options;
+    let numa_policy = options.numa();
+
     // Calculate protection flags
     let mut prot = PROT_NONE;
     if readable {
@@ -202,16 +243,21 @@ pub unsafe fn map_anon(
         }
     }
 
-    // Add populate flag if requested
-    if populate {
+    // Add populate flag if requested, unless the pages have to wait for the
+    // NUMA policy
+    if populate && numa_policy.is_none() {
         flags |= MAP_POPULATE;
     }
 
-    // Apply custom alignment if requested
+    // Map at the requested address if any
     let mut aligned_len = len;
-    let mut aligned_addr: *mut c_void = ptr::null_mut();
+    let mut aligned_addr: *mut c_void = match placement {
+        Some(placement) => map_placed(placement, len, prot, flags, -1, 0, alignment)?,
+        None => ptr::null_mut(),
+    };
     
-    if let Some(align) = alignment {
+    // Apply custom alignment if requested
+    if let Some(align) = alignment.filter(|_| aligned_addr.is_null()) {
         let page_size = page_size();
         if align > page_size {
             // For custom alignment, we'll allocate extra space and then adjust the pointer
@@ -274,11 +320,84 @@ pub unsafe fn map_anon(
     }
 
     // Apply NUMA policy if requested
-    if let Some(policy) = numa_policy {
-        apply_numa_policy(addr, aligned_len, policy)?;
+    if let Some((policy, numa_flags)) = numa_policy {
+        // Huge page maps span whole huge pages, and mbind rejects partial ones
+        let span = match huge_pages {
+            Some(size) => alignment::align_up(aligned_len, size.bytes()),
+            None => aligned_len,
+        };
+        if let Err(err) = apply_numa_policy(addr, span, policy, numa_flags) {
+            munmap(addr, span);
+            return Err(err);
+        }
+        if populate {
+            populate_range(addr, span, writable);
+        }
+    }
+
+    Ok(MmapRaw::new(addr as *mut u8, aligned_len))
+}
+
+/// Map `len` bytes at the address requested by `placement`.
+///
+/// Fixed placements use `MAP_FIXED_NOREPLACE`, so an occupied range fails with
+/// `Error::AddressInUse` instead of being replaced. Kernels older than 4.17
+/// ignore the flag and treat the address as a hint, so the result is checked
+/// as well. Returns null if a hint could not be honoured with the requested
+/// alignment, so the caller should map anywhere.
+unsafe fn map_placed(
+    placement: Placement,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: i64,
+    alignment: Option<usize>,
+) -> Result<*mut c_void> {
+    let requested = placement.address() as *mut c_void;
+    let flags = match placement {
+        Placement::Fixed(_) => flags | MAP_FIXED_NOREPLACE,
+        Placement::Hint(_) => flags,
+    };
+
+    let addr = mmap(requested, len, prot, flags, fd, offset);
+    if addr == libc::MAP_FAILED {
+        let err = io::Error::last_os_error();
+        return match placement {
+            Placement::Fixed(address) if err.raw_os_error() == Some(libc::EEXIST) => {
+                Err(Error::AddressInUse { address })
+            }
+            _ => Err(Error::Io(err)),
+        };
+    }
+
+    check_placed(placement, addr, len, alignment)
+}
+
+/// Check a mapping of `len` bytes that the kernel placed at `addr` against the
+/// requested placement, unmapping it if it cannot be used.
+unsafe fn check_placed(
+    placement: Placement,
+    addr: *mut c_void,
+    len: usize,
+    alignment: Option<usize>,
+) -> Result<*mut c_void> {
+    if addr as usize != placement.address() {
+        let misaligned = alignment.is_some_and(|align| !alignment::is_aligned(addr as usize, align));
+        match placement {
+            Placement::Fixed(address) => {
+                munmap(addr, len);
+                return Err(Error::AddressInUse { address });
+            }
+            Placement::Hint(_) if misaligned => {
+                munmap(addr, len);
+                return Ok(ptr::null_mut());
+            }
+            Placement::Hint(_) => {}
+        }
     }
 
-    Ok(MmapRaw { ptr: addr as *mut u8, len: aligned_len })
+    Ok(addr)
 }
 
 /// Flush memory map changes to disk on Linux.
@@ -313,6 +432,164 @@ pub unsafe fn unmap(addr: *mut u8, len: usize) -> Result<()> {
     }
 }
 
+/// Lock a memory range into RAM on Linux.
+///
+/// With `on_fault`, pages are locked as they are first touched (`mlock2` with
+/// `MLOCK_ONFAULT`) instead of being faulted in immediately.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn lock(addr: *mut u8, len: usize, on_fault: bool) -> Result<()> {
+    let result = if on_fault {
+        libc::mlock2(addr as *const c_void, len, libc::MLOCK_ONFAULT)
+    } else {
+        libc::mlock(addr as *const c_void, len)
+    };
+    
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(lock_error(Some(len), io::Error::last_os_error()))
+    }
+}
+
+/// Unlock a memory range on Linux.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn unlock(addr: *mut u8, len: usize) -> Result<()> {
+    let result = libc::munlock(addr as *const c_void, len);
+    
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Lock all current and/or future memory of the process into RAM on Linux.
+pub fn lock_all(current: bool, future: bool) -> Result<()> {
+    let mut flags = 0;
+    if current {
+        flags |= libc::MCL_CURRENT;
+    }
+    if future {
+        flags |= libc::MCL_FUTURE;
+    }
+
+    let result = unsafe { libc::mlockall(flags) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(lock_error(None, io::Error::last_os_error()))
+    }
+}
+
+/// Unlock all memory of the process on Linux.
+pub fn unlock_all() -> Result<()> {
+    let result = unsafe { libc::munlockall() };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Return the soft `RLIMIT_MEMLOCK` limit in bytes, or `None` if unlimited.
+pub fn memlock_limit() -> Option<u64> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+
+    if result != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        None
+    } else {
+        Some(limit.rlim_cur as u64)
+    }
+}
+
+/// Build the error for a failed lock, reporting `RLIMIT_MEMLOCK` when the
+/// failure is caused by the limit.
+///
+/// `EPERM` means the process may not lock memory at all, for lack of
+/// privilege while the limit is 0, so it stays a permission error.
+pub(crate) fn lock_error(requested: Option<usize>, err: io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(libc::ENOMEM) | Some(libc::EAGAIN) => match memlock_limit() {
+            Some(limit) => Error::MemoryLockLimit { requested, limit },
+            None => Error::Io(err),
+        },
+        _ => Error::Io(err),
+    }
+}
+
+/// Report which pages of a memory range are resident in RAM on Linux using
+/// `mincore`.
+///
+/// `addr` must be page-aligned. The result holds one entry per page.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn residency(addr: *mut u8, len: usize) -> Result<Vec<bool>> {
+    let page_size = page_size();
+    let pages = len.div_ceil(page_size);
+    let mut vec: Vec<libc::c_uchar> = vec![0; pages];
+    
+    let result = libc::mincore(addr as *mut c_void, len, vec.as_mut_ptr());
+    
+    if result == 0 {
+        Ok(vec.into_iter().map(|page| page & 1 != 0).collect())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Change the protection of a memory range on Linux using `mprotect`.
+///
+/// # Safety
+///
+/// This function is unsafe because removing access from memory that is still
+/// referenced causes faults on the next access.
+pub unsafe fn protect(addr: *mut u8, len: usize, protection: Protection) -> Result<()> {
+    let prot = match protection {
+        Protection::None => PROT_NONE,
+        Protection::Read => PROT_READ,
+        Protection::ReadWrite => PROT_READ | PROT_WRITE,
+        Protection::ReadExec => PROT_READ | PROT_EXEC,
+        Protection::ReadWriteExec => PROT_READ | PROT_WRITE | PROT_EXEC,
+    };
+    
+    let result = mprotect(addr as *mut c_void, len, prot);
+    
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::ProtectionError(io::Error::last_os_error()))
+    }
+}
+
+/// Resize a memory mapping on Linux using `mremap`.
+///
+/// The mapping may be moved to a new address if it cannot be grown in place.
+///
+/// # Safety
+///
+/// This function is unsafe because it invalidates every pointer into the old
+/// mapping if the mapping is moved.
+pub unsafe fn remap(addr: *mut u8, old_len: usize, new_len: usize) -> Result<*mut u8> {
+    let new_addr = libc::mremap(addr as *mut c_void, old_len, new_len, libc::MREMAP_MAYMOVE);
+
+    if new_addr == libc::MAP_FAILED {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(new_addr as *mut u8)
+    }
+}
+
 /// Advise the kernel about how the memory map will be accessed on Linux.
 ///
 /// # Safety
@@ -328,6 +605,9 @@ pub unsafe fn advise(addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
         Advice::SequentialOnce => MADV_SEQUENTIAL,  // Updated
         Advice::RandomOnce => MADV_RANDOM,          // Updated
         Advice::Free => MADV_FREE,
+        Advice::HugePage => MADV_HUGEPAGE,
+        Advice::NoHugePage => MADV_NOHUGEPAGE,
+        Advice::Collapse => MADV_COLLAPSE,
     };
     
     let result = madvise(addr as *mut c_void, len, advice_flag);
@@ -339,41 +619,783 @@ pub unsafe fn advise(addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
     }
 }
 
-/// Apply NUMA policy to a memory mapping.
-unsafe fn apply_numa_policy(addr: *mut c_void, len: usize, policy: NumaPolicy) -> Result<()> {
-    // This is a simplified implementation. In a real-world scenario,
-    // you would use the libnuma library or direct syscalls for more advanced NUMA control.
+/// Preallocate disk space for a file on Linux using `fallocate`.
+///
+/// File systems that do not support `fallocate` fail with `EOPNOTSUPP`
+/// instead of leaving the file sparse.
+pub fn preallocate(file: &File, len: u64) -> Result<()> {
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) };
     
-    #[cfg(target_os = "linux")]
-    {
-        match policy {
-            NumaPolicy::Interleave(nodes) => {
-                // Implement interleave policy using mbind
-                // This is a placeholder - actual implementation would use libnuma or syscalls
-                Ok(())
-            },
-            NumaPolicy::Bind(node) => {
-                // Implement bind policy using mbind
-                // This is a placeholder - actual implementation would use libnuma or syscalls
-                Ok(())
-            },
-            NumaPolicy::Preferred(node) => {
-                // Implement preferred policy using mbind
-                // This is a placeholder - actual implementation would use libnuma or syscalls
-                Ok(())
-            },
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Flush a directory's entries to disk on Linux.
+pub fn sync_dir(path: &Path) -> Result<()> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+/// Open a POSIX shared memory object on Linux using `shm_open`.
+///
+/// `name` must start with a slash and contain no other slashes.
+pub fn shm_open(name: &str, writable: bool, create: bool, exclusive: bool) -> Result<File> {
+    let c_name = CString::new(name).map_err(|_| Error::InvalidArgument("Shared memory name contains a NUL byte".into()))?;
+
+    let mut oflag = if writable { libc::O_RDWR } else { libc::O_RDONLY };
+    oflag |= libc::O_CLOEXEC;
+    if create {
+        oflag |= libc::O_CREAT;
+    }
+    if exclusive {
+        oflag |= libc::O_EXCL;
+    }
+
+    let fd = unsafe { libc::shm_open(c_name.as_ptr(), oflag, 0o600 as libc::mode_t) };
+
+    if fd == -1 {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+/// Remove a POSIX shared memory object on Linux using `shm_unlink`.
+pub fn shm_unlink(name: &str) -> Result<()> {
+    let c_name = CString::new(name).map_err(|_| Error::InvalidArgument("Shared memory name contains a NUL byte".into()))?;
+
+    let result = unsafe { libc::shm_unlink(c_name.as_ptr()) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Create an anonymous memory-backed file on Linux using `memfd_create`.
+pub fn memfd_create(name: &str, huge_pages: Option<HugePageSize>, allow_sealing: bool) -> Result<File> {
+    let name = CString::new(name).map_err(|_| Error::InvalidArgument("memfd name contains a NUL byte".into()))?;
+
+    let mut flags = libc::MFD_CLOEXEC;
+    if allow_sealing {
+        flags |= libc::MFD_ALLOW_SEALING;
+    }
+    if let Some(page_size) = huge_pages {
+        flags |= libc::MFD_HUGETLB;
+        match page_size {
+            HugePageSize::TwoMB => flags |= libc::MFD_HUGE_2MB,
+            HugePageSize::OneGB => flags |= libc::MFD_HUGE_1GB,
         }
     }
-    
-    #[cfg(not(target_os = "linux"))]
-    {
-        // NUMA is primarily a Linux feature
-        Err(Error::PlatformError(libc::ENOSYS))
+
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), flags) };
+
+    if fd == -1 {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd) })
     }
 }
 
+/// Return the seals applied to a file on Linux.
+pub fn get_seals(file: &File) -> Result<c_int> {
+    let seals = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GET_SEALS) };
+
+    if seals == -1 {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(seals)
+    }
+}
+
+/// Add seals to a file on Linux.
+pub fn add_seals(file: &File, seals: c_int) -> Result<()> {
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Send a file descriptor over a Unix socket on Linux using `SCM_RIGHTS`.
+pub fn send_fd(socket: &UnixStream, fd: BorrowedFd<'_>) -> Result<()> {
+    // At least one byte of regular data must accompany the descriptor
+    let mut payload = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<c_int>() as u32) } as usize;
+    let mut control = vec![0u8; space];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = space as _;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<c_int>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut c_int, fd.as_raw_fd());
+    }
+
+    let result = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+
+    if result == -1 {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Number of descriptors `receive_fd` makes room for in one message.
+const MAX_RECEIVED_FDS: usize = 16;
+
+/// Receive a file descriptor sent with `send_fd` over a Unix socket on Linux.
+///
+/// Only the first descriptor of the message is returned; any others are
+/// closed. Fails if the kernel truncated the control data, since descriptors
+/// may then have been lost.
+pub fn receive_fd(socket: &UnixStream) -> Result<OwnedFd> {
+    let mut payload = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut c_void,
+        iov_len: payload.len(),
+    };
+
+    // Leave room for more descriptors than the one expected, so that extra
+    // descriptors are received and closed rather than truncated away.
+    let space = unsafe { libc::CMSG_SPACE((MAX_RECEIVED_FDS * mem::size_of::<c_int>()) as u32) } as usize;
+    let mut control = vec![0u8; space];
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = space as _;
+
+    let result = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+
+    if result == -1 {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+
+    // Take ownership of every received descriptor first, so that all of them
+    // are closed on error and any beyond the first are closed on success.
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const c_int;
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<c_int>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "control data was truncated while receiving a descriptor",
+        )));
+    }
+    if result == 0 && fds.is_empty() {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "socket closed before a descriptor was received")));
+    }
+
+    fds.into_iter()
+        .next()
+        .ok_or_else(|| Error::InvalidArgument("Message did not carry a file descriptor".into()))
+}
+
+/// Create a non-blocking inotify instance watching the files in `dir` on Linux.
+///
+/// Events are reported for files in the directory that are modified, created,
+/// deleted, or renamed into or out of it.
+pub fn watch_dir(dir: &Path) -> Result<OwnedFd> {
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| Error::InvalidArgument("Path contains a nul byte".into()))?;
+
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd == -1 {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mask = libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+    let result = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) };
+
+    if result == -1 {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(fd)
+    }
+}
+
+/// Drain the pending events of an inotify instance created by `watch_dir` on
+/// Linux, returning true if any of them concerns the file called `name`.
+///
+/// An overflowed event queue is reported as concerning every file.
+pub fn read_watch_events(fd: BorrowedFd<'_>, name: &OsStr) -> Result<bool> {
+    let header = mem::size_of::<libc::inotify_event>();
+    let mut buf = [0u8; 4096];
+    let mut found = false;
+
+    loop {
+        let read = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr() as *mut c_void, buf.len()) };
+        if read == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Ok(found);
+            }
+            return Err(Error::Io(err));
+        }
+
+        let read = read as usize;
+        let mut pos = 0;
+        while pos + header <= read {
+            let event = unsafe { ptr::read_unaligned(buf.as_ptr().add(pos) as *const libc::inotify_event) };
+            let event_name = &buf[pos + header..pos + header + event.len as usize];
+            let event_name = event_name.split(|&byte| byte == 0).next().unwrap_or(&[]);
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 || event_name == name.as_bytes() {
+                found = true;
+            }
+            pos += header + event.len as usize;
+        }
+    }
+}
+
+/// Wait until a file descriptor is readable on Linux.
+///
+/// Returns false if `timeout` expires or the wait is interrupted by a signal.
+pub fn wait_readable(fd: BorrowedFd<'_>, timeout: Option<Duration>) -> Result<bool> {
+    let timeout = match timeout {
+        Some(timeout) => timeout.as_millis().min(c_int::MAX as u128) as c_int,
+        None => -1,
+    };
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    let result = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+
+    if result == -1 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(Error::Io(err));
+    }
+    Ok(result > 0)
+}
+
+/// Map a memfd of `capacity` bytes twice, back to back, on Linux.
+///
+/// `capacity` and `align` must be multiples of the page size (or huge page
+/// size, if `huge_pages` is set). The returned mapping is `2 * capacity` bytes
+/// long, and both halves alias the same memory.
+///
+/// # Safety
+///
+/// This function is unsafe because it creates a memory map whose halves alias
+/// each other.
+pub unsafe fn map_ring(
+    capacity: usize,
+    huge_pages: Option<HugePageSize>,
+    populate: bool,
+    align: usize,
+) -> Result<MmapRaw> {
+    let file = memfd_create("membase-ring", huge_pages, false)?;
+    file.set_len(capacity as u64)?;
+
+    // Reserve an aligned range for both halves
+    let map_len = capacity * 2;
+    let base = reserve_range(map_len, align)? as usize;
+
+    // Map the file over each half of the reservation
+    let mut flags = MAP_SHARED | MAP_FIXED;
+    if populate {
+        flags |= MAP_POPULATE;
+    }
+
+    for half in 0..2 {
+        let addr = mmap(
+            (base + half * capacity) as *mut c_void,
+            capacity,
+            PROT_READ | PROT_WRITE,
+            flags,
+            file.as_raw_fd(),
+            0,
+        );
+
+        if addr == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            munmap(base as *mut c_void, map_len);
+            return Err(Error::Io(err));
+        }
+    }
+
+    // The mappings keep the memfd alive, so the descriptor can be closed here
+    Ok(MmapRaw::new(base as *mut u8, map_len))
+}
+
+/// Reserve `len` bytes of address space on Linux without committing memory.
+///
+/// The range is mapped `PROT_NONE` with `MAP_NORESERVE`, so it costs neither
+/// memory nor commit charge until parts of it are committed with `commit`.
+/// `align` must be a multiple of the page size.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn reserve(len: usize, align: usize, placement: Option<Placement>) -> Result<MmapRaw> {
+    if let Some(placement) = placement {
+        let addr = map_placed(placement, len, PROT_NONE, RESERVE_FLAGS, -1, 0, Some(align))?;
+        if !addr.is_null() {
+            return Ok(MmapRaw::new(addr as *mut u8, len));
+        }
+    }
+
+    let addr = reserve_range(len, align)?;
+    Ok(MmapRaw::new(addr as *mut u8, len))
+}
+
+/// Commit part of a reservation on Linux, making it accessible.
+///
+/// The range is mapped over with `MAP_FIXED`, either anonymously or from
+/// `file` at the given offset. If mapping fails, the range is reserved again so
+/// the reservation stays intact.
+///
+/// # Safety
+///
+/// This function is unsafe because it replaces whatever is mapped in the range,
+/// which must lie within a reservation.
+pub unsafe fn commit(addr: *mut u8, len: usize, file: Option<(&File, u64)>, options: &MmapOptions) -> Result<()> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        huge_pages,
+        copy_on_write,
+        populate,
+        ..
+    } = options;
+    let numa_policy = options.numa();
+
+    // Calculate protection flags
+    let mut prot = PROT_NONE;
+    if readable {
+        prot |= PROT_READ;
+    }
+    if writable {
+        prot |= PROT_WRITE;
+    }
+    if executable {
+        prot |= PROT_EXEC;
+    }
+
+    // Calculate mapping flags
+    let mut flags = MAP_FIXED;
+    let (fd, offset) = match file {
+        Some((file, offset)) => {
+            if let Some(page_size) = huge_pages {
+                check_huge_page_file(file, page_size)?;
+            }
+            flags |= if copy_on_write { MAP_PRIVATE } else { MAP_SHARED };
+            (file.as_raw_fd(), offset as i64)
+        }
+        None => {
+            flags |= MAP_PRIVATE | MAP_ANONYMOUS;
+            if let Some(page_size) = huge_pages {
+                flags |= MAP_HUGETLB;
+                match page_size {
+                    HugePageSize::TwoMB => flags |= MAP_HUGE_2MB,
+                    HugePageSize::OneGB => flags |= MAP_HUGE_1GB,
+                }
+            }
+            (-1, 0)
+        }
+    };
+    if populate && numa_policy.is_none() {
+        flags |= MAP_POPULATE;
+    }
+
+    let result = mmap(addr as *mut c_void, len, prot, flags, fd, offset);
+    if result == libc::MAP_FAILED {
+        let err = io::Error::last_os_error();
+        mmap(addr as *mut c_void, len, PROT_NONE, RESERVE_FLAGS | MAP_FIXED, -1, 0);
+        return Err(Error::Io(err));
+    }
+
+    // Apply NUMA policy if requested
+    if let Some((policy, numa_flags)) = numa_policy {
+        if let Err(err) = apply_numa_policy(result, len, policy, numa_flags) {
+            mmap(addr as *mut c_void, len, PROT_NONE, RESERVE_FLAGS | MAP_FIXED, -1, 0);
+            return Err(err);
+        }
+        if populate {
+            populate_range(result, len, writable && (file.is_none() || copy_on_write));
+        }
+    }
+
+    Ok(())
+}
+
+/// Decommit part of a reservation on Linux.
+///
+/// The range is mapped over with a fresh reservation, which releases its
+/// pages, its commit charge and any file it was mapped from. Changes to shared
+/// file-backed pages have already reached the page cache and are not lost.
+///
+/// # Safety
+///
+/// This function is unsafe because it discards the contents of the range,
+/// which must lie within a reservation.
+pub unsafe fn decommit(addr: *mut u8, len: usize) -> Result<()> {
+    let result = mmap(addr as *mut c_void, len, PROT_NONE, RESERVE_FLAGS | MAP_FIXED, -1, 0);
+    if result == libc::MAP_FAILED {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Flags of the anonymous mapping backing a reservation.
+const RESERVE_FLAGS: c_int = MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE;
+
+/// Check that `file` can back a map with huge pages of `size`, which only
+/// files on a hugetlbfs mounted with that page size can.
+fn check_huge_page_file(file: &File, size: HugePageSize) -> Result<()> {
+    let mut stat: libc::statfs = unsafe { mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) } != 0 {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+
+    if stat.f_type as u32 != libc::HUGETLBFS_MAGIC as u32 {
+        return Err(Error::InvalidArgument(
+            "Huge pages can only back maps of files on hugetlbfs; use HugeFile to create one".into(),
+        ));
+    }
+    if stat.f_bsize as usize != size.bytes() {
+        return Err(Error::InvalidArgument(format!(
+            "File is on a hugetlbfs with {}-byte pages, not {}-byte pages",
+            stat.f_bsize,
+            size.bytes()
+        )));
+    }
+    Ok(())
+}
+
+/// Reserve an inaccessible range of `len` bytes aligned to `align`.
+unsafe fn reserve_range(len: usize, align: usize) -> Result<*mut c_void> {
+    // Reserve enough address space for the range plus alignment slack
+    let extra = align - alignment::page_size();
+    let reservation = mmap(ptr::null_mut(), len + extra, PROT_NONE, RESERVE_FLAGS, -1, 0);
+
+    if reservation == libc::MAP_FAILED {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+
+    // Trim the reservation down to an aligned range
+    let base = alignment::align_up(reservation as usize, align);
+    let prefix_size = base - reservation as usize;
+    if prefix_size > 0 {
+        munmap(reservation, prefix_size);
+    }
+    let suffix_size = extra - prefix_size;
+    if suffix_size > 0 {
+        munmap((base + len) as *mut c_void, suffix_size);
+    }
+
+    Ok(base as *mut c_void)
+}
+
+/// Apply a NUMA policy to a memory mapping with `mbind`.
+///
+/// The kernel silently drops nodes that do not exist or that the thread may
+/// not use, such as node 1 on a single-node machine, as long as one node is
+/// left. Such masks are rejected here instead, with `EINVAL` like the kernel
+/// uses when no node is left.
+unsafe fn apply_numa_policy(addr: *mut c_void, len: usize, policy: &NumaPolicy, flags: NumaFlags) -> Result<()> {
+    let (mode, mask) = match policy {
+        NumaPolicy::Interleave(mask) => (MPOL_INTERLEAVE, mask.clone()),
+        NumaPolicy::Bind(mask) => (MPOL_BIND, mask.clone()),
+        NumaPolicy::Preferred(node) => (MPOL_PREFERRED, NodeMask::single(*node)),
+        NumaPolicy::Local => (MPOL_LOCAL, NodeMask::new()),
+    };
+
+    // The kernel reads the mask as an array of longs, and ignores the last bit
+    // of `maxnode`
+    let mut words: Vec<c_ulong> = Vec::with_capacity(mask.as_words().len() * 2);
+    for &word in mask.as_words() {
+        words.push(word as c_ulong);
+        if c_ulong::BITS < 64 {
+            words.push((word >> 32) as c_ulong);
+        }
+    }
+    if !words.is_empty() {
+        let allowed = allowed_nodes()?;
+        let unavailable = words
+            .iter()
+            .enumerate()
+            .any(|(index, word)| word & !allowed.get(index).copied().unwrap_or(0) != 0);
+        if unavailable {
+            return Err(Error::NumaAllocationFailed(io::Error::from_raw_os_error(libc::EINVAL)));
+        }
+    }
+
+    let (nodemask, maxnode) = match words.len() {
+        0 => (ptr::null(), 0),
+        count => (words.as_ptr(), count as c_ulong * c_ulong::BITS as c_ulong + 1),
+    };
+
+    let mut mbind_flags = 0;
+    if flags.contains(NumaFlags::STRICT) {
+        mbind_flags |= MPOL_MF_STRICT;
+    }
+    if flags.contains(NumaFlags::MOVE) {
+        mbind_flags |= MPOL_MF_MOVE;
+    }
+
+    let result = libc::syscall(libc::SYS_mbind, addr, len, mode, nodemask, maxnode, mbind_flags);
+    if result != 0 {
+        return Err(Error::NumaAllocationFailed(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Return the mask of nodes the calling thread may allocate memory from, as
+/// an array of longs.
+fn allowed_nodes() -> Result<Vec<c_ulong>> {
+    // The mask must have room for every possible node, which is not known up
+    // front, so grow it until it does
+    let mut words: Vec<c_ulong> = vec![0; 16];
+    loop {
+        let maxnode = words.len() as c_ulong * c_ulong::BITS as c_ulong;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_get_mempolicy,
+                ptr::null_mut::<c_int>(),
+                words.as_mut_ptr(),
+                maxnode,
+                ptr::null_mut::<c_void>(),
+                MPOL_F_MEMS_ALLOWED,
+            )
+        };
+        if result == 0 {
+            return Ok(words);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINVAL) || words.len() >= 1 << 12 {
+            return Err(Error::NumaAllocationFailed(err));
+        }
+        words.resize(words.len() * 2, 0);
+    }
+}
+
+/// Prefault a range after its NUMA policy has been applied, as `MAP_POPULATE`
+/// would have faulted the pages in before the policy. Failures are ignored,
+/// as with `MAP_POPULATE`.
+unsafe fn populate_range(addr: *mut c_void, len: usize, write: bool) {
+    let advice = if write { MADV_POPULATE_WRITE } else { MADV_POPULATE_READ };
+    madvise(addr, len, advice);
+}
+
+/// Return the CPU and NUMA node the calling thread is running on.
+///
+/// The thread may be migrated right after the call, so the result is only a
+/// hint unless the thread is pinned.
+pub fn getcpu() -> Result<(u32, u32)> {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    let result = unsafe { libc::syscall(libc::SYS_getcpu, &mut cpu, &mut node, ptr::null_mut::<c_void>()) };
+    if result != 0 {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+    Ok((cpu, node))
+}
+
 /// Get the system page size.
 #[inline]
 fn page_size() -> usize {
     unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send `fds` in a single `SCM_RIGHTS` message.
+    fn send_fds(socket: &UnixStream, fds: &[c_int]) {
+        let mut payload = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut c_void,
+            iov_len: payload.len(),
+        };
+
+        let data_len = mem::size_of_val(fds) as u32;
+        let space = unsafe { libc::CMSG_SPACE(data_len) } as usize;
+        let mut control = vec![0u8; space];
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = space as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut c_int, fds.len());
+            assert_eq!(libc::sendmsg(socket.as_raw_fd(), &msg, 0), 1);
+        }
+    }
+
+    /// Create a pipe with a non-blocking read end.
+    fn pipe() -> (OwnedFd, OwnedFd) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) }, 0);
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }
+    }
+
+    /// Return true if every write end of the pipe has been closed.
+    fn writers_closed(reader: &OwnedFd) -> bool {
+        let mut byte = [0u8; 1];
+        let result = unsafe { libc::read(reader.as_raw_fd(), byte.as_mut_ptr() as *mut c_void, 1) };
+        match result {
+            0 => true,
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock => false,
+            result => panic!("unexpected read result: {}", result),
+        }
+    }
+
+    #[test]
+    fn receive_fd_closes_extra_descriptors() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let (reader, writer) = pipe();
+        send_fds(&sender, &[writer.as_raw_fd(), writer.as_raw_fd()]);
+        drop(writer);
+
+        let received = receive_fd(&receiver).unwrap();
+        assert!(!writers_closed(&reader));
+        drop(received);
+        assert!(writers_closed(&reader));
+    }
+
+    #[test]
+    fn receive_fd_rejects_truncated_control_data() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let (reader, writer) = pipe();
+        send_fds(&sender, &[writer.as_raw_fd(); MAX_RECEIVED_FDS + 4]);
+        drop(writer);
+
+        match receive_fd(&receiver) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(writers_closed(&reader));
+    }
+
+    #[test]
+    fn receive_fd_reports_a_closed_socket() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        drop(sender);
+
+        match receive_fd(&receiver) {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn lock_error_reports_the_limit_only_when_it_is_the_cause() {
+        for errno in [libc::ENOMEM, libc::EAGAIN] {
+            match (lock_error(Some(4096), io::Error::from_raw_os_error(errno)), memlock_limit()) {
+                (Error::MemoryLockLimit { requested, limit }, Some(expected)) => {
+                    assert_eq!(requested, Some(4096));
+                    assert_eq!(limit, expected);
+                }
+                (Error::Io(err), None) => assert_eq!(err.raw_os_error(), Some(errno)),
+                (err, _) => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        // Without privilege and with a limit of 0, locking is not permitted at all
+        match lock_error(Some(4096), io::Error::from_raw_os_error(libc::EPERM)) {
+            Error::Io(err) => assert_eq!(err.kind(), io::ErrorKind::PermissionDenied),
+            err => panic!("unexpected error: {:?}", err),
+        }
+        match lock_error(None, io::Error::from_raw_os_error(libc::EINVAL)) {
+            Error::Io(err) => assert_eq!(err.raw_os_error(), Some(libc::EINVAL)),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    /// Map `len` bytes of anonymous memory anywhere.
+    fn map_anywhere(len: usize) -> *mut c_void {
+        let addr = unsafe { mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0) };
+        assert_ne!(addr, libc::MAP_FAILED);
+        addr
+    }
+
+    /// Return true if any page of the range is mapped.
+    fn is_mapped(addr: *mut c_void, len: usize) -> bool {
+        let mut vec = vec![0u8; len.div_ceil(page_size())];
+        unsafe { libc::mincore(addr, len, vec.as_mut_ptr()) == 0 }
+    }
+
+    #[test]
+    fn misplaced_fixed_maps_are_unmapped() {
+        // Kernels that ignore MAP_FIXED_NOREPLACE place the map elsewhere
+        let len = page_size();
+        let addr = map_anywhere(len);
+        let requested = addr as usize + 64 * len;
+
+        match unsafe { check_placed(Placement::Fixed(requested), addr, len, None) } {
+            Err(Error::AddressInUse { address }) => assert_eq!(address, requested),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(!is_mapped(addr, len));
+    }
+
+    #[test]
+    fn misplaced_hints_are_kept_unless_misaligned() {
+        let len = page_size();
+        let addr = map_anywhere(len);
+        let requested = addr as usize + 64 * len;
+
+        let placed = unsafe { check_placed(Placement::Hint(requested), addr, len, Some(len)) }.unwrap();
+        assert_eq!(placed, addr);
+        assert!(is_mapped(addr, len));
+
+        // An alignment no page-aligned address below it can have
+        let align = (addr as usize + len).next_power_of_two() << 1;
+        let placed = unsafe { check_placed(Placement::Hint(requested), addr, len, Some(align)) }.unwrap();
+        assert!(placed.is_null());
+        assert!(!is_mapped(addr, len));
+    }
+}
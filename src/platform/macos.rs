@@ -1,19 +1,22 @@
 //! macOS-specific memory mapping implementation.
+use std::ffi::CString;
 use std::fs::File;
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
 use std::ptr;
 
 use libc::{
-    c_void, mmap, munmap, msync, madvise, PROT_NONE, PROT_READ, PROT_WRITE, PROT_EXEC,
-    MAP_SHARED, MAP_PRIVATE, MAP_ANON, MS_ASYNC, MS_SYNC,
+    c_int, c_void, mmap, munmap, msync, madvise, mprotect, PROT_NONE, PROT_READ, PROT_WRITE, PROT_EXEC,
+    MAP_SHARED, MAP_PRIVATE, MAP_ANON, MAP_FIXED, MS_ASYNC, MS_SYNC,
     MADV_NORMAL, MADV_RANDOM, MADV_SEQUENTIAL, MADV_WILLNEED, MADV_DONTNEED, MADV_FREE,
 };
 
 use crate::error::{Error, Result};
-use crate::mmap::MmapRaw;
-use crate::advanced::{HugePageSize, NumaPolicy};
-use crate::platform::Advice;
+use crate::mmap::{MmapOptions, MmapRaw, Placement};
+use crate::advanced::HugePageSize;
+use crate::platform::{Advice, Protection};
+use crate::utils::alignment;
 
 /// Map a file into memory on macOS.
 ///
@@ -22,20 +25,18 @@ use crate::platform::Advice;
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and potentially modified, which might lead to undefined behavior if not used
 /// correctly.
-pub unsafe fn map_file(
-    file: &File,
-    offset: u64,
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    _huge_pages: Option<HugePageSize>,
-    _numa_policy: Option<NumaPolicy>,
-    _stack: bool,
-    copy_on_write: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<MmapRaw> {
+pub unsafe fn map_file(file: &File, offset: u64, len: usize, options: &MmapOptions) -> Result<MmapRaw> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        copy_on_write,
+        populate,
+        alignment,
+        placement,
+        ..
+    } = options;
+
     // Calculate protection flags
     let mut prot = PROT_NONE;
     if readable {
@@ -64,9 +65,16 @@ pub unsafe fn map_file(
     let offset_delta = offset - aligned_offset;
     let aligned_len = len + offset_delta as usize;
 
+    // Map at the requested address if any
+    let mut aligned_addr: *mut c_void = match placement {
+        Some(placement) => {
+            map_placed(placement, aligned_len, prot, flags, file.as_raw_fd(), aligned_offset as i64, alignment)?
+        }
+        None => ptr::null_mut(),
+    };
+
     // Apply custom alignment if requested
-    let mut aligned_addr: *mut c_void = ptr::null_mut();
-    if let Some(align) = alignment {
+    if let Some(align) = alignment.filter(|_| aligned_addr.is_null()) {
         if align > page_size {
             // For custom alignment, we'll allocate extra space and then adjust the pointer
             let extra = align - 1;
@@ -139,7 +147,7 @@ pub unsafe fn map_file(
     // Adjust pointer for the offset delta
     let ptr = (addr as usize + offset_delta as usize) as *mut u8;
 
-    Ok(MmapRaw { ptr, len })
+    Ok(MmapRaw::new(ptr, len))
 }
 
 /// Create an anonymous memory map on macOS.
@@ -148,17 +156,17 @@ pub unsafe fn map_file(
 ///
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and modified, which might lead to undefined behavior if not used correctly.
-pub unsafe fn map_anon(
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    _huge_pages: Option<HugePageSize>,
-    _numa_policy: Option<NumaPolicy>,
-    _stack: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<MmapRaw> {
+pub unsafe fn map_anon(len: usize, options: &MmapOptions) -> Result<MmapRaw> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        populate,
+        alignment,
+        placement,
+        ..
+    } = options;
+
     // Calculate protection flags
     let mut prot = PROT_NONE;
     if readable {
@@ -177,11 +185,15 @@ pub unsafe fn map_anon(
     // Note: macOS doesn't support MAP_STACK, MAP_POPULATE, or MAP_HUGETLB
     // We'll silently ignore these options on macOS
 
-    // Apply custom alignment if requested
+    // Map at the requested address if any
     let mut aligned_len = len;
-    let mut aligned_addr: *mut c_void = ptr::null_mut();
+    let mut aligned_addr: *mut c_void = match placement {
+        Some(placement) => map_placed(placement, len, prot, flags, -1, 0, alignment)?,
+        None => ptr::null_mut(),
+    };
     
-    if let Some(align) = alignment {
+    // Apply custom alignment if requested
+    if let Some(align) = alignment.filter(|_| aligned_addr.is_null()) {
         let page_size = page_size();
         if align > page_size {
             // For custom alignment, we'll allocate extra space and then adjust the pointer
@@ -254,7 +266,46 @@ pub unsafe fn map_anon(
         }
     }
 
-    Ok(MmapRaw { ptr: addr as *mut u8, len: aligned_len })
+    Ok(MmapRaw::new(addr as *mut u8, aligned_len))
+}
+
+/// Map `len` bytes at the address requested by `placement`.
+///
+/// macOS has no `MAP_FIXED_NOREPLACE`, so the address is always passed as a
+/// hint, and a fixed placement that the kernel moved elsewhere is unmapped and
+/// reported as `Error::AddressInUse`. Returns null if a hint could not be
+/// honoured with the requested alignment, so the caller should map anywhere.
+unsafe fn map_placed(
+    placement: Placement,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: i64,
+    alignment: Option<usize>,
+) -> Result<*mut c_void> {
+    let requested = placement.address() as *mut c_void;
+    let addr = mmap(requested, len, prot, flags, fd, offset);
+    if addr == libc::MAP_FAILED {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+
+    if addr != requested {
+        let misaligned = alignment.is_some_and(|align| !alignment::is_aligned(addr as usize, align));
+        match placement {
+            Placement::Fixed(address) => {
+                munmap(addr, len);
+                return Err(Error::AddressInUse { address });
+            }
+            Placement::Hint(_) if misaligned => {
+                munmap(addr, len);
+                return Ok(ptr::null_mut());
+            }
+            Placement::Hint(_) => {}
+        }
+    }
+
+    Ok(addr)
 }
 
 /// Flush memory map changes to disk on macOS.
@@ -289,6 +340,267 @@ pub unsafe fn unmap(addr: *mut u8, len: usize) -> Result<()> {
     }
 }
 
+/// Lock a memory range into RAM on macOS.
+///
+/// macOS cannot defer locking until pages are touched, so `on_fault` is
+/// ignored and every page is locked immediately.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn lock(addr: *mut u8, len: usize, on_fault: bool) -> Result<()> {
+    // macOS has no MLOCK_ONFAULT, so pages are always locked immediately
+    let _ = on_fault;
+    let result = libc::mlock(addr as *const c_void, len);
+    
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(lock_error(Some(len), io::Error::last_os_error()))
+    }
+}
+
+/// Unlock a memory range on macOS.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn unlock(addr: *mut u8, len: usize) -> Result<()> {
+    let result = libc::munlock(addr as *const c_void, len);
+    
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Lock all current and/or future memory of the process into RAM on macOS.
+///
+/// `mlockall` is not supported by macOS.
+pub fn lock_all(_current: bool, _future: bool) -> Result<()> {
+    Err(Error::PlatformError(libc::ENOTSUP))
+}
+
+/// Unlock all memory of the process on macOS.
+///
+/// `munlockall` is not supported by macOS.
+pub fn unlock_all() -> Result<()> {
+    Err(Error::PlatformError(libc::ENOTSUP))
+}
+
+/// Return the soft `RLIMIT_MEMLOCK` limit in bytes, or `None` if unlimited.
+pub fn memlock_limit() -> Option<u64> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+
+    if result != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        None
+    } else {
+        Some(limit.rlim_cur as u64)
+    }
+}
+
+/// Build the error for a failed lock, reporting `RLIMIT_MEMLOCK` when the
+/// failure is caused by the limit.
+///
+/// `EPERM` means the process may not lock memory at all, for lack of
+/// privilege while the limit is 0, so it stays a permission error.
+pub(crate) fn lock_error(requested: Option<usize>, err: io::Error) -> Error {
+    match err.raw_os_error() {
+        Some(libc::ENOMEM) | Some(libc::EAGAIN) => match memlock_limit() {
+            Some(limit) => Error::MemoryLockLimit { requested, limit },
+            None => Error::Io(err),
+        },
+        _ => Error::Io(err),
+    }
+}
+
+/// Report which pages of a memory range are resident in RAM on macOS using
+/// `mincore`.
+///
+/// `addr` must be page-aligned. The result holds one entry per page.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn residency(addr: *mut u8, len: usize) -> Result<Vec<bool>> {
+    let page_size = page_size();
+    let pages = len.div_ceil(page_size);
+    let mut vec: Vec<libc::c_char> = vec![0; pages];
+    
+    let result = libc::mincore(addr as *mut c_void, len, vec.as_mut_ptr());
+    
+    if result == 0 {
+        Ok(vec.into_iter().map(|page| page & 1 != 0).collect())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Change the protection of a memory range on macOS using `mprotect`.
+///
+/// # Safety
+///
+/// This function is unsafe because removing access from memory that is still
+/// referenced causes faults on the next access.
+pub unsafe fn protect(addr: *mut u8, len: usize, protection: Protection) -> Result<()> {
+    let prot = match protection {
+        Protection::None => PROT_NONE,
+        Protection::Read => PROT_READ,
+        Protection::ReadWrite => PROT_READ | PROT_WRITE,
+        Protection::ReadExec => PROT_READ | PROT_EXEC,
+        Protection::ReadWriteExec => PROT_READ | PROT_WRITE | PROT_EXEC,
+    };
+    
+    let result = mprotect(addr as *mut c_void, len, prot);
+    
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::ProtectionError(io::Error::last_os_error()))
+    }
+}
+
+/// Resize a memory mapping on macOS.
+///
+/// macOS has no `mremap`, so resizing a live mapping is not supported.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn remap(_addr: *mut u8, _old_len: usize, _new_len: usize) -> Result<*mut u8> {
+    Err(Error::PlatformError(libc::ENOTSUP))
+}
+
+/// Map a ring buffer of `capacity` bytes twice, back to back, on macOS.
+///
+/// Ring buffers are backed by `memfd_create`, which macOS does not provide.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn map_ring(
+    _capacity: usize,
+    _huge_pages: Option<HugePageSize>,
+    _populate: bool,
+    _align: usize,
+) -> Result<MmapRaw> {
+    Err(Error::PlatformError(libc::ENOTSUP))
+}
+
+/// Reserve `len` bytes of address space on macOS without committing memory.
+///
+/// The range is mapped `PROT_NONE`, so it costs no memory until parts of it
+/// are committed with `commit`. `align` must be a multiple of the page size.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn reserve(len: usize, align: usize, placement: Option<Placement>) -> Result<MmapRaw> {
+    if let Some(placement) = placement {
+        let addr = map_placed(placement, len, PROT_NONE, MAP_PRIVATE | MAP_ANON, -1, 0, Some(align))?;
+        if !addr.is_null() {
+            return Ok(MmapRaw::new(addr as *mut u8, len));
+        }
+    }
+
+    // Reserve enough address space for the range plus alignment slack
+    let extra = align - page_size();
+    let reservation = mmap(ptr::null_mut(), len + extra, PROT_NONE, MAP_PRIVATE | MAP_ANON, -1, 0);
+
+    if reservation == libc::MAP_FAILED {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+
+    // Trim the reservation down to an aligned range
+    let base = alignment::align_up(reservation as usize, align);
+    let prefix_size = base - reservation as usize;
+    if prefix_size > 0 {
+        munmap(reservation, prefix_size);
+    }
+    let suffix_size = extra - prefix_size;
+    if suffix_size > 0 {
+        munmap((base + len) as *mut c_void, suffix_size);
+    }
+
+    Ok(MmapRaw::new(base as *mut u8, len))
+}
+
+/// Commit part of a reservation on macOS, making it accessible.
+///
+/// The range is mapped over with `MAP_FIXED`, either anonymously or from
+/// `file` at the given offset. Huge pages and NUMA policies are not supported
+/// on macOS and are ignored.
+///
+/// # Safety
+///
+/// This function is unsafe because it replaces whatever is mapped in the range,
+/// which must lie within a reservation.
+pub unsafe fn commit(addr: *mut u8, len: usize, file: Option<(&File, u64)>, options: &MmapOptions) -> Result<()> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        copy_on_write,
+        populate,
+        ..
+    } = options;
+
+    // Calculate protection flags
+    let mut prot = PROT_NONE;
+    if readable {
+        prot |= PROT_READ;
+    }
+    if writable {
+        prot |= PROT_WRITE;
+    }
+    if executable {
+        prot |= PROT_EXEC;
+    }
+
+    let (flags, fd, offset) = match file {
+        Some((file, offset)) => {
+            let sharing = if copy_on_write { MAP_PRIVATE } else { MAP_SHARED };
+            (sharing | MAP_FIXED, file.as_raw_fd(), offset as i64)
+        }
+        None => (MAP_PRIVATE | MAP_ANON | MAP_FIXED, -1, 0),
+    };
+
+    let result = mmap(addr as *mut c_void, len, prot, flags, fd, offset);
+    if result == libc::MAP_FAILED {
+        let err = io::Error::last_os_error();
+        mmap(addr as *mut c_void, len, PROT_NONE, MAP_PRIVATE | MAP_ANON | MAP_FIXED, -1, 0);
+        return Err(Error::Io(err));
+    }
+
+    // If populate is requested, we can simulate it by touching the pages
+    if populate && readable {
+        for i in (0..len).step_by(page_size()) {
+            ptr::read_volatile(addr.add(i));
+        }
+    }
+
+    Ok(())
+}
+
+/// Decommit part of a reservation on macOS.
+///
+/// `MADV_DONTNEED` does not release memory on macOS, so the range is replaced
+/// with a fresh inaccessible mapping instead.
+///
+/// # Safety
+///
+/// This function is unsafe because it discards the contents of the range,
+/// which must lie within a reservation.
+pub unsafe fn decommit(addr: *mut u8, len: usize) -> Result<()> {
+    let result = mmap(addr as *mut c_void, len, PROT_NONE, MAP_PRIVATE | MAP_ANON | MAP_FIXED, -1, 0);
+    if result == libc::MAP_FAILED {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
 /// Advise the kernel about how the memory map will be accessed on macOS.
 ///
 /// # Safety
@@ -304,6 +616,8 @@ pub unsafe fn advise(addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
         Advice::SequentialOnce => MADV_SEQUENTIAL,  // Updated
         Advice::RandomOnce => MADV_RANDOM,          // Updated
         Advice::Free => MADV_FREE,
+        // macOS has no transparent huge pages, so there is nothing to advise
+        Advice::HugePage | Advice::NoHugePage | Advice::Collapse => return Ok(()),
     };
     
     let result = madvise(addr as *mut c_void, len, advice_flag);
@@ -315,6 +629,78 @@ pub unsafe fn advise(addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
     }
 }
 
+/// Preallocate disk space for a file on macOS using `F_PREALLOCATE`.
+///
+/// Contiguous space is requested first, falling back to any free space.
+pub fn preallocate(file: &File, len: u64) -> Result<()> {
+    let current_len = file.metadata()?.len();
+    if len > current_len {
+        let mut store = libc::fstore_t {
+            fst_flags: libc::F_ALLOCATECONTIG,
+            fst_posmode: libc::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: (len - current_len) as libc::off_t,
+            fst_bytesalloc: 0,
+        };
+
+        let mut result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &store) };
+        if result == -1 {
+            store.fst_flags = libc::F_ALLOCATEALL;
+            result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_PREALLOCATE, &store) };
+        }
+
+        if result == -1 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+    }
+
+    file.set_len(len)?;
+    Ok(())
+}
+
+/// Flush a directory's entries to disk on macOS.
+pub fn sync_dir(path: &Path) -> Result<()> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+/// Open a POSIX shared memory object on macOS using `shm_open`.
+///
+/// `name` must start with a slash and contain no other slashes.
+pub fn shm_open(name: &str, writable: bool, create: bool, exclusive: bool) -> Result<File> {
+    let c_name = CString::new(name).map_err(|_| Error::InvalidArgument("Shared memory name contains a NUL byte".into()))?;
+
+    let mut oflag = if writable { libc::O_RDWR } else { libc::O_RDONLY };
+    oflag |= libc::O_CLOEXEC;
+    if create {
+        oflag |= libc::O_CREAT;
+    }
+    if exclusive {
+        oflag |= libc::O_EXCL;
+    }
+
+    let fd = unsafe { libc::shm_open(c_name.as_ptr(), oflag, 0o600 as libc::c_uint) };
+
+    if fd == -1 {
+        Err(Error::Io(io::Error::last_os_error()))
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+/// Remove a POSIX shared memory object on macOS using `shm_unlink`.
+pub fn shm_unlink(name: &str) -> Result<()> {
+    let c_name = CString::new(name).map_err(|_| Error::InvalidArgument("Shared memory name contains a NUL byte".into()))?;
+
+    let result = unsafe { libc::shm_unlink(c_name.as_ptr()) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
 /// Get the system page size.
 #[inline]
 fn page_size() -> usize {
@@ -4,9 +4,11 @@
 //! operations for Linux, macOS, and Windows.
 
 use std::fs::File;
+use std::path::Path;
 
 use crate::error::Result;
-use crate::advanced::{HugePageSize, NumaPolicy};
+use crate::mmap::{MmapOptions, Placement};
+use crate::advanced::HugePageSize;
 
 /// Memory access advice for the kernel.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -34,6 +36,35 @@ pub enum Advice {
     
     /// Do not make pages eligible for reclamation.
     Free,
+    
+    /// Back the range with transparent huge pages where possible.
+    HugePage,
+    
+    /// Do not back the range with transparent huge pages.
+    NoHugePage,
+    
+    /// Collapse the range into transparent huge pages right away, instead of
+    /// waiting for khugepaged (Linux 6.1 and later).
+    Collapse,
+}
+
+/// Memory protection for a range of a memory map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    /// No access; any access faults.
+    None,
+    
+    /// Read-only access.
+    Read,
+    
+    /// Read and write access.
+    ReadWrite,
+    
+    /// Read and execute access.
+    ReadExec,
+    
+    /// Read, write and execute access.
+    ReadWriteExec,
 }
 
 // Re-export platform-specific implementations
@@ -52,6 +83,9 @@ mod windows;
 #[cfg(windows)]
 pub use self::windows::*;
 
+#[cfg(all(any(target_os = "linux", target_os = "macos"), any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod sigbus;
+
 // Provide a default implementation for unsupported platforms
 #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
 mod unsupported;
@@ -67,31 +101,18 @@ pub use self::unsupported::*;
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and potentially modified, which might lead to undefined behavior if not used
 /// correctly.
-pub unsafe fn map_file(
-    file: &File,
-    offset: u64,
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    huge_pages: Option<HugePageSize>,
-    numa_policy: Option<NumaPolicy>,
-    stack: bool,
-    copy_on_write: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<crate::mmap::MmapRaw> {
+pub unsafe fn map_file(file: &File, offset: u64, len: usize, options: &MmapOptions) -> Result<crate::mmap::MmapRaw> {
     #[cfg(target_os = "linux")]
-    return linux::map_file(file, offset, len, readable, writable, executable, huge_pages, numa_policy, stack, copy_on_write, populate, alignment);
+    return linux::map_file(file, offset, len, options);
     
     #[cfg(target_os = "macos")]
-    return macos::map_file(file, offset, len, readable, writable, executable, huge_pages, numa_policy, stack, copy_on_write, populate, alignment);
+    return macos::map_file(file, offset, len, options);
     
     #[cfg(windows)]
-    return windows::map_file(file, offset, len, readable, writable, executable, huge_pages, numa_policy, stack, copy_on_write, populate, alignment);
+    return windows::map_file(file, offset, len, options);
     
     #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
-    return unsupported::map_file(file, offset, len, readable, writable, executable, huge_pages, numa_policy, stack, copy_on_write, populate, alignment);
+    return unsupported::map_file(file, offset, len, options);
 }
 
 /// Create an anonymous memory map.
@@ -100,28 +121,18 @@ pub unsafe fn map_file(
 ///
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and modified, which might lead to undefined behavior if not used correctly.
-pub unsafe fn map_anon(
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    huge_pages: Option<HugePageSize>,
-    numa_policy: Option<NumaPolicy>,
-    stack: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<crate::mmap::MmapRaw> {
+pub unsafe fn map_anon(len: usize, options: &MmapOptions) -> Result<crate::mmap::MmapRaw> {
     #[cfg(target_os = "linux")]
-    return linux::map_anon(len, readable, writable, executable, huge_pages, numa_policy, stack, populate, alignment);
+    return linux::map_anon(len, options);
     
     #[cfg(target_os = "macos")]
-    return macos::map_anon(len, readable, writable, executable, huge_pages, numa_policy, stack, populate, alignment);
+    return macos::map_anon(len, options);
     
     #[cfg(windows)]
-    return windows::map_anon(len, readable, writable, executable, huge_pages, numa_policy, stack, populate, alignment);
+    return windows::map_anon(len, options);
     
     #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
-    return unsupported::map_anon(len, readable, writable, executable, huge_pages, numa_policy, stack, populate, alignment);
+    return unsupported::map_anon(len, options);
 }
 
 /// Flush memory map changes to disk.
@@ -162,6 +173,260 @@ pub unsafe fn unmap(addr: *mut u8, len: usize) -> Result<()> {
     return unsupported::unmap(addr, len);
 }
 
+/// Map the same memory twice, back to back, for use as a ring buffer.
+///
+/// `capacity` and `align` must be multiples of the page size (or huge page
+/// size, if `huge_pages` is set). The returned mapping is `2 * capacity` bytes
+/// long, and both halves alias the same memory.
+///
+/// # Safety
+///
+/// This function is unsafe because it creates a memory map whose halves alias
+/// each other.
+pub unsafe fn map_ring(
+    capacity: usize,
+    huge_pages: Option<HugePageSize>,
+    populate: bool,
+    align: usize,
+) -> Result<crate::mmap::MmapRaw> {
+    #[cfg(target_os = "linux")]
+    return linux::map_ring(capacity, huge_pages, populate, align);
+    
+    #[cfg(target_os = "macos")]
+    return macos::map_ring(capacity, huge_pages, populate, align);
+    
+    #[cfg(windows)]
+    return windows::map_ring(capacity, huge_pages, populate, align);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::map_ring(capacity, huge_pages, populate, align);
+}
+
+/// Reserve `len` bytes of address space without committing memory.
+///
+/// `align` must be a multiple of the page size. The reserved range is
+/// inaccessible until parts of it are committed with `commit`.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn reserve(len: usize, align: usize, placement: Option<Placement>) -> Result<crate::mmap::MmapRaw> {
+    #[cfg(target_os = "linux")]
+    return linux::reserve(len, align, placement);
+    
+    #[cfg(target_os = "macos")]
+    return macos::reserve(len, align, placement);
+    
+    #[cfg(windows)]
+    return windows::reserve(len, align, placement);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::reserve(len, align, placement);
+}
+
+/// Commit part of a reservation, backing it with anonymous memory or with
+/// `file` at the given offset.
+///
+/// # Safety
+///
+/// This function is unsafe because it replaces whatever is mapped in the range,
+/// which must lie within a reservation.
+pub unsafe fn commit(addr: *mut u8, len: usize, file: Option<(&File, u64)>, options: &MmapOptions) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::commit(addr, len, file, options);
+    
+    #[cfg(target_os = "macos")]
+    return macos::commit(addr, len, file, options);
+    
+    #[cfg(windows)]
+    return windows::commit(addr, len, file, options);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::commit(addr, len, file, options);
+}
+
+/// Decommit part of a reservation, releasing its memory and making it
+/// inaccessible again.
+///
+/// # Safety
+///
+/// This function is unsafe because it discards the contents of the range,
+/// which must lie within a reservation.
+pub unsafe fn decommit(addr: *mut u8, len: usize) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::decommit(addr, len);
+    
+    #[cfg(target_os = "macos")]
+    return macos::decommit(addr, len);
+    
+    #[cfg(windows)]
+    return windows::decommit(addr, len);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::decommit(addr, len);
+}
+
+/// Lock a memory range into RAM.
+///
+/// With `on_fault`, pages are locked as they are first touched instead of
+/// being faulted in immediately, where the platform supports it.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn lock(addr: *mut u8, len: usize, on_fault: bool) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::lock(addr, len, on_fault);
+    
+    #[cfg(target_os = "macos")]
+    return macos::lock(addr, len, on_fault);
+    
+    #[cfg(windows)]
+    return windows::lock(addr, len, on_fault);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::lock(addr, len, on_fault);
+}
+
+/// Unlock a memory range.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn unlock(addr: *mut u8, len: usize) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::unlock(addr, len);
+    
+    #[cfg(target_os = "macos")]
+    return macos::unlock(addr, len);
+    
+    #[cfg(windows)]
+    return windows::unlock(addr, len);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::unlock(addr, len);
+}
+
+/// Lock all current and/or future memory of the process into RAM.
+pub fn lock_all(current: bool, future: bool) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::lock_all(current, future);
+    
+    #[cfg(target_os = "macos")]
+    return macos::lock_all(current, future);
+    
+    #[cfg(windows)]
+    return windows::lock_all(current, future);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::lock_all(current, future);
+}
+
+/// Unlock all memory of the process.
+pub fn unlock_all() -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::unlock_all();
+    
+    #[cfg(target_os = "macos")]
+    return macos::unlock_all();
+    
+    #[cfg(windows)]
+    return windows::unlock_all();
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::unlock_all();
+}
+
+/// Report which pages of a memory range are resident in RAM.
+///
+/// `addr` must be page-aligned. The result holds one entry per page.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn residency(addr: *mut u8, len: usize) -> Result<Vec<bool>> {
+    #[cfg(target_os = "linux")]
+    return linux::residency(addr, len);
+    
+    #[cfg(target_os = "macos")]
+    return macos::residency(addr, len);
+    
+    #[cfg(windows)]
+    return windows::residency(addr, len);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::residency(addr, len);
+}
+
+/// Copy bytes out of a memory map, turning faults caused by a truncated backing
+/// file into `Error::TruncatedMapping`.
+///
+/// The offset in the error is relative to `src`. On Unix architectures
+/// without a recoverable copy routine, faults are not caught.
+///
+/// # Safety
+///
+/// `src` must be valid for reads of `len` bytes apart from truncation faults,
+/// and `dst` must be valid for writes of `len` bytes.
+pub unsafe fn try_copy(src: *const u8, dst: *mut u8, len: usize) -> Result<()> {
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), any(target_arch = "x86_64", target_arch = "aarch64")))]
+    return sigbus::try_copy(src, dst, len);
+    
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), not(any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    {
+        std::ptr::copy_nonoverlapping(src, dst, len);
+        return Ok(());
+    }
+    
+    #[cfg(windows)]
+    return windows::try_copy(src, dst, len);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::try_copy(src, dst, len);
+}
+
+/// Change the protection of a memory range.
+///
+/// # Safety
+///
+/// This function is unsafe because removing access from memory that is still
+/// referenced causes faults on the next access.
+pub unsafe fn protect(addr: *mut u8, len: usize, protection: Protection) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::protect(addr, len, protection);
+    
+    #[cfg(target_os = "macos")]
+    return macos::protect(addr, len, protection);
+    
+    #[cfg(windows)]
+    return windows::protect(addr, len, protection);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::protect(addr, len, protection);
+}
+
+/// Resize a memory mapping, moving it if necessary.
+///
+/// Returns the new start address of the mapping.
+///
+/// # Safety
+///
+/// This function is unsafe because it invalidates every pointer into the old
+/// mapping if the mapping is moved.
+pub unsafe fn remap(addr: *mut u8, old_len: usize, new_len: usize) -> Result<*mut u8> {
+    #[cfg(target_os = "linux")]
+    return linux::remap(addr, old_len, new_len);
+    
+    #[cfg(target_os = "macos")]
+    return macos::remap(addr, old_len, new_len);
+    
+    #[cfg(windows)]
+    return windows::remap(addr, old_len, new_len);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::remap(addr, old_len, new_len);
+}
+
 /// Advise the kernel about how the memory map will be accessed.
 ///
 /// # Safety
@@ -180,3 +445,33 @@ pub unsafe fn advise(addr: *mut u8, len: usize, advice: Advice) -> Result<()> {
     #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
     return unsupported::advise(addr, len, advice);
 }
+
+/// Preallocate disk space for a file and set its length to `len`.
+pub fn preallocate(file: &File, len: u64) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::preallocate(file, len);
+    
+    #[cfg(target_os = "macos")]
+    return macos::preallocate(file, len);
+    
+    #[cfg(windows)]
+    return windows::preallocate(file, len);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::preallocate(file, len);
+}
+
+/// Flush a directory's entries to disk, making files created in it durable.
+pub fn sync_dir(path: &Path) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::sync_dir(path);
+    
+    #[cfg(target_os = "macos")]
+    return macos::sync_dir(path);
+    
+    #[cfg(windows)]
+    return windows::sync_dir(path);
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    return unsupported::sync_dir(path);
+}
@@ -0,0 +1,398 @@
+//! Recovery from SIGBUS faults on truncated file mappings.
+//!
+//! Touching a page of a file mapping that lies entirely past the end of the
+//! file raises SIGBUS. `try_copy` copies with a small assembly routine whose
+//! only load instruction has a fixup label, like the exception tables of the
+//! kernel's user copy routines. A process-wide handler recognises faults at
+//! that instruction while the current thread has a recovery point set,
+//! records where the copy stopped, and resumes at the fixup label, which
+//! makes the routine return early. The mapping itself is never modified.
+//! Faults anywhere else are passed on to the previously installed handler.
+
+use std::arch::global_asm;
+use std::cell::Cell;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+use std::sync::OnceLock;
+
+use libc::{c_int, c_void, siginfo_t};
+
+use crate::error::{Error, Result};
+use crate::utils::alignment;
+
+/// The recovery point of a thread: whether it is inside the copy routine, and
+/// where the last copy stopped.
+struct RecoveryPoint {
+    /// Whether faults in the copy routine should be recovered.
+    active: Cell<bool>,
+
+    /// Source address of the byte the copy stopped at.
+    resume: Cell<usize>,
+
+    /// Address that faulted.
+    fault: Cell<usize>,
+}
+
+thread_local! {
+    static RECOVERY_POINT: RecoveryPoint = const {
+        RecoveryPoint {
+            active: Cell::new(false),
+            resume: Cell::new(0),
+            fault: Cell::new(0),
+        }
+    };
+}
+
+/// The SIGBUS action that was installed before ours, or the errno of a failed
+/// installation.
+static PREVIOUS: OnceLock<std::result::Result<libc::sigaction, i32>> = OnceLock::new();
+
+extern "C" {
+    /// Copy `len` bytes from `src` to `dst` in ascending order, returning 0,
+    /// or 1 if a fault at the load instruction was recovered.
+    fn membase_recoverable_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    /// The only instruction of `membase_recoverable_copy` that reads `src`.
+    static membase_recoverable_copy_load: u8;
+
+    /// Where `membase_recoverable_copy` resumes after a recovered fault.
+    static membase_recoverable_copy_fixup: u8;
+}
+
+// The source register always holds the address of the next byte to read, so
+// that every byte before it has been stored when the load faults.
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+global_asm!(
+    ".pushsection .text.membase_recoverable_copy,\"ax\",@progbits",
+    ".p2align 4",
+    ".globl membase_recoverable_copy",
+    ".globl membase_recoverable_copy_load",
+    ".globl membase_recoverable_copy_fixup",
+    ".hidden membase_recoverable_copy",
+    ".hidden membase_recoverable_copy_load",
+    ".hidden membase_recoverable_copy_fixup",
+    "membase_recoverable_copy:",
+    "    mov rcx, rdx",
+    "membase_recoverable_copy_load:",
+    "    rep movsb",
+    "    xor eax, eax",
+    "    ret",
+    "membase_recoverable_copy_fixup:",
+    "    mov eax, 1",
+    "    ret",
+    ".popsection",
+);
+
+#[cfg(all(target_arch = "x86_64", target_os = "macos"))]
+global_asm!(
+    ".text",
+    ".p2align 4",
+    ".private_extern _membase_recoverable_copy",
+    ".private_extern _membase_recoverable_copy_load",
+    ".private_extern _membase_recoverable_copy_fixup",
+    "_membase_recoverable_copy:",
+    "    mov rcx, rdx",
+    "_membase_recoverable_copy_load:",
+    "    rep movsb",
+    "    xor eax, eax",
+    "    ret",
+    "_membase_recoverable_copy_fixup:",
+    "    mov eax, 1",
+    "    ret",
+);
+
+#[cfg(all(target_arch = "aarch64", target_os = "linux"))]
+global_asm!(
+    ".pushsection .text.membase_recoverable_copy,\"ax\",%progbits",
+    ".p2align 4",
+    ".globl membase_recoverable_copy",
+    ".globl membase_recoverable_copy_load",
+    ".globl membase_recoverable_copy_fixup",
+    ".hidden membase_recoverable_copy",
+    ".hidden membase_recoverable_copy_load",
+    ".hidden membase_recoverable_copy_fixup",
+    "membase_recoverable_copy:",
+    "    cbz x2, 2f",
+    "1:",
+    "membase_recoverable_copy_load:",
+    "    ldrb w3, [x1]",
+    "    strb w3, [x0], #1",
+    "    add x1, x1, #1",
+    "    subs x2, x2, #1",
+    "    b.ne 1b",
+    "2:",
+    "    mov x0, #0",
+    "    ret",
+    "membase_recoverable_copy_fixup:",
+    "    mov x0, #1",
+    "    ret",
+    ".popsection",
+);
+
+#[cfg(all(target_arch = "aarch64", target_os = "macos"))]
+global_asm!(
+    ".text",
+    ".p2align 4",
+    ".private_extern _membase_recoverable_copy",
+    ".private_extern _membase_recoverable_copy_load",
+    ".private_extern _membase_recoverable_copy_fixup",
+    "_membase_recoverable_copy:",
+    "    cbz x2, 2f",
+    "1:",
+    "_membase_recoverable_copy_load:",
+    "    ldrb w3, [x1]",
+    "    strb w3, [x0], #1",
+    "    add x1, x1, #1",
+    "    subs x2, x2, #1",
+    "    b.ne 1b",
+    "2:",
+    "    mov x0, #0",
+    "    ret",
+    "_membase_recoverable_copy_fixup:",
+    "    mov x0, #1",
+    "    ret",
+);
+
+/// Copy `len` bytes from `src` to `dst`, turning SIGBUS faults in the source
+/// into `Error::TruncatedMapping`.
+///
+/// The offset in the error is relative to `src`, and is the start of the first
+/// page that could not be read, or `src` itself if that page is the first.
+/// Every byte of `dst` before the offset holds the source data; the rest is
+/// left unspecified. The source mapping is not modified, so later accesses to
+/// the missing pages fault again.
+///
+/// # Safety
+///
+/// `src` must be valid for reads of `len` bytes apart from SIGBUS faults, and
+/// `dst` must be valid for writes of `len` bytes.
+pub unsafe fn try_copy(src: *const u8, dst: *mut u8, len: usize) -> Result<()> {
+    install_handler()?;
+
+    let start = src as usize;
+    let page_size = alignment::page_size();
+    let mut copied = 0;
+    let mut end = len;
+    let mut truncated = false;
+
+    // A fault stops the copy at the faulting byte, which may lie before the
+    // missing page if the routine reads ahead. Copy again up to the start of
+    // the missing page, which stops for good once no bytes are left.
+    while copied < end {
+        match recoverable_copy(src.add(copied), dst.add(copied), end - copied) {
+            None => copied = end,
+            Some((resume, fault)) => {
+                truncated = true;
+                copied = resume.clamp(start + copied, start + end) - start;
+                let missing = alignment::align_down(fault, page_size).clamp(start, start + end) - start;
+                end = missing.max(copied);
+            }
+        }
+    }
+
+    if truncated {
+        Err(Error::TruncatedMapping { offset: end })
+    } else {
+        Ok(())
+    }
+}
+
+/// Run the copy routine with the recovery point of this thread set, returning
+/// where it stopped and the faulting address if a fault was recovered.
+unsafe fn recoverable_copy(src: *const u8, dst: *mut u8, len: usize) -> Option<(usize, usize)> {
+    RECOVERY_POINT.with(|point| point.active.set(true));
+    compiler_fence(Ordering::SeqCst);
+
+    let faulted = membase_recoverable_copy(dst, src, len) != 0;
+
+    compiler_fence(Ordering::SeqCst);
+    RECOVERY_POINT.with(|point| {
+        point.active.set(false);
+        faulted.then(|| (point.resume.get(), point.fault.get()))
+    })
+}
+
+/// Install the SIGBUS handler, once per process.
+fn install_handler() -> Result<()> {
+    let previous = PREVIOUS.get_or_init(|| unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_sigbus as extern "C" fn(c_int, *mut siginfo_t, *mut c_void) as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut previous: libc::sigaction = mem::zeroed();
+        if libc::sigaction(libc::SIGBUS, &action, &mut previous) == 0 {
+            Ok(previous)
+        } else {
+            Err(io::Error::last_os_error().raw_os_error().unwrap_or(0))
+        }
+    });
+
+    match previous {
+        Ok(_) => Ok(()),
+        Err(errno) => Err(Error::Io(io::Error::from_raw_os_error(*errno))),
+    }
+}
+
+/// The process-wide SIGBUS handler.
+extern "C" fn handle_sigbus(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let registers = unsafe { Registers::of(context) };
+    let load = ptr::addr_of!(membase_recoverable_copy_load) as usize;
+    let fixup = ptr::addr_of!(membase_recoverable_copy_fixup) as usize;
+
+    let recovered = RECOVERY_POINT
+        .try_with(|point| {
+            if !point.active.get() || registers.pc() != load {
+                return false;
+            }
+
+            // Returning from the handler resumes at the fixup label, so the
+            // faulting load is never retried
+            point.resume.set(registers.source());
+            point.fault.set(unsafe { fault_address(info) });
+            registers.set_pc(fixup);
+            true
+        })
+        .unwrap_or(false);
+
+    if !recovered {
+        unsafe { forward(signal, info, context) };
+    }
+}
+
+/// Pass a fault we cannot recover from on to the previous handler.
+unsafe fn forward(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    match PREVIOUS.get() {
+        Some(Ok(previous))
+            if previous.sa_sigaction != libc::SIG_DFL && previous.sa_sigaction != libc::SIG_IGN =>
+        {
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) =
+                    mem::transmute(previous.sa_sigaction);
+                handler(signal, info, context);
+            } else {
+                let handler: extern "C" fn(c_int) = mem::transmute(previous.sa_sigaction);
+                handler(signal);
+            }
+        }
+        _ => {
+            // Restore the default action and raise the signal again, so the
+            // process terminates with SIGBUS once the handler returns
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = libc::SIG_DFL;
+            libc::sigaction(signal, &action, ptr::null_mut());
+            libc::raise(signal);
+        }
+    }
+}
+
+/// The saved registers of the interrupted thread.
+struct Registers(*mut libc::ucontext_t);
+
+impl Registers {
+    /// Wrap the context passed to a signal handler.
+    #[inline]
+    unsafe fn of(context: *mut c_void) -> Registers {
+        Registers(context as *mut libc::ucontext_t)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+impl Registers {
+    /// Return the program counter.
+    #[inline]
+    fn pc(&self) -> usize {
+        unsafe { (*self.0).uc_mcontext.gregs[libc::REG_RIP as usize] as usize }
+    }
+
+    /// Set the program counter the thread resumes at.
+    #[inline]
+    fn set_pc(&self, pc: usize) {
+        unsafe { (*self.0).uc_mcontext.gregs[libc::REG_RIP as usize] = pc as i64 };
+    }
+
+    /// Return the source pointer of the copy routine.
+    #[inline]
+    fn source(&self) -> usize {
+        unsafe { (*self.0).uc_mcontext.gregs[libc::REG_RSI as usize] as usize }
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_os = "linux"))]
+impl Registers {
+    /// Return the program counter.
+    #[inline]
+    fn pc(&self) -> usize {
+        unsafe { (*self.0).uc_mcontext.pc as usize }
+    }
+
+    /// Set the program counter the thread resumes at.
+    #[inline]
+    fn set_pc(&self, pc: usize) {
+        unsafe { (*self.0).uc_mcontext.pc = pc as u64 };
+    }
+
+    /// Return the source pointer of the copy routine.
+    #[inline]
+    fn source(&self) -> usize {
+        unsafe { (*self.0).uc_mcontext.regs[1] as usize }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "macos"))]
+impl Registers {
+    /// Return the program counter.
+    #[inline]
+    fn pc(&self) -> usize {
+        unsafe { (*(*self.0).uc_mcontext).__ss.__rip as usize }
+    }
+
+    /// Set the program counter the thread resumes at.
+    #[inline]
+    fn set_pc(&self, pc: usize) {
+        unsafe { (*(*self.0).uc_mcontext).__ss.__rip = pc as u64 };
+    }
+
+    /// Return the source pointer of the copy routine.
+    #[inline]
+    fn source(&self) -> usize {
+        unsafe { (*(*self.0).uc_mcontext).__ss.__rsi as usize }
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_os = "macos"))]
+impl Registers {
+    /// Return the program counter.
+    #[inline]
+    fn pc(&self) -> usize {
+        unsafe { (*(*self.0).uc_mcontext).__ss.__pc as usize }
+    }
+
+    /// Set the program counter the thread resumes at.
+    #[inline]
+    fn set_pc(&self, pc: usize) {
+        unsafe { (*(*self.0).uc_mcontext).__ss.__pc = pc as u64 };
+    }
+
+    /// Return the source pointer of the copy routine.
+    #[inline]
+    fn source(&self) -> usize {
+        unsafe { (*(*self.0).uc_mcontext).__ss.__x[1] as usize }
+    }
+}
+
+/// Return the faulting address of a signal.
+#[cfg(target_os = "linux")]
+#[inline]
+unsafe fn fault_address(info: *mut siginfo_t) -> usize {
+    (*info).si_addr() as usize
+}
+
+/// Return the faulting address of a signal.
+#[cfg(target_os = "macos")]
+#[inline]
+unsafe fn fault_address(info: *mut siginfo_t) -> usize {
+    (*info).si_addr as usize
+}
@@ -3,15 +3,17 @@
 use std::fs::File;
 use std::io;
 use std::os::windows::io::AsRawHandle;
+use std::path::Path;
 use std::ptr;
 
 use winapi::um::memoryapi::{
-    CreateFileMappingW, MapViewOfFileEx, FlushViewOfFile, UnmapViewOfFile,
+    CreateFileMappingW, MapViewOfFileEx, FlushViewOfFile, UnmapViewOfFile, VirtualProtect,
+    VirtualLock, VirtualUnlock, VirtualFree,
     FILE_MAP_READ, FILE_MAP_WRITE, FILE_MAP_EXECUTE, FILE_MAP_COPY,
 };
 use winapi::um::winnt::{
-    PAGE_READONLY, PAGE_READWRITE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
-    SEC_COMMIT, SEC_RESERVE, SEC_LARGE_PAGES, MEM_COMMIT, MEM_RESERVE,
+    PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
+    SEC_COMMIT, SEC_RESERVE, SEC_LARGE_PAGES, MEM_COMMIT, MEM_RESERVE, MEM_DECOMMIT,
 };
 use winapi::um::handleapi::CloseHandle;
 use winapi::um::sysinfoapi::GetSystemInfo;
@@ -20,9 +22,9 @@ use winapi::shared::minwindef::{DWORD, LPVOID};
 use winapi::shared::basetsd::SIZE_T;
 
 use crate::error::{Error, Result};
-use crate::mmap::MmapRaw;
-use crate::advanced::{HugePageSize, NumaPolicy};
-use crate::platform::Advice;
+use crate::mmap::{MmapOptions, MmapRaw, Placement};
+use crate::advanced::HugePageSize;
+use crate::platform::{Advice, Protection};
 use crate::utils::alignment;
 
 /// Map a file into memory on Windows.
@@ -32,20 +34,21 @@ use crate::utils::alignment;
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and potentially modified, which might lead to undefined behavior if not used
 /// correctly.
-pub unsafe fn map_file(
-    file: &File,
-    offset: u64,
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    huge_pages: Option<HugePageSize>,
-    numa_policy: Option<NumaPolicy>,
-    stack: bool,
-    copy_on_write: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<MmapRaw> {
+pub unsafe fn map_file(file: &File, offset: u64, len: usize, options: &MmapOptions) -> Result<MmapRaw> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        huge_pages,
+        stack,
+        copy_on_write,
+        populate,
+        alignment,
+        placement,
+        ..
+    } = options;
+    let numa_policy = options.numa();
+
     // Calculate protection flags
     let page_protection = if executable {
         if writable {
@@ -87,6 +90,7 @@ pub unsafe fn map_file(
     let aligned_offset = offset & !(page_size - 1);
     let offset_delta = offset - aligned_offset;
     let aligned_len = len + offset_delta as usize;
+    let aligned_addr = placement_addr(placement, &system_info)?;
 
     // Calculate maximum size for the file mapping
     let maximum_size_high = ((aligned_offset + aligned_len as u64) >> 32) as DWORD;
@@ -111,7 +115,6 @@ pub unsafe fn map_file(
     let offset_low = (aligned_offset & 0xFFFFFFFF) as DWORD;
 
     // Apply custom alignment if requested
-    let mut aligned_addr: LPVOID = ptr::null_mut();
     if let Some(align) = alignment {
         if align > page_size as usize {
             // Windows doesn't provide direct support for custom alignment in MapViewOfFileEx
@@ -128,7 +131,7 @@ pub unsafe fn map_file(
     }
 
     // Map view of file
-    let addr = MapViewOfFileEx(
+    let mut addr = MapViewOfFileEx(
         file_mapping,
         desired_access,
         offset_high,
@@ -137,11 +140,24 @@ pub unsafe fn map_file(
         aligned_addr,
     );
 
+    // Fall back to any address if a hinted one is unavailable
+    if addr.is_null() && matches!(placement, Some(Placement::Hint(_))) {
+        addr = MapViewOfFileEx(
+            file_mapping,
+            desired_access,
+            offset_high,
+            offset_low,
+            aligned_len as SIZE_T,
+            ptr::null_mut(),
+        );
+    }
+    let err = io::Error::last_os_error();
+
     // Close file mapping handle (the view will remain valid)
     CloseHandle(file_mapping);
 
     if addr.is_null() {
-        return Err(Error::Io(io::Error::last_os_error()));
+        return Err(placement_error(placement, err));
     }
 
     // NUMA is not directly supported through this API on Windows
@@ -158,7 +174,7 @@ pub unsafe fn map_file(
     // Adjust pointer for the offset delta
     let ptr = (addr as usize + offset_delta as usize) as *mut u8;
 
-    Ok(MmapRaw { ptr, len })
+    Ok(MmapRaw::new(ptr, len))
 }
 
 /// Create an anonymous memory map on Windows.
@@ -167,17 +183,20 @@ pub unsafe fn map_file(
 ///
 /// This function is unsafe because it creates a memory map that can be accessed
 /// and modified, which might lead to undefined behavior if not used correctly.
-pub unsafe fn map_anon(
-    len: usize,
-    readable: bool,
-    writable: bool,
-    executable: bool,
-    huge_pages: Option<HugePageSize>,
-    numa_policy: Option<NumaPolicy>,
-    stack: bool,
-    populate: bool,
-    alignment: Option<usize>,
-) -> Result<MmapRaw> {
+pub unsafe fn map_anon(len: usize, options: &MmapOptions) -> Result<MmapRaw> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        huge_pages,
+        stack,
+        populate,
+        alignment,
+        placement,
+        ..
+    } = options;
+    let numa_policy = options.numa();
+
     // Calculate protection flags
     let page_protection = if executable {
         if writable {
@@ -203,7 +222,7 @@ pub unsafe fn map_anon(
 
     // Apply custom alignment if requested
     let mut aligned_len = len;
-    let mut aligned_addr: LPVOID = ptr::null_mut();
+    let aligned_addr = placement_addr(placement, &system_info)?;
     
     if let Some(align) = alignment {
         if align > page_size {
@@ -225,15 +244,25 @@ pub unsafe fn map_anon(
     }
 
     // Allocate memory
-    let addr = VirtualAlloc(
+    let mut addr = VirtualAlloc(
         aligned_addr,
         aligned_len as SIZE_T,
         allocation_type,
         page_protection,
     );
 
+    // Fall back to any address if a hinted one is unavailable
+    if addr.is_null() && matches!(placement, Some(Placement::Hint(_))) {
+        addr = VirtualAlloc(
+            ptr::null_mut(),
+            aligned_len as SIZE_T,
+            allocation_type,
+            page_protection,
+        );
+    }
+
     if addr.is_null() {
-        return Err(Error::Io(io::Error::last_os_error()));
+        return Err(placement_error(placement, io::Error::last_os_error()));
     }
 
     // NUMA is not directly supported through this API on Windows
@@ -247,7 +276,7 @@ pub unsafe fn map_anon(
         }
     }
 
-    Ok(MmapRaw { ptr: addr as *mut u8, len: aligned_len })
+    Ok(MmapRaw::new(addr as *mut u8, aligned_len))
 }
 
 /// Flush memory map changes to disk on Windows.
@@ -283,6 +312,235 @@ pub unsafe fn unmap(addr: *mut u8, _len: usize) -> Result<()> {
     }
 }
 
+/// Lock a memory range into RAM on Windows using `VirtualLock`.
+///
+/// Windows cannot defer locking until pages are touched, so `on_fault` is
+/// ignored. The amount of lockable memory is bounded by the process working
+/// set size rather than `RLIMIT_MEMLOCK`.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn lock(addr: *mut u8, len: usize, _on_fault: bool) -> Result<()> {
+    let result = VirtualLock(addr as LPVOID, len as SIZE_T);
+    
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Unlock a memory range on Windows using `VirtualUnlock`.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn unlock(addr: *mut u8, len: usize) -> Result<()> {
+    let result = VirtualUnlock(addr as LPVOID, len as SIZE_T);
+    
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+/// Build the error for a failed lock on Windows.
+///
+/// Windows bounds locked memory by the working set size rather than a limit
+/// that can be reported, so the OS error is passed through.
+pub(crate) fn lock_error(_requested: Option<usize>, err: io::Error) -> Error {
+    Error::Io(err)
+}
+
+/// Lock all current and/or future memory of the process into RAM on Windows.
+///
+/// Windows has no equivalent of `mlockall`.
+pub fn lock_all(_current: bool, _future: bool) -> Result<()> {
+    Err(Error::PlatformError(winapi::shared::winerror::ERROR_NOT_SUPPORTED as i32))
+}
+
+/// Unlock all memory of the process on Windows.
+///
+/// Windows has no equivalent of `munlockall`.
+pub fn unlock_all() -> Result<()> {
+    Err(Error::PlatformError(winapi::shared::winerror::ERROR_NOT_SUPPORTED as i32))
+}
+
+/// Report which pages of a memory range are resident in RAM on Windows.
+///
+/// Not supported yet.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn residency(_addr: *mut u8, _len: usize) -> Result<Vec<bool>> {
+    Err(Error::PlatformError(winapi::shared::winerror::ERROR_NOT_SUPPORTED as i32))
+}
+
+/// Copy bytes out of a memory map on Windows.
+///
+/// Windows refuses to truncate a file while a view of it is mapped, so the
+/// copy cannot fault and is performed directly.
+///
+/// # Safety
+///
+/// `src` must be valid for reads and `dst` valid for writes of `len` bytes.
+pub unsafe fn try_copy(src: *const u8, dst: *mut u8, len: usize) -> Result<()> {
+    ptr::copy_nonoverlapping(src, dst, len);
+    Ok(())
+}
+
+/// Change the protection of a memory range on Windows using `VirtualProtect`.
+///
+/// # Safety
+///
+/// This function is unsafe because removing access from memory that is still
+/// referenced causes faults on the next access.
+pub unsafe fn protect(addr: *mut u8, len: usize, protection: Protection) -> Result<()> {
+    let new_protection = match protection {
+        Protection::None => PAGE_NOACCESS,
+        Protection::Read => PAGE_READONLY,
+        Protection::ReadWrite => PAGE_READWRITE,
+        Protection::ReadExec => PAGE_EXECUTE_READ,
+        Protection::ReadWriteExec => PAGE_EXECUTE_READWRITE,
+    };
+    
+    let mut old_protection: DWORD = 0;
+    let result = VirtualProtect(addr as LPVOID, len as SIZE_T, new_protection, &mut old_protection);
+    
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(Error::ProtectionError(io::Error::last_os_error()))
+    }
+}
+
+/// Resize a memory mapping on Windows.
+///
+/// Views of a file mapping cannot be resized in place on Windows.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn remap(_addr: *mut u8, _old_len: usize, _new_len: usize) -> Result<*mut u8> {
+    Err(Error::PlatformError(winapi::shared::winerror::ERROR_NOT_SUPPORTED as i32))
+}
+
+/// Map a ring buffer of `capacity` bytes twice, back to back, on Windows.
+///
+/// Placing two views at adjacent addresses requires placeholder support that
+/// this crate does not use, so ring buffers are not supported on Windows.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn map_ring(
+    _capacity: usize,
+    _huge_pages: Option<HugePageSize>,
+    _populate: bool,
+    _align: usize,
+) -> Result<MmapRaw> {
+    Err(Error::PlatformError(winapi::shared::winerror::ERROR_NOT_SUPPORTED as i32))
+}
+
+/// Reserve `len` bytes of address space on Windows without committing memory.
+///
+/// `VirtualAlloc` only reserves on allocation granularity boundaries, so
+/// alignments beyond that are not supported.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory.
+pub unsafe fn reserve(len: usize, align: usize, placement: Option<Placement>) -> Result<MmapRaw> {
+    let system_info = get_system_info();
+    if align > system_info.dwAllocationGranularity as usize {
+        return Err(Error::InvalidArgument("Custom alignment beyond allocation granularity is not supported on Windows".into()));
+    }
+
+    let aligned_addr = placement_addr(placement, &system_info)?;
+    let mut addr = VirtualAlloc(aligned_addr, len as SIZE_T, MEM_RESERVE, PAGE_NOACCESS);
+
+    // Fall back to any address if a hinted one is unavailable
+    if addr.is_null() && matches!(placement, Some(Placement::Hint(_))) {
+        addr = VirtualAlloc(ptr::null_mut(), len as SIZE_T, MEM_RESERVE, PAGE_NOACCESS);
+    }
+
+    if addr.is_null() {
+        return Err(placement_error(placement, io::Error::last_os_error()));
+    }
+
+    Ok(MmapRaw::new(addr as *mut u8, len))
+}
+
+/// Commit part of a reservation on Windows, making it accessible.
+///
+/// Mapping files into a reservation requires placeholder support, and large
+/// pages must be committed together with their reservation, so neither is
+/// supported. NUMA policies are ignored.
+///
+/// # Safety
+///
+/// This function is unsafe because it operates on raw memory, which must lie
+/// within a reservation.
+pub unsafe fn commit(addr: *mut u8, len: usize, file: Option<(&File, u64)>, options: &MmapOptions) -> Result<()> {
+    let &MmapOptions {
+        readable,
+        writable,
+        executable,
+        huge_pages,
+        populate,
+        ..
+    } = options;
+
+    if file.is_some() || huge_pages.is_some() {
+        return Err(Error::PlatformError(winapi::shared::winerror::ERROR_NOT_SUPPORTED as i32));
+    }
+
+    // Calculate protection flags
+    let page_protection = if executable {
+        if writable {
+            PAGE_EXECUTE_READWRITE
+        } else {
+            PAGE_EXECUTE_READ
+        }
+    } else if writable {
+        PAGE_READWRITE
+    } else if readable {
+        PAGE_READONLY
+    } else {
+        PAGE_NOACCESS
+    };
+
+    if VirtualAlloc(addr as LPVOID, len as SIZE_T, MEM_COMMIT, page_protection).is_null() {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+
+    // If populate is requested, we can simulate it by touching the pages
+    if populate && readable {
+        let page_size = get_system_info().dwPageSize as usize;
+        for i in (0..len).step_by(page_size) {
+            ptr::read_volatile(addr.add(i));
+        }
+    }
+
+    Ok(())
+}
+
+/// Decommit part of a reservation on Windows with `MEM_DECOMMIT`.
+///
+/// # Safety
+///
+/// This function is unsafe because it discards the contents of the range,
+/// which must lie within a reservation.
+pub unsafe fn decommit(addr: *mut u8, len: usize) -> Result<()> {
+    if VirtualFree(addr as LPVOID, len as SIZE_T, MEM_DECOMMIT) == 0 {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
 /// Advise the kernel about how the memory map will be accessed on Windows.
 ///
 /// # Safety
@@ -294,6 +552,53 @@ pub unsafe fn advise(_addr: *mut u8, _len: usize, _advice: Advice) -> Result<()>
     Ok(())
 }
 
+/// Preallocate disk space for a file on Windows.
+///
+/// Setting the end of file on NTFS allocates the space without making the file
+/// sparse.
+pub fn preallocate(file: &File, len: u64) -> Result<()> {
+    file.set_len(len)?;
+    Ok(())
+}
+
+/// Flush a directory's entries to disk on Windows.
+///
+/// Windows does not support flushing directory handles, and NTFS journals
+/// directory updates itself, so this is a no-op.
+pub fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Return the address requested by `placement`, or null for any address.
+///
+/// Views and allocations must start on an allocation granularity boundary,
+/// which is usually larger than the page size.
+fn placement_addr(placement: Option<Placement>, system_info: &winapi::um::sysinfoapi::SYSTEM_INFO) -> Result<LPVOID> {
+    let address = match placement {
+        Some(placement) => placement.address(),
+        None => return Ok(ptr::null_mut()),
+    };
+
+    let granularity = system_info.dwAllocationGranularity as usize;
+    if address % granularity != 0 {
+        return Err(Error::UnalignedAddress { address, alignment: granularity });
+    }
+    Ok(address as LPVOID)
+}
+
+/// Convert the error of a failed mapping, reporting an occupied fixed address
+/// as `Error::AddressInUse`.
+fn placement_error(placement: Option<Placement>, err: io::Error) -> Error {
+    match placement {
+        Some(Placement::Fixed(address))
+            if err.raw_os_error() == Some(winapi::shared::winerror::ERROR_INVALID_ADDRESS as i32) =>
+        {
+            Error::AddressInUse { address }
+        }
+        _ => Error::Io(err),
+    }
+}
+
 /// Get system information.
 #[inline]
 fn get_system_info() -> winapi::um::sysinfoapi::SYSTEM_INFO {
@@ -0,0 +1,28 @@
+//! Plain-old-data types for typed memory map access.
+//!
+//! This module provides the `Pod` marker trait, which identifies types that can
+//! be safely reinterpreted from the raw bytes of a memory map.
+
+/// A marker trait for plain-old-data types.
+///
+/// Types implementing `Pod` can be read from and written to a memory map as
+/// raw bytes without any validation.
+///
+/// # Safety
+///
+/// Implementors must guarantee that:
+///
+/// - every bit pattern is a valid value of the type,
+/// - the type contains no padding bytes,
+/// - the type contains no pointers or references.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
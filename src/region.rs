@@ -0,0 +1,299 @@
+//! Disjoint mutable regions of a memory map.
+//!
+//! This module provides `MmapRegion`, an owned handle to a sub-range of a
+//! writable memory map. Regions created from the same map never overlap, so
+//! they can be handed to different threads and written in parallel.
+
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::mmap::{check_range, MmapRaw};
+use crate::platform;
+use crate::utils::alignment;
+
+/// An owned, mutable region of a memory map.
+///
+/// The underlying mapping stays alive until every region created from it has
+/// been dropped.
+#[derive(Debug)]
+pub struct MmapRegion {
+    /// The memory map this region belongs to.
+    map: Arc<MmapRaw>,
+
+    /// Offset of the region within the memory map.
+    offset: usize,
+
+    /// Length of the region.
+    len: usize,
+}
+
+// Safety: regions created from the same map never overlap, and mutation of a
+// region's bytes requires `&mut` access to that region.
+unsafe impl Send for MmapRegion {}
+unsafe impl Sync for MmapRegion {}
+
+impl MmapRegion {
+    /// Create a region covering the whole memory map.
+    #[inline]
+    pub(crate) fn new(map: MmapRaw) -> MmapRegion {
+        let len = map.len;
+        MmapRegion {
+            map: Arc::new(map),
+            offset: 0,
+            len,
+        }
+    }
+
+    /// Return the length of the region.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if the region is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the offset of the region within its memory map.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Return a pointer to the region.
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.start()
+    }
+
+    /// Return a mutable pointer to the region.
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.start()
+    }
+
+    /// Split the region into two at byte `mid`.
+    pub fn into_split_at(self, mid: usize) -> Result<(MmapRegion, MmapRegion)> {
+        if mid > self.len {
+            return Err(Error::InvalidArgument(format!(
+                "Split point {} is out of bounds for a region of length {}",
+                mid, self.len
+            )));
+        }
+
+        let second = MmapRegion {
+            map: Arc::clone(&self.map),
+            offset: self.offset + mid,
+            len: self.len - mid,
+        };
+        let first = MmapRegion {
+            map: self.map,
+            offset: self.offset,
+            len: mid,
+        };
+
+        Ok((first, second))
+    }
+
+    /// Split the region into regions of `chunk_size` bytes.
+    ///
+    /// The last region is shorter if the length of the region is not a
+    /// multiple of `chunk_size`.
+    pub fn into_chunks(self, chunk_size: usize) -> Result<Vec<MmapRegion>> {
+        if chunk_size == 0 {
+            return Err(Error::InvalidArgument("Chunk size must be non-zero".into()));
+        }
+
+        let mut regions = Vec::with_capacity(self.len.div_ceil(chunk_size));
+        let mut offset = 0;
+        while offset < self.len {
+            let len = chunk_size.min(self.len - offset);
+            regions.push(MmapRegion {
+                map: Arc::clone(&self.map),
+                offset: self.offset + offset,
+                len,
+            });
+            offset += len;
+        }
+
+        Ok(regions)
+    }
+
+    /// Split the region into exactly `count` regions of nearly equal size.
+    ///
+    /// Boundaries between regions fall on page boundaries, so no two regions
+    /// share a page, and the sizes of the regions differ by at most one page.
+    /// If the region spans fewer than `count` pages, the last regions are
+    /// empty.
+    pub fn split_into(self, count: usize) -> Result<Vec<MmapRegion>> {
+        if count == 0 {
+            return Err(Error::InvalidArgument("Region count must be non-zero".into()));
+        }
+
+        // Distribute whole pages, counted from the page holding the start of
+        // the region, evenly over the regions
+        let page_size = alignment::page_size();
+        let start = self.start() as usize;
+        let base = alignment::align_down(start, page_size);
+        let pages = (start + self.len - base).div_ceil(page_size);
+        let boundary = |index: usize| {
+            let address = base + pages * index / count * page_size;
+            address.clamp(start, start + self.len) - start
+        };
+
+        let mut regions = Vec::with_capacity(count);
+        for index in 0..count {
+            let (first, last) = (boundary(index), boundary(index + 1));
+            regions.push(MmapRegion {
+                map: Arc::clone(&self.map),
+                offset: self.offset + first,
+                len: last - first,
+            });
+        }
+
+        Ok(regions)
+    }
+
+    /// Flush the region to disk.
+    #[inline]
+    pub fn flush(&self) -> Result<()> {
+        self.flush_range(0, self.len)
+    }
+
+    /// Flush the region to disk asynchronously.
+    #[inline]
+    pub fn flush_async(&self) -> Result<()> {
+        self.flush_async_range(0, self.len)
+    }
+
+    /// Flush a range of the region to disk.
+    ///
+    /// The range is widened to page boundaries, so bytes of neighbouring
+    /// regions that share a page are flushed as well.
+    #[inline]
+    pub fn flush_range(&self, offset: usize, len: usize) -> Result<()> {
+        check_range(offset, len, self.len)?;
+        let (addr, len) = self.map.page_range(self.offset + offset, len)?;
+        unsafe { self.map.backend().flush(addr, len, false, &self.map.state) }
+    }
+
+    /// Flush a range of the region to disk asynchronously.
+    ///
+    /// The range is widened to page boundaries, so bytes of neighbouring
+    /// regions that share a page are flushed as well.
+    #[inline]
+    pub fn flush_async_range(&self, offset: usize, len: usize) -> Result<()> {
+        check_range(offset, len, self.len)?;
+        let (addr, len) = self.map.page_range(self.offset + offset, len)?;
+        unsafe { self.map.backend().flush(addr, len, true, &self.map.state) }
+    }
+
+    /// Advise the kernel about how the region will be accessed.
+    #[inline]
+    pub fn advise(&self, advice: platform::Advice) -> Result<()> {
+        self.advise_range(0, self.len, advice)
+    }
+
+    /// Advise the kernel about how a range of the region will be accessed.
+    ///
+    /// Unlike flushing, advice only applies to pages that lie entirely within
+    /// the region. Advice such as `DontNeed` discards page contents, so it must
+    /// never reach a page shared with a neighbouring region.
+    pub fn advise_range(&self, offset: usize, len: usize, advice: platform::Advice) -> Result<()> {
+        check_range(offset, len, self.len)?;
+
+        let page_size = alignment::page_size();
+        let start = self.start() as usize + offset;
+        let aligned_start = alignment::align_up(start, page_size);
+        let aligned_end = alignment::align_down(start + len, page_size);
+        if aligned_end <= aligned_start {
+            return Ok(());
+        }
+
+        unsafe { self.map.backend().advise(aligned_start as *mut u8, aligned_end - aligned_start, advice) }
+    }
+
+    /// Return a pointer to the first byte of the region.
+    #[inline]
+    fn start(&self) -> *mut u8 {
+        unsafe { self.map.ptr.add(self.offset) }
+    }
+}
+
+impl Deref for MmapRegion {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.start(), self.len) }
+    }
+}
+
+impl DerefMut for MmapRegion {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.start(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for MmapRegion {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for MmapRegion {
+    #[inline]
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mmap::{test_lock, MmapOptions};
+    use crate::utils::alignment;
+
+    #[test]
+    fn split_into_yields_exactly_count_page_aligned_regions() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let map = unsafe { MmapOptions::new().map_anon(10 * page_size + 100) }.unwrap();
+        let base = map.as_ptr() as usize;
+
+        let regions = map.split_into(4).unwrap();
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions.iter().map(|region| region.len()).sum::<usize>(), 10 * page_size + 100);
+        for pair in regions.windows(2) {
+            assert_eq!(pair[0].offset() + pair[0].len(), pair[1].offset());
+            assert!(alignment::is_aligned(base + pair[1].offset(), page_size));
+        }
+
+        let sizes: Vec<usize> = regions.iter().map(|region| region.len().div_ceil(page_size)).collect();
+        assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1);
+    }
+
+    #[test]
+    fn split_into_more_regions_than_pages_leaves_empty_regions() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let map = unsafe { MmapOptions::new().map_anon(2 * page_size) }.unwrap();
+
+        let regions = map.split_into(3).unwrap();
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions.iter().filter(|region| region.is_empty()).count(), 1);
+        assert_eq!(regions.iter().map(|region| region.len()).sum::<usize>(), 2 * page_size);
+    }
+
+    #[test]
+    fn split_into_zero_regions_fails() {
+        let _lock = test_lock();
+        let map = unsafe { MmapOptions::new().map_anon(4096) }.unwrap();
+        assert!(map.split_into(0).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Memory maps that follow a file as it changes.
+//!
+//! This module provides `RemappingMmap`, which maps a file by path and maps it
+//! again whenever the file grows, shrinks or is replaced, and `MmapSnapshot`,
+//! a cheap handle to one version of that mapping. Readers work on snapshots,
+//! so a remap never pulls memory out from under them.
+
+use std::fs::{self, File};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+use crate::mmap::{Mmap, MmapOptions};
+#[cfg(target_os = "linux")]
+use crate::platform;
+use crate::utils::RwLock;
+
+/// A read-only memory map of a file that is remapped as the file changes.
+///
+/// Call `refresh` to check the file and remap it if needed, either by polling
+/// or, on Linux, after `wait` reports a change. In-place modifications of the
+/// file show through the current mapping and do not cause a remap.
+#[derive(Debug)]
+pub struct RemappingMmap {
+    /// Path of the mapped file.
+    path: PathBuf,
+
+    /// Options used for every mapping of the file.
+    options: MmapOptions,
+
+    /// The current mapping and its generation.
+    current: RwLock<MmapSnapshot>,
+
+    /// Inotify instance watching the directory containing the file.
+    #[cfg(target_os = "linux")]
+    watch: Option<OwnedFd>,
+}
+
+impl RemappingMmap {
+    /// Map the file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// Other processes may modify the file at any time, which might lead to
+    /// undefined behavior. See `MmapOptions::open`.
+    #[inline]
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> Result<RemappingMmap> {
+        RemappingMmap::with_options(path, MmapOptions::new())
+    }
+
+    /// Map the file at `path` using `options`.
+    ///
+    /// The length of the mapping always follows the length of the file, so any
+    /// length set in `options` is ignored. An empty file, or one shorter than
+    /// the offset in `options`, is represented by an empty snapshot.
+    ///
+    /// # Safety
+    ///
+    /// Other processes may modify the file at any time, which might lead to
+    /// undefined behavior. See `MmapOptions::open`.
+    pub unsafe fn with_options<P: AsRef<Path>>(path: P, options: MmapOptions) -> Result<RemappingMmap> {
+        let path = path.as_ref().to_path_buf();
+        let map = map_file(&path, &options)?;
+
+        Ok(RemappingMmap {
+            path,
+            options,
+            current: RwLock::new(MmapSnapshot { map, generation: 0 }),
+            #[cfg(target_os = "linux")]
+            watch: None,
+        })
+    }
+
+    /// Watch the directory containing the file with inotify, so that `wait`
+    /// can block until the file changes and `refresh` skips checking the file
+    /// when nothing happened.
+    #[cfg(target_os = "linux")]
+    pub fn watch(mut self) -> Result<RemappingMmap> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        self.watch = Some(platform::watch_dir(dir)?);
+        Ok(self)
+    }
+
+    /// Return the path of the mapped file.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the number of times the file has been remapped.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.current.read().generation
+    }
+
+    /// Return a handle to the current mapping.
+    ///
+    /// The snapshot keeps its mapping alive and unchanged even if the file is
+    /// remapped afterwards.
+    #[inline]
+    pub fn snapshot(&self) -> MmapSnapshot {
+        self.current.read().clone()
+    }
+
+    /// Remap the file if it has grown, shrunk or been replaced since it was
+    /// last mapped, returning true if it was remapped.
+    ///
+    /// When watching, the file is only checked if an event concerning it has
+    /// arrived since the last call.
+    pub fn refresh(&self) -> Result<bool> {
+        #[cfg(target_os = "linux")]
+        if let Some(ref watch) = self.watch {
+            let name = self.path.file_name().unwrap_or_default();
+            if !platform::read_watch_events(watch.as_fd(), name)? {
+                return Ok(false);
+            }
+        }
+
+        let mut current = self.current.write();
+        let stale = match current.map {
+            Some(ref map) => map.check_stale(false)?,
+            None => file_len(&self.path)? > self.options.offset,
+        };
+        if !stale {
+            return Ok(false);
+        }
+
+        let map = unsafe { map_file(&self.path, &self.options)? };
+        *current = MmapSnapshot {
+            map,
+            generation: current.generation + 1,
+        };
+
+        Ok(true)
+    }
+
+    /// Block until the watched directory reports a change or `timeout`
+    /// expires, then `refresh`, returning true if the file was remapped.
+    ///
+    /// Fails with `Error::InvalidArgument` unless `watch` has been called.
+    #[cfg(target_os = "linux")]
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<bool> {
+        let watch = self
+            .watch
+            .as_ref()
+            .ok_or(Error::InvalidArgument("Waiting requires a watched RemappingMmap".into()))?;
+
+        if platform::wait_readable(watch.as_fd(), timeout)? {
+            self.refresh()
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// A handle to one version of a `RemappingMmap`.
+///
+/// Snapshots are cheap to clone, and dereference to the bytes of the file as
+/// mapped at the time the snapshot was taken.
+#[derive(Debug, Clone)]
+pub struct MmapSnapshot {
+    /// The mapping, or `None` if the file was empty.
+    map: Option<Arc<Mmap>>,
+
+    /// The generation of the mapping.
+    generation: u64,
+}
+
+impl MmapSnapshot {
+    /// Return the generation of the mapping, incremented on every remap.
+    #[inline]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Return the underlying memory map, or `None` if the file was empty.
+    #[inline]
+    pub fn as_mmap(&self) -> Option<&Mmap> {
+        self.map.as_deref()
+    }
+
+    /// Return true if the file has changed since this snapshot was taken.
+    ///
+    /// See `Mmap::is_stale`.
+    #[inline]
+    pub fn is_stale(&self) -> Result<bool> {
+        match self.map {
+            Some(ref map) => map.is_stale(),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Deref for MmapSnapshot {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self.map {
+            Some(ref map) => map,
+            None => &[],
+        }
+    }
+}
+
+impl AsRef<[u8]> for MmapSnapshot {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Map the whole file at `path` past the offset in `options`, or return
+/// `None` if there is nothing to map.
+///
+/// The length is taken from the opened file, so a file replaced at `path` in
+/// the meantime is never mapped with the length of its predecessor.
+unsafe fn map_file(path: &Path, options: &MmapOptions) -> Result<Option<Arc<Mmap>>> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len <= options.offset {
+        return Ok(None);
+    }
+
+    let len = (len - options.offset).try_into().map_err(|_| Error::SizeExceedsSystemLimit)?;
+    let map = options.clone().write(false).len(len).map_opened(file, path)?;
+    Ok(Some(Arc::new(map)))
+}
+
+/// Return the length of the file at `path`.
+#[inline]
+fn file_len(path: &Path) -> Result<u64> {
+    Ok(fs::metadata(path)?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::mmap::test_lock;
+
+    #[test]
+    fn maps_go_stale_when_the_file_changes() {
+        let _lock = test_lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        fs::write(&path, b"version one").unwrap();
+
+        // A different size
+        let map = unsafe { MmapOptions::new().open(&path) }.unwrap();
+        assert!(!map.is_stale().unwrap());
+        File::options().write(true).open(&path).unwrap().set_len(20).unwrap();
+        assert!(map.is_stale().unwrap());
+        assert!(map.check_stale(false).unwrap());
+
+        // A different modification time, which only `is_stale` considers
+        let map = unsafe { MmapOptions::new().open(&path) }.unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(map.is_stale().unwrap());
+        assert!(!map.check_stale(false).unwrap());
+
+        // Another file renamed over the path, with the same size and time
+        let map = unsafe { MmapOptions::new().open(&path) }.unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let other = dir.path().join("other");
+        fs::write(&other, vec![b'x'; metadata.len() as usize]).unwrap();
+        File::options().write(true).open(&other).unwrap().set_modified(metadata.modified().unwrap()).unwrap();
+        fs::rename(&other, &path).unwrap();
+        assert!(map.is_stale().unwrap());
+        assert!(map.check_stale(false).unwrap());
+    }
+
+    #[test]
+    fn refresh_remaps_only_changed_files() {
+        let _lock = test_lock();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        fs::write(&path, b"").unwrap();
+
+        let map = unsafe { RemappingMmap::open(&path) }.unwrap();
+        let empty = map.snapshot();
+        assert_eq!((map.generation(), empty.generation()), (0, 0));
+        assert!(empty.is_empty() && empty.as_mmap().is_none());
+        assert!(!map.refresh().unwrap());
+
+        fs::write(&path, b"first").unwrap();
+        assert!(map.refresh().unwrap());
+        let first = map.snapshot();
+        assert_eq!((map.generation(), first.generation()), (1, 1));
+        assert_eq!(&first[..], b"first");
+        assert!(!map.refresh().unwrap());
+
+        // Replacing the file remaps it, while older snapshots keep their bytes
+        let replacement = dir.path().join("replacement");
+        fs::write(&replacement, b"second version").unwrap();
+        fs::rename(&replacement, &path).unwrap();
+        assert!(first.is_stale().unwrap());
+        assert!(map.refresh().unwrap());
+        assert_eq!(map.generation(), 2);
+        assert_eq!(&map.snapshot()[..], b"second version");
+        assert_eq!(&first[..], b"first");
+        assert_eq!(empty.generation(), 0);
+    }
+}
@@ -0,0 +1,349 @@
+//! Address-space reservations with incremental commit.
+//!
+//! This module provides `Reservation`, a range of address space that costs no
+//! memory until parts of it are committed. An arena can reserve room for its
+//! largest possible size up front and commit memory as it grows, without ever
+//! moving, so pointers into it stay valid.
+
+use std::fs::File;
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+use std::slice;
+
+use crate::error::{Error, Result};
+use crate::mmap::{self, MmapOptions, MmapRaw};
+use crate::platform;
+use crate::utils::alignment;
+
+/// A reserved range of address space whose sub-ranges can be committed and
+/// decommitted individually.
+///
+/// The whole range is reserved inaccessible (`PROT_NONE` with `MAP_NORESERVE`
+/// on Linux) and committed chunks are mapped over it in place, so the start
+/// address never changes.
+#[derive(Debug)]
+pub struct Reservation {
+    /// The reserved range.
+    map: MmapRaw,
+
+    /// Options applied to committed chunks.
+    options: MmapOptions,
+
+    /// Size in bytes that offsets and lengths of chunks must be multiples of.
+    granularity: usize,
+
+    /// Committed ranges, sorted and merged.
+    committed: Vec<Range<usize>>,
+}
+
+impl Reservation {
+    /// Reserve at least `max_len` bytes of address space for readable and
+    /// writable memory.
+    ///
+    /// The length is rounded up to a multiple of the page size.
+    #[inline]
+    pub fn new(max_len: usize) -> Result<Reservation> {
+        Reservation::with_options(max_len, &MmapOptions::new().write(true))
+    }
+
+    /// Reserve at least `max_len` bytes of address space, committing chunks
+    /// with `options`.
+    ///
+    /// Committed chunks honour the `readable`, `writable`, `executable`,
+    /// `huge_pages`, `numa_policy` and `populate` options, and file-backed
+    /// chunks also `copy_on_write`. The reservation itself honours `alignment`
+    /// and `at_address` or `hint_address`. With huge pages, the length and
+    /// every chunk are multiples of the huge page size.
+    pub fn with_options(max_len: usize, options: &MmapOptions) -> Result<Reservation> {
+        if max_len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+
+        let granularity = match options.huge_pages {
+            Some(size) => size.bytes(),
+            None => alignment::page_size(),
+        };
+        let align = match options.alignment {
+            Some(align) if !align.is_power_of_two() => {
+                return Err(Error::InvalidArgument("Alignment must be a power of two".into()));
+            }
+            Some(align) => align.max(granularity),
+            None => granularity,
+        };
+        let len = max_len
+            .checked_add(granularity - 1)
+            .map(|len| alignment::align_down(len, granularity))
+            .ok_or(Error::SizeExceedsSystemLimit)?;
+
+        if let Some(placement) = options.placement {
+            let address = placement.address();
+            if address == 0 || !alignment::is_aligned(address, align) {
+                return Err(Error::UnalignedAddress { address, alignment: align });
+            }
+        }
+
+        // Reserved address space costs no memory, so only committed chunks
+        // count towards the mapped memory
+        let map = unsafe { platform::reserve(len, align, options.placement)? };
+        mmap::record_mapping(0);
+
+        Ok(Reservation {
+            map,
+            options: options.clone(),
+            granularity,
+            committed: Vec::new(),
+        })
+    }
+
+    /// Return the size of the reservation in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len
+    }
+
+    /// Return true if the reservation is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.len == 0
+    }
+
+    /// Return the size in bytes that offsets and lengths of committed chunks
+    /// must be multiples of: the page size, or the huge page size.
+    #[inline]
+    pub fn granularity(&self) -> usize {
+        self.granularity
+    }
+
+    /// Return a pointer to the start of the reservation.
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.map.ptr
+    }
+
+    /// Return a mutable pointer to the start of the reservation.
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.map.ptr
+    }
+
+    /// Commit `len` bytes at `offset` with anonymous, zero-filled memory.
+    ///
+    /// The range must not overlap memory that is already committed.
+    pub fn commit(&mut self, offset: usize, len: usize) -> Result<()> {
+        self.check_chunk(offset, len)?;
+        unsafe { self.commit_impl(offset, len, None) }
+    }
+
+    /// Commit `len` bytes at `offset` backed by `file`, starting at
+    /// `file_offset`, which must be a multiple of the page size.
+    ///
+    /// The range must not overlap memory that is already committed. The chunk
+    /// is shared with the file unless `copy_on_write` is set.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because the chunk can be accessed while the file
+    /// is being modified by external processes, which might lead to undefined
+    /// behavior. See `MmapOptions::map`.
+    pub unsafe fn commit_file(&mut self, offset: usize, len: usize, file: &File, file_offset: u64) -> Result<()> {
+        self.check_chunk(offset, len)?;
+        if !file_offset.is_multiple_of(alignment::page_size() as u64) {
+            return Err(Error::InvalidArgument(format!(
+                "File offset {} is not a multiple of the page size",
+                file_offset
+            )));
+        }
+        self.commit_impl(offset, len, Some((file, file_offset)))
+    }
+
+    /// Decommit `len` bytes at `offset`, releasing their memory and making them
+    /// inaccessible again.
+    ///
+    /// Anonymous memory that is committed again afterwards reads as zeros.
+    /// Parts of the range that are not committed are left alone.
+    pub fn decommit(&mut self, offset: usize, len: usize) -> Result<()> {
+        self.check_bounds(offset, len)?;
+        unsafe { platform::decommit(self.map.ptr.add(offset), len)? };
+
+        let end = offset + len;
+        let mut committed = Vec::with_capacity(self.committed.len() + 1);
+        let mut released = 0;
+        for range in self.committed.drain(..) {
+            released += range.end.min(end).saturating_sub(range.start.max(offset));
+            if range.start < offset {
+                committed.push(range.start..range.end.min(offset));
+            }
+            if range.end > end {
+                committed.push(range.start.max(end)..range.end);
+            }
+        }
+        self.committed = committed;
+        mmap::record_decommit(released);
+
+        Ok(())
+    }
+
+    /// Return true if every byte of `len` bytes at `offset` is committed.
+    pub fn is_committed(&self, offset: usize, len: usize) -> bool {
+        let end = match offset.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        self.committed
+            .iter()
+            .any(|range| range.start <= offset && end <= range.end)
+    }
+
+    /// Return the committed ranges, sorted and with adjacent ranges merged.
+    #[inline]
+    pub fn committed_ranges(&self) -> &[Range<usize>] {
+        &self.committed
+    }
+
+    /// Return the total number of committed bytes.
+    pub fn committed_bytes(&self) -> usize {
+        self.committed.iter().map(|range| range.end - range.start).sum()
+    }
+
+    /// Get a slice of `len` committed bytes at `offset`.
+    pub fn as_slice(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.check_committed(offset, len)?;
+        Ok(unsafe { slice::from_raw_parts(self.map.ptr.add(offset), len) })
+    }
+
+    /// Get a mutable slice of `len` committed bytes at `offset`.
+    pub fn as_mut_slice(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        self.check_committed(offset, len)?;
+        Ok(unsafe { slice::from_raw_parts_mut(self.map.ptr.add(offset), len) })
+    }
+
+    /// Map a chunk over the reservation and record it as committed.
+    unsafe fn commit_impl(&mut self, offset: usize, len: usize, file: Option<(&File, u64)>) -> Result<()> {
+        platform::commit(self.map.ptr.add(offset), len, file, &self.options)?;
+        mmap::record_commit(len);
+
+        let index = self.committed.partition_point(|range| range.end < offset);
+        let mut range = offset..offset + len;
+        while index < self.committed.len() && self.committed[index].start <= range.end {
+            let next = self.committed.remove(index);
+            range = range.start.min(next.start)..range.end.max(next.end);
+        }
+        self.committed.insert(index, range);
+
+        Ok(())
+    }
+
+    /// Check that a chunk to be committed is in bounds, aligned and not yet
+    /// committed.
+    fn check_chunk(&self, offset: usize, len: usize) -> Result<()> {
+        self.check_bounds(offset, len)?;
+
+        let end = offset + len;
+        if self.committed.iter().any(|range| range.start < end && offset < range.end) {
+            return Err(Error::InvalidArgument(format!(
+                "Range {}..{} overlaps committed memory",
+                offset, end
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that a range is non-empty, in bounds and a multiple of the
+    /// granularity.
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<()> {
+        if len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+        mmap::check_range(offset, len, self.map.len)?;
+
+        if !alignment::is_aligned(offset, self.granularity) || !alignment::is_aligned(len, self.granularity) {
+            return Err(Error::InvalidArgument(format!(
+                "Range {}..{} is not aligned to {} bytes",
+                offset,
+                offset + len,
+                self.granularity
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that a range is entirely committed.
+    fn check_committed(&self, offset: usize, len: usize) -> Result<()> {
+        mmap::check_range(offset, len, self.map.len)?;
+        if len != 0 && !self.is_committed(offset, len) {
+            return Err(Error::InvalidArgument(format!(
+                "Range {}..{} is not committed",
+                offset,
+                offset + len
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        // Only committed memory is counted as mapped, so release the
+        // reservation here rather than in `MmapRaw`, which would stop counting
+        // its whole length
+        let ptr = mem::replace(&mut self.map.ptr, ptr::null_mut());
+        mmap::release_mapping(self.committed_bytes());
+        let _ = unsafe { platform::unmap(ptr, self.map.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmap::test_lock;
+
+    /// Return the mapping counters.
+    fn counters() -> (usize, usize) {
+        (mmap::total_mapped_memory(), mmap::active_mappings())
+    }
+
+    #[test]
+    fn only_committed_bytes_count_as_mapped() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let (memory, mappings) = counters();
+
+        let mut reservation = Reservation::new(16 * page_size).unwrap();
+        assert_eq!(counters(), (memory, mappings + 1));
+
+        reservation.commit(0, 4 * page_size).unwrap();
+        reservation.commit(8 * page_size, 2 * page_size).unwrap();
+        assert_eq!(counters(), (memory + 6 * page_size, mappings + 1));
+
+        // Only the committed parts of the range are released
+        reservation.decommit(2 * page_size, 8 * page_size).unwrap();
+        assert_eq!(counters(), (memory + 2 * page_size, mappings + 1));
+
+        drop(reservation);
+        assert_eq!(counters(), (memory, mappings));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn decommit_replaces_file_chunks() {
+        let _lock = test_lock();
+        let page_size = alignment::page_size();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file().set_len(2 * page_size as u64).unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+
+        let mut reservation = Reservation::new(4 * page_size).unwrap();
+        unsafe { reservation.commit_file(0, 2 * page_size, file.as_file(), 0) }.unwrap();
+        reservation.as_mut_slice(0, 1).unwrap()[0] = 1;
+        let maps_file = || std::fs::read_to_string("/proc/self/maps").unwrap().contains(&path);
+        assert!(maps_file());
+
+        reservation.decommit(0, 2 * page_size).unwrap();
+        assert!(!maps_file());
+
+        // The range can be committed again, with fresh zero-filled memory
+        reservation.commit(0, 2 * page_size).unwrap();
+        assert_eq!(reservation.as_slice(0, 1).unwrap(), &[0]);
+    }
+}
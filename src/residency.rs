@@ -0,0 +1,117 @@
+//! Page residency reporting.
+//!
+//! This module provides `Residency`, a compact bitmap recording which pages of
+//! a memory map are resident in RAM, as reported by `mincore`. It can be used
+//! to decide whether touching a range would fault, or to check whether
+//! prefetching has warmed the page cache.
+
+use std::ops::Range;
+
+/// A snapshot of which pages of a memory map are resident in RAM.
+///
+/// Page `0` is the page containing the first byte of the memory map. The
+/// snapshot is not updated as pages are faulted in or evicted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Residency {
+    /// One bit per page, least significant bit first.
+    bits: Vec<u8>,
+
+    /// Number of pages covered.
+    pages: usize,
+
+    /// Size of a page in bytes.
+    page_size: usize,
+
+    /// Offset of the first byte of the memory map within its first page.
+    start: usize,
+
+    /// Length of the memory map.
+    len: usize,
+}
+
+impl Residency {
+    /// Pack per-page residency into a bitmap.
+    pub(crate) fn new(pages: &[bool], page_size: usize, start: usize, len: usize) -> Residency {
+        let mut bits = vec![0u8; pages.len().div_ceil(8)];
+        for (page, _) in pages.iter().enumerate().filter(|(_, &resident)| resident) {
+            bits[page / 8] |= 1 << (page % 8);
+        }
+
+        Residency {
+            bits,
+            pages: pages.len(),
+            page_size,
+            start,
+            len,
+        }
+    }
+
+    /// Return the number of pages covered.
+    #[inline]
+    pub fn page_count(&self) -> usize {
+        self.pages
+    }
+
+    /// Return the size of a page in bytes.
+    #[inline]
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Return the raw bitmap, one bit per page, least significant bit first.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    /// Return true if `page` is resident.
+    ///
+    /// Pages past the end of the memory map are reported as not resident.
+    #[inline]
+    pub fn is_page_resident(&self, page: usize) -> bool {
+        page < self.pages && self.bits[page / 8] & (1 << (page % 8)) != 0
+    }
+
+    /// Return the number of resident pages.
+    #[inline]
+    pub fn resident_pages(&self) -> usize {
+        self.bits.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    /// Return the number of bytes of the memory map on resident pages.
+    pub fn resident_bytes(&self) -> usize {
+        (0..self.pages)
+            .filter(|&page| self.is_page_resident(page))
+            .map(|page| self.page_span(page).len())
+            .sum()
+    }
+
+    /// Return the byte ranges of the memory map that are resident.
+    ///
+    /// Adjacent resident pages are merged into a single range.
+    pub fn resident_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for page in (0..self.pages).filter(|&page| self.is_page_resident(page)) {
+            let span = self.page_span(page);
+            match ranges.last_mut() {
+                Some(last) if last.end == span.start => last.end = span.end,
+                _ => ranges.push(span),
+            }
+        }
+        ranges
+    }
+
+    /// Return true if every page of the memory map is resident.
+    #[inline]
+    pub fn is_fully_resident(&self) -> bool {
+        self.resident_pages() == self.pages
+    }
+
+    /// Return the bytes of the memory map that lie on `page`.
+    #[inline]
+    fn page_span(&self, page: usize) -> Range<usize> {
+        let start = (page * self.page_size).saturating_sub(self.start);
+        let end = ((page + 1) * self.page_size - self.start).min(self.len);
+        start..end
+    }
+}
@@ -0,0 +1,481 @@
+//! Double-mapped ring buffers.
+//!
+//! This module provides `RingBuffer`, which maps the same memory twice, back
+//! to back. Any range of up to `capacity` bytes starting inside the buffer is
+//! contiguous in memory, so reads and writes that wrap past the end never
+//! have to be split in two.
+//!
+//! A `RingBuffer` is used from one thread at a time. To pass bytes between
+//! two threads, split it into a `Producer` and a `Consumer`.
+
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::mmap::{self, MmapOptions, MmapRaw};
+use crate::platform;
+use crate::utils::alignment;
+
+/// A ring buffer backed by a double-mapped memory region.
+///
+/// The buffer tracks a producer cursor (`write_cursor`) and a consumer cursor
+/// (`read_cursor`). Both only ever increase; the bytes between them are the
+/// readable contents of the buffer.
+///
+/// Every method that moves a cursor takes `&mut self`, so a `RingBuffer` is
+/// single-threaded: a producer and a consumer cannot use it at the same time
+/// from different threads. Use `split` to get halves that can.
+#[derive(Debug)]
+pub struct RingBuffer {
+    /// The double mapping, `2 * capacity` bytes long.
+    map: MmapRaw,
+
+    /// Size of the buffer in bytes.
+    capacity: usize,
+
+    /// Total number of bytes consumed.
+    read: u64,
+
+    /// Total number of bytes produced.
+    write: u64,
+}
+
+impl RingBuffer {
+    /// Create a ring buffer of at least `capacity` bytes.
+    ///
+    /// The capacity is rounded up to a multiple of the page size.
+    #[inline]
+    pub fn new(capacity: usize) -> Result<RingBuffer> {
+        RingBuffer::with_options(capacity, &MmapOptions::new())
+    }
+
+    /// Create a ring buffer of at least `capacity` bytes using `options`.
+    ///
+    /// The `huge_pages`, `alignment` and `populate` options are honoured. The
+    /// capacity is rounded up to a multiple of the page size, or of the huge
+    /// page size if huge pages are requested.
+    pub fn with_options(capacity: usize, options: &MmapOptions) -> Result<RingBuffer> {
+        if capacity == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+
+        let page_size = match options.huge_pages {
+            Some(size) => size.bytes(),
+            None => alignment::page_size(),
+        };
+        let align = match options.alignment {
+            Some(align) if !align.is_power_of_two() => {
+                return Err(Error::InvalidArgument("Alignment must be a power of two".into()));
+            }
+            Some(align) => align.max(page_size),
+            None => page_size,
+        };
+        let capacity = capacity
+            .checked_add(page_size - 1)
+            .map(|capacity| alignment::align_down(capacity, page_size))
+            .ok_or(Error::SizeExceedsSystemLimit)?;
+        if capacity > isize::MAX as usize / 2 {
+            return Err(Error::SizeExceedsSystemLimit);
+        }
+
+        let map = unsafe { platform::map_ring(capacity, options.huge_pages, options.populate, align)? };
+        mmap::record_mapping(map.len);
+
+        Ok(RingBuffer {
+            map,
+            capacity,
+            read: 0,
+            write: 0,
+        })
+    }
+
+    /// Return the size of the buffer in bytes.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the number of bytes available for reading.
+    #[inline]
+    pub fn len(&self) -> usize {
+        (self.write - self.read) as usize
+    }
+
+    /// Return true if there are no bytes available for reading.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.write == self.read
+    }
+
+    /// Return true if there is no space available for writing.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity
+    }
+
+    /// Return the number of bytes available for writing.
+    #[inline]
+    pub fn free_space(&self) -> usize {
+        self.capacity - self.len()
+    }
+
+    /// Return the total number of bytes consumed so far.
+    #[inline]
+    pub fn read_cursor(&self) -> u64 {
+        self.read
+    }
+
+    /// Return the total number of bytes produced so far.
+    #[inline]
+    pub fn write_cursor(&self) -> u64 {
+        self.write
+    }
+
+    /// Return a contiguous slice of `len` writable bytes at the write cursor.
+    ///
+    /// Returns `None` if fewer than `len` bytes are free. Bytes written to the
+    /// slice become readable once they are committed with `advance_write`.
+    #[inline]
+    pub fn as_contiguous_write(&mut self, len: usize) -> Option<&mut [u8]> {
+        if len > self.free_space() {
+            return None;
+        }
+        let start = self.position(self.write);
+        Some(unsafe { slice::from_raw_parts_mut(self.map.ptr.add(start), len) })
+    }
+
+    /// Return a contiguous slice of every readable byte at the read cursor.
+    ///
+    /// The bytes stay in the buffer until they are released with
+    /// `advance_read`.
+    #[inline]
+    pub fn as_contiguous_read(&self) -> &[u8] {
+        let start = self.position(self.read);
+        unsafe { slice::from_raw_parts(self.map.ptr.add(start), self.len()) }
+    }
+
+    /// Commit `len` written bytes, making them readable.
+    #[inline]
+    pub fn advance_write(&mut self, len: usize) -> Result<()> {
+        if len > self.free_space() {
+            return Err(Error::InvalidArgument(format!(
+                "Cannot commit {} bytes with only {} bytes free",
+                len,
+                self.free_space()
+            )));
+        }
+        self.write += len as u64;
+        Ok(())
+    }
+
+    /// Release `len` read bytes, freeing their space for writing.
+    #[inline]
+    pub fn advance_read(&mut self, len: usize) -> Result<()> {
+        if len > self.len() {
+            return Err(Error::InvalidArgument(format!(
+                "Cannot consume {} bytes with only {} bytes readable",
+                len,
+                self.len()
+            )));
+        }
+        self.read += len as u64;
+        Ok(())
+    }
+
+    /// Copy as much of `data` as fits into the buffer, returning the number of
+    /// bytes written.
+    #[inline]
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let len = data.len().min(self.free_space());
+        if let Some(dst) = self.as_contiguous_write(len) {
+            dst.copy_from_slice(&data[..len]);
+        }
+        self.write += len as u64;
+        len
+    }
+
+    /// Move as many readable bytes as fit into `buf`, returning the number of
+    /// bytes read.
+    #[inline]
+    pub fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.len());
+        buf[..len].copy_from_slice(&self.as_contiguous_read()[..len]);
+        self.read += len as u64;
+        len
+    }
+
+    /// Split the buffer into a producer and a consumer half, which can be
+    /// moved to different threads.
+    ///
+    /// Bytes already in the buffer stay readable by the consumer. The mapping
+    /// is unmapped once both halves are dropped.
+    pub fn split(self) -> (Producer, Consumer) {
+        let shared = Arc::new(Shared {
+            map: self.map,
+            capacity: self.capacity,
+            read: AtomicU64::new(self.read),
+            write: AtomicU64::new(self.write),
+        });
+        let producer = Producer {
+            shared: Arc::clone(&shared),
+            write: self.write,
+        };
+        let consumer = Consumer {
+            shared,
+            read: self.read,
+        };
+        (producer, consumer)
+    }
+
+    /// Convert a cursor into an offset within the first half of the mapping.
+    #[inline]
+    fn position(&self, cursor: u64) -> usize {
+        (cursor % self.capacity as u64) as usize
+    }
+}
+
+/// The state shared by the two halves of a split ring buffer.
+#[derive(Debug)]
+struct Shared {
+    /// The double mapping, `2 * capacity` bytes long.
+    map: MmapRaw,
+
+    /// Size of the buffer in bytes.
+    capacity: usize,
+
+    /// Total number of bytes consumed, published by the consumer.
+    read: AtomicU64,
+
+    /// Total number of bytes produced, published by the producer.
+    write: AtomicU64,
+}
+
+impl Shared {
+    /// Return a pointer to the byte at `cursor`.
+    #[inline]
+    fn ptr(&self, cursor: u64) -> *mut u8 {
+        let start = (cursor % self.capacity as u64) as usize;
+        unsafe { self.map.ptr.add(start) }
+    }
+}
+
+/// The writing half of a ring buffer, created with `RingBuffer::split`.
+///
+/// The producer owns the write cursor. It publishes written bytes with a
+/// release store, and observes the space freed by the consumer with an
+/// acquire load, so bytes are never read before they are written, nor
+/// overwritten before they are read.
+#[derive(Debug)]
+pub struct Producer {
+    shared: Arc<Shared>,
+
+    /// Total number of bytes produced, owned by this half.
+    write: u64,
+}
+
+impl Producer {
+    /// Return the size of the buffer in bytes.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Return the number of bytes available for writing.
+    ///
+    /// More space may become free at any time as the consumer reads.
+    #[inline]
+    pub fn free_space(&self) -> usize {
+        let read = self.shared.read.load(Ordering::Acquire);
+        self.shared.capacity - (self.write - read) as usize
+    }
+
+    /// Return true if there is no space available for writing.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.free_space() == 0
+    }
+
+    /// Return the total number of bytes produced so far.
+    #[inline]
+    pub fn write_cursor(&self) -> u64 {
+        self.write
+    }
+
+    /// Return a contiguous slice of `len` writable bytes at the write cursor.
+    ///
+    /// Returns `None` if fewer than `len` bytes are free. Bytes written to the
+    /// slice become readable once they are committed with `advance_write`.
+    #[inline]
+    pub fn as_contiguous_write(&mut self, len: usize) -> Option<&mut [u8]> {
+        if len > self.free_space() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts_mut(self.shared.ptr(self.write), len) })
+    }
+
+    /// Commit `len` written bytes, making them readable by the consumer.
+    #[inline]
+    pub fn advance_write(&mut self, len: usize) -> Result<()> {
+        let free = self.free_space();
+        if len > free {
+            return Err(Error::InvalidArgument(format!(
+                "Cannot commit {} bytes with only {} bytes free",
+                len, free
+            )));
+        }
+        self.write += len as u64;
+        self.shared.write.store(self.write, Ordering::Release);
+        Ok(())
+    }
+
+    /// Copy as much of `data` as fits into the buffer, returning the number of
+    /// bytes written.
+    #[inline]
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let len = data.len().min(self.free_space());
+        if let Some(dst) = self.as_contiguous_write(len) {
+            dst.copy_from_slice(&data[..len]);
+        }
+        self.write += len as u64;
+        self.shared.write.store(self.write, Ordering::Release);
+        len
+    }
+}
+
+/// The reading half of a ring buffer, created with `RingBuffer::split`.
+///
+/// The consumer owns the read cursor. It observes bytes written by the
+/// producer with an acquire load, and frees their space with a release
+/// store once it is done with them.
+#[derive(Debug)]
+pub struct Consumer {
+    shared: Arc<Shared>,
+
+    /// Total number of bytes consumed, owned by this half.
+    read: u64,
+}
+
+impl Consumer {
+    /// Return the size of the buffer in bytes.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Return the number of bytes available for reading.
+    ///
+    /// More bytes may become readable at any time as the producer writes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        let write = self.shared.write.load(Ordering::Acquire);
+        (write - self.read) as usize
+    }
+
+    /// Return true if there are no bytes available for reading.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the total number of bytes consumed so far.
+    #[inline]
+    pub fn read_cursor(&self) -> u64 {
+        self.read
+    }
+
+    /// Return a contiguous slice of every readable byte at the read cursor.
+    ///
+    /// The bytes stay in the buffer until they are released with
+    /// `advance_read`.
+    #[inline]
+    pub fn as_contiguous_read(&self) -> &[u8] {
+        let len = self.len();
+        unsafe { slice::from_raw_parts(self.shared.ptr(self.read), len) }
+    }
+
+    /// Release `len` read bytes, freeing their space for the producer.
+    #[inline]
+    pub fn advance_read(&mut self, len: usize) -> Result<()> {
+        let readable = self.len();
+        if len > readable {
+            return Err(Error::InvalidArgument(format!(
+                "Cannot consume {} bytes with only {} bytes readable",
+                len, readable
+            )));
+        }
+        self.read += len as u64;
+        self.shared.read.store(self.read, Ordering::Release);
+        Ok(())
+    }
+
+    /// Move as many readable bytes as fit into `buf`, returning the number of
+    /// bytes read.
+    #[inline]
+    pub fn pop(&mut self, buf: &mut [u8]) -> usize {
+        let readable = self.as_contiguous_read();
+        let len = buf.len().min(readable.len());
+        buf[..len].copy_from_slice(&readable[..len]);
+        self.read += len as u64;
+        self.shared.read.store(self.read, Ordering::Release);
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::mmap::test_lock;
+
+    #[test]
+    fn split_halves_pass_bytes_between_threads() {
+        let _lock = test_lock();
+        let (mut producer, mut consumer) = RingBuffer::new(1).unwrap().split();
+        let total = producer.capacity() as u64 * 8 + 3;
+
+        let writer = thread::spawn(move || {
+            let mut next = 0u64;
+            while next < total {
+                let len = ((total - next) as usize).min(producer.free_space()).min(1000);
+                if let Some(dst) = producer.as_contiguous_write(len) {
+                    for (i, byte) in dst.iter_mut().enumerate() {
+                        *byte = (next + i as u64) as u8;
+                    }
+                }
+                producer.advance_write(len).unwrap();
+                next += len as u64;
+            }
+        });
+
+        let mut next = 0u64;
+        while next < total {
+            let readable = consumer.as_contiguous_read();
+            for (i, &byte) in readable.iter().enumerate() {
+                assert_eq!(byte, (next + i as u64) as u8);
+            }
+            let len = readable.len();
+            consumer.advance_read(len).unwrap();
+            next += len as u64;
+        }
+        writer.join().unwrap();
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn split_keeps_buffered_bytes() {
+        let _lock = test_lock();
+        let mut ring = RingBuffer::new(1).unwrap();
+        assert_eq!(ring.push(b"hello"), 5);
+
+        let (mut producer, mut consumer) = ring.split();
+        assert_eq!(producer.free_space(), producer.capacity() - 5);
+        assert_eq!(producer.push(b" world"), 6);
+
+        let mut buf = [0; 16];
+        assert_eq!(consumer.pop(&mut buf), 11);
+        assert_eq!(&buf[..11], b"hello world");
+        assert_eq!(producer.free_space(), producer.capacity());
+    }
+}
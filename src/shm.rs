@@ -0,0 +1,343 @@
+//! Named POSIX shared memory segments.
+//!
+//! This module provides `SharedMemory`, a handle to a segment created with
+//! `shm_open` that unrelated processes can open by name and map.
+
+use std::fs::File;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::mmap::{Mmap, MmapMut, MmapOptions};
+use crate::platform;
+
+/// How long `open_or_create` waits by default for the process that created a
+/// segment to set its size.
+pub const DEFAULT_SIZE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A named POSIX shared memory segment.
+///
+/// Dropping the handle does not remove the segment unless it created the
+/// segment and `unlink_on_drop` has been set; segments otherwise persist until `SharedMemory::unlink` is
+/// called or the system restarts.
+#[derive(Debug)]
+pub struct SharedMemory {
+    /// Name of the segment, including the leading slash.
+    name: String,
+
+    /// File descriptor of the segment.
+    file: File,
+
+    /// Whether this handle created the segment.
+    created: bool,
+
+    /// Whether to unlink the segment when this handle is dropped.
+    unlink_on_drop: bool,
+}
+
+impl SharedMemory {
+    /// Create a new segment of `len` bytes.
+    ///
+    /// Returns `Error::SharedMemoryExists` if a segment with this name already
+    /// exists.
+    pub fn create(name: &str, len: usize) -> Result<SharedMemory> {
+        if len == 0 {
+            return Err(Error::ZeroSizedMapping);
+        }
+
+        let name = normalize_name(name)?;
+        let file = platform::shm_open(&name, true, true, true).map_err(|err| map_errno(err, &name))?;
+
+        if let Err(err) = file.set_len(len as u64) {
+            let _ = platform::shm_unlink(&name);
+            return Err(Error::Io(err));
+        }
+
+        Ok(SharedMemory {
+            name,
+            file,
+            created: true,
+            unlink_on_drop: false,
+        })
+    }
+
+    /// Open an existing segment for reading and writing.
+    ///
+    /// Returns `Error::SharedMemoryNotFound` if no segment with this name
+    /// exists.
+    #[inline]
+    pub fn open(name: &str) -> Result<SharedMemory> {
+        SharedMemory::open_impl(name, true)
+    }
+
+    /// Open an existing segment for reading only.
+    ///
+    /// Returns `Error::SharedMemoryNotFound` if no segment with this name
+    /// exists.
+    #[inline]
+    pub fn open_read_only(name: &str) -> Result<SharedMemory> {
+        SharedMemory::open_impl(name, false)
+    }
+
+    /// Open a segment of `len` bytes, creating it if it does not exist.
+    ///
+    /// Returns `Error::SizeMismatch` if the segment already exists with a
+    /// different size. Use `created` to find out whether this call created
+    /// the segment.
+    ///
+    /// A segment of size 0 is still being created by another process, which
+    /// has not set its size yet. This waits up to `DEFAULT_SIZE_TIMEOUT` for
+    /// the size to be set; see `open_or_create_timeout`.
+    #[inline]
+    pub fn open_or_create(name: &str, len: usize) -> Result<SharedMemory> {
+        SharedMemory::open_or_create_timeout(name, len, DEFAULT_SIZE_TIMEOUT)
+    }
+
+    /// Open a segment of `len` bytes, creating it if it does not exist, and
+    /// wait up to `timeout` for another process creating it to set its size.
+    ///
+    /// Returns `Error::SharedMemoryNotSized` if the segment still has size 0
+    /// after `timeout`, for example because its creator died. A segment that
+    /// this call did not create is never resized.
+    pub fn open_or_create_timeout(name: &str, len: usize, timeout: Duration) -> Result<SharedMemory> {
+        match SharedMemory::create(name, len) {
+            Err(Error::SharedMemoryExists(_)) => {}
+            result => return result,
+        }
+
+        let shm = SharedMemory::open(name)?;
+        let deadline = Instant::now() + timeout;
+        let mut actual = shm.len()?;
+        while actual == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+            actual = shm.len()?;
+        }
+        if actual == 0 {
+            return Err(Error::SharedMemoryNotSized(shm.name.clone()));
+        }
+        if actual != len {
+            return Err(Error::SizeMismatch { expected: len, actual });
+        }
+
+        Ok(shm)
+    }
+
+    /// Remove the segment with this name.
+    ///
+    /// Existing handles and memory maps of the segment stay valid, but the
+    /// name can no longer be opened.
+    #[inline]
+    pub fn unlink(name: &str) -> Result<()> {
+        let name = normalize_name(name)?;
+        platform::shm_unlink(&name).map_err(|err| map_errno(err, &name))
+    }
+
+    /// Configure whether the segment is unlinked when this handle is dropped.
+    ///
+    /// Only takes effect if this handle created the segment, so handles that
+    /// merely opened it can set it unconditionally.
+    #[inline]
+    pub fn unlink_on_drop(mut self, unlink: bool) -> SharedMemory {
+        self.unlink_on_drop = unlink;
+        self
+    }
+
+    /// Return the name of the segment, including the leading slash.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return true if this handle created the segment.
+    #[inline]
+    pub fn created(&self) -> bool {
+        self.created
+    }
+
+    /// Return the size of the segment in bytes.
+    #[inline]
+    pub fn len(&self) -> Result<usize> {
+        let len = self.file.metadata()?.len();
+        len.try_into().map_err(|_| Error::SizeExceedsSystemLimit)
+    }
+
+    /// Return true if the segment is empty.
+    #[inline]
+    pub fn is_empty(&self) -> Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Return the file backing the segment.
+    #[inline]
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Create a read-only memory map of the segment.
+    ///
+    /// Shared memory lives on tmpfs, which does not support `MAP_HUGETLB`, so
+    /// `options` requesting huge pages fail with an `Unsupported` I/O error.
+    /// Transparent huge pages can be requested instead.
+    ///
+    /// # Safety
+    ///
+    /// Other processes may modify the segment at any time, which might lead to
+    /// undefined behavior.
+    #[inline]
+    pub unsafe fn map(&self, options: &MmapOptions) -> Result<Mmap> {
+        shared_options(options)?.map(&self.file)
+    }
+
+    /// Create a writable memory map of the segment.
+    ///
+    /// See `map` for the requirements on `options`.
+    ///
+    /// # Safety
+    ///
+    /// Other processes may access the segment at any time, which might lead to
+    /// undefined behavior.
+    #[inline]
+    pub unsafe fn map_mut(&self, options: &MmapOptions) -> Result<MmapMut> {
+        shared_options(options)?.map_mut(&self.file)
+    }
+
+    /// Open an existing segment.
+    fn open_impl(name: &str, writable: bool) -> Result<SharedMemory> {
+        let name = normalize_name(name)?;
+        let file = platform::shm_open(&name, writable, false, false).map_err(|err| map_errno(err, &name))?;
+
+        Ok(SharedMemory {
+            name,
+            file,
+            created: false,
+            unlink_on_drop: false,
+        })
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        if self.created && self.unlink_on_drop {
+            let _ = platform::shm_unlink(&self.name);
+        }
+    }
+}
+
+/// Prepare mapping options for a shared memory segment, which must be mapped
+/// shared and cannot use hugetlb pages.
+fn shared_options(options: &MmapOptions) -> Result<MmapOptions> {
+    if options.huge_pages.is_some() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "shared memory segments cannot be backed by hugetlb pages",
+        )));
+    }
+    Ok(options.clone().copy_on_write(false))
+}
+
+/// Add the leading slash required by `shm_open`, and reject names that
+/// contain any other slash.
+fn normalize_name(name: &str) -> Result<String> {
+    let bare = name.strip_prefix('/').unwrap_or(name);
+    if bare.is_empty() || bare.contains('/') {
+        return Err(Error::InvalidArgument(format!("Invalid shared memory name: {:?}", name)));
+    }
+    Ok(format!("/{}", bare))
+}
+
+/// Turn the errno values with a dedicated error variant into that variant.
+fn map_errno(err: Error, name: &str) -> Error {
+    match err {
+        Error::Io(ref io_err) if io_err.raw_os_error() == Some(libc::EEXIST) => {
+            Error::SharedMemoryExists(name.to_string())
+        }
+        Error::Io(ref io_err) if io_err.raw_os_error() == Some(libc::ENOENT) => {
+            Error::SharedMemoryNotFound(name.to_string())
+        }
+        err => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a segment of size 0, as another process does between
+    /// `shm_open` and `ftruncate`.
+    fn create_unsized(name: &str) -> File {
+        platform::shm_open(&normalize_name(name).unwrap(), true, true, true).unwrap()
+    }
+
+    #[test]
+    fn open_or_create_waits_for_the_creator_to_set_the_size() {
+        let name = format!("membase-test-wait-{}", std::process::id());
+        let file = create_unsized(&name);
+        let creator = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            file.set_len(8192).unwrap();
+        });
+
+        let shm = SharedMemory::open_or_create(&name, 8192);
+        creator.join().unwrap();
+        SharedMemory::unlink(&name).unwrap();
+        let shm = shm.unwrap();
+        assert!(!shm.created());
+        assert_eq!(shm.len().unwrap(), 8192);
+    }
+
+    #[test]
+    fn open_or_create_leaves_an_unsized_segment_alone() {
+        let name = format!("membase-test-abandoned-{}", std::process::id());
+        let file = create_unsized(&name);
+
+        let result = SharedMemory::open_or_create_timeout(&name, 4096, Duration::from_millis(10));
+        SharedMemory::unlink(&name).unwrap();
+        match result {
+            Err(Error::SharedMemoryNotSized(ref segment)) => assert_eq!(segment, &format!("/{}", name)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(file.metadata().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn open_or_create_reports_a_different_size() {
+        let name = format!("membase-test-mismatch-{}", std::process::id());
+        let _shm = SharedMemory::create(&name, 4096).unwrap().unlink_on_drop(true);
+
+        match SharedMemory::open_or_create(&name, 8192) {
+            Err(Error::SizeMismatch { expected: 8192, actual: 4096 }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn only_the_creator_unlinks_on_drop() {
+        let name = format!("membase-test-unlink-{}", std::process::id());
+        let creator = SharedMemory::create(&name, 4096).unwrap().unlink_on_drop(true);
+
+        let opener = SharedMemory::open(&name).unwrap().unlink_on_drop(true);
+        assert!(!opener.created());
+        drop(opener);
+        SharedMemory::open(&name).unwrap();
+
+        drop(creator);
+        assert!(matches!(SharedMemory::open(&name), Err(Error::SharedMemoryNotFound(_))));
+    }
+
+    #[test]
+    fn huge_page_maps_are_unsupported() {
+        let _lock = crate::mmap::test_lock();
+        let name = format!("membase-test-huge-{}", std::process::id());
+        let shm = SharedMemory::create(&name, 4096).unwrap().unlink_on_drop(true);
+        let options = MmapOptions::new().huge_pages(crate::advanced::HugePageSize::TwoMB);
+
+        match unsafe { shm.map_mut(&options) } {
+            Err(Error::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::Unsupported),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let mut map = unsafe { shm.map_mut(&MmapOptions::new()) }.unwrap();
+        map[0] = 1;
+        assert_eq!(unsafe { shm.map(&MmapOptions::new()) }.unwrap()[0], 1);
+    }
+}
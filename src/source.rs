@@ -0,0 +1,419 @@
+//! Reading inputs that may or may not be mappable.
+//!
+//! This module provides `Source`, a file, path or standard input, and
+//! `MmapOrBuffer`, which holds the contents of a source either as a memory map
+//! or, for inputs that cannot be mapped such as pipes, empty files and procfs
+//! files, as a page-aligned heap buffer. Both dereference to `[u8]`, so one
+//! code path handles every kind of input.
+
+use std::alloc::{self, Layout};
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+use std::slice;
+
+#[cfg(unix)]
+use std::os::unix::io::AsFd;
+#[cfg(windows)]
+use std::os::windows::io::AsHandle;
+
+use crate::error::{Error, Result};
+use crate::mmap::{Mmap, MmapOptions};
+use crate::utils::alignment;
+
+/// Initial capacity of the buffer used when the size of an input is unknown.
+const INITIAL_BUFFER_SIZE: usize = 64 * 1024;
+
+/// An input to be read with `MmapOrBuffer`.
+#[derive(Debug)]
+pub enum Source {
+    /// The standard input of the process.
+    Stdin,
+
+    /// The file at a path.
+    Path(PathBuf),
+
+    /// An open file, read from its current position.
+    File(File),
+}
+
+impl Source {
+    /// Interpret a command-line argument, treating `-` as standard input.
+    #[inline]
+    pub fn from_arg<S: AsRef<str>>(arg: S) -> Source {
+        match arg.as_ref() {
+            "-" => Source::Stdin,
+            path => Source::Path(PathBuf::from(path)),
+        }
+    }
+
+    /// Open the source, mapping it if possible and reading it into a buffer
+    /// otherwise.
+    ///
+    /// Regular files with content are mapped from their current position.
+    /// Empty files, files that report a length of zero (such as procfs files),
+    /// pipes, sockets and anything else that cannot be mapped are read into a
+    /// buffer instead.
+    ///
+    /// # Safety
+    ///
+    /// If the source is mapped, other processes may modify the file at any
+    /// time, which might lead to undefined behavior. See `MmapOptions::map`.
+    pub unsafe fn open(self) -> Result<MmapOrBuffer> {
+        let file = match self {
+            Source::Stdin => stdin_file()?,
+            Source::Path(path) => File::open(path)?,
+            Source::File(file) => file,
+        };
+
+        let metadata = file.metadata()?;
+        let mut hint = INITIAL_BUFFER_SIZE;
+        if metadata.is_file() && metadata.len() > 0 {
+            let position = (&file).stream_position()?;
+            if position >= metadata.len() {
+                return Ok(MmapOrBuffer::Buffer(AlignedBuffer::read_from(&file, 0)?));
+            }
+
+            let len = (metadata.len() - position).try_into().map_err(|_| Error::SizeExceedsSystemLimit)?;
+            if let Ok(map) = MmapOptions::new().offset(position).len(len).map(&file) {
+                return Ok(MmapOrBuffer::Mmap(map));
+            }
+            hint = len;
+        }
+
+        Ok(MmapOrBuffer::Buffer(AlignedBuffer::read_from(&file, hint)?))
+    }
+}
+
+impl From<PathBuf> for Source {
+    #[inline]
+    fn from(path: PathBuf) -> Source {
+        Source::Path(path)
+    }
+}
+
+impl From<&Path> for Source {
+    #[inline]
+    fn from(path: &Path) -> Source {
+        Source::Path(path.to_path_buf())
+    }
+}
+
+impl From<File> for Source {
+    #[inline]
+    fn from(file: File) -> Source {
+        Source::File(file)
+    }
+}
+
+/// How the contents of a `MmapOrBuffer` were obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The input was memory mapped.
+    Mapped,
+
+    /// The input was read into a heap buffer.
+    Buffered,
+}
+
+/// The contents of a `Source`, either memory mapped or read into a buffer.
+///
+/// Buffers are aligned to the page size, like memory maps, so typed access
+/// behaves the same with either strategy.
+#[derive(Debug)]
+pub enum MmapOrBuffer {
+    /// The input was memory mapped.
+    Mmap(Mmap),
+
+    /// The input was read into a heap buffer.
+    Buffer(AlignedBuffer),
+}
+
+impl MmapOrBuffer {
+    /// Open a source, mapping it if possible and reading it into a buffer
+    /// otherwise. See `Source::open`.
+    ///
+    /// # Safety
+    ///
+    /// See `Source::open`.
+    #[inline]
+    pub unsafe fn open<S: Into<Source>>(source: S) -> Result<MmapOrBuffer> {
+        source.into().open()
+    }
+
+    /// Return how the contents were obtained.
+    #[inline]
+    pub fn strategy(&self) -> Strategy {
+        match self {
+            MmapOrBuffer::Mmap(_) => Strategy::Mapped,
+            MmapOrBuffer::Buffer(_) => Strategy::Buffered,
+        }
+    }
+
+    /// Return true if the contents are memory mapped.
+    #[inline]
+    pub fn is_mapped(&self) -> bool {
+        self.strategy() == Strategy::Mapped
+    }
+
+    /// Return the memory map, if the contents are memory mapped.
+    #[inline]
+    pub fn as_mmap(&self) -> Option<&Mmap> {
+        match self {
+            MmapOrBuffer::Mmap(map) => Some(map),
+            MmapOrBuffer::Buffer(_) => None,
+        }
+    }
+}
+
+impl Deref for MmapOrBuffer {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            MmapOrBuffer::Mmap(map) => map,
+            MmapOrBuffer::Buffer(buf) => buf,
+        }
+    }
+}
+
+impl AsRef<[u8]> for MmapOrBuffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// A page-aligned heap buffer holding the contents of an input.
+#[derive(Debug)]
+pub struct AlignedBuffer {
+    /// Start of the allocation.
+    ptr: NonNull<u8>,
+
+    /// Number of bytes read.
+    len: usize,
+
+    /// Size of the allocation.
+    capacity: usize,
+}
+
+// Safety: `AlignedBuffer` owns its allocation, and only hands out shared
+// access to it.
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Read `reader` to the end into a new buffer, starting with room for
+    /// `hint` bytes.
+    fn read_from<R: Read>(mut reader: R, hint: usize) -> Result<AlignedBuffer> {
+        let mut buf = AlignedBuffer::with_capacity(hint.max(1))?;
+        loop {
+            if buf.len == buf.capacity {
+                let capacity = buf.capacity.checked_mul(2).ok_or(Error::SizeExceedsSystemLimit)?;
+                buf.grow(capacity)?;
+            }
+
+            let spare = unsafe { slice::from_raw_parts_mut(buf.ptr.as_ptr().add(buf.len), buf.capacity - buf.len) };
+            match reader.read(spare) {
+                Ok(0) => return Ok(buf),
+                Ok(read) => buf.len += read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+    }
+
+    /// Allocate a zeroed buffer of `capacity` bytes.
+    fn with_capacity(capacity: usize) -> Result<AlignedBuffer> {
+        let layout = AlignedBuffer::layout(capacity)?;
+        let ptr = NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .ok_or(Error::Io(io::Error::from(io::ErrorKind::OutOfMemory)))?;
+
+        Ok(AlignedBuffer { ptr, len: 0, capacity })
+    }
+
+    /// Grow the allocation to `capacity` bytes, zeroing the new bytes.
+    fn grow(&mut self, capacity: usize) -> Result<()> {
+        let layout = AlignedBuffer::layout(capacity)?;
+        let ptr = unsafe { alloc::realloc(self.ptr.as_ptr(), AlignedBuffer::layout(self.capacity)?, layout.size()) };
+        let ptr = NonNull::new(ptr).ok_or(Error::Io(io::Error::from(io::ErrorKind::OutOfMemory)))?;
+        unsafe { ptr::write_bytes(ptr.as_ptr().add(self.capacity), 0, capacity - self.capacity) };
+
+        self.ptr = ptr;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Return the layout of an allocation of `capacity` bytes.
+    #[inline]
+    fn layout(capacity: usize) -> Result<Layout> {
+        Layout::from_size_align(capacity, alignment::page_size()).map_err(|_| Error::SizeExceedsSystemLimit)
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for AlignedBuffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        if let Ok(layout) = AlignedBuffer::layout(self.capacity) {
+            unsafe { alloc::dealloc(self.ptr.as_ptr(), layout) };
+        }
+    }
+}
+
+/// Return a file referring to the standard input of the process.
+#[cfg(unix)]
+#[inline]
+fn stdin_file() -> Result<File> {
+    Ok(File::from(io::stdin().as_fd().try_clone_to_owned()?))
+}
+
+/// Return a file referring to the standard input of the process.
+#[cfg(windows)]
+#[inline]
+fn stdin_file() -> Result<File> {
+    Ok(File::from(io::stdin().as_handle().try_clone_to_owned()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{SeekFrom, Write};
+    use std::thread;
+
+    use super::*;
+    use crate::mmap::test_lock;
+
+    #[test]
+    fn regular_files_are_mapped() {
+        let _lock = test_lock();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"mapped contents").unwrap();
+
+        let contents = unsafe { MmapOrBuffer::open(file.path()) }.unwrap();
+        assert_eq!(contents.strategy(), Strategy::Mapped);
+        assert!(contents.as_mmap().is_some());
+        assert_eq!(&contents[..], b"mapped contents");
+    }
+
+    #[test]
+    fn empty_files_are_buffered() {
+        let _lock = test_lock();
+        let file = tempfile::NamedTempFile::new().unwrap();
+
+        let contents = unsafe { MmapOrBuffer::open(file.path()) }.unwrap();
+        assert_eq!(contents.strategy(), Strategy::Buffered);
+        assert!(contents.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn procfs_files_are_buffered() {
+        let _lock = test_lock();
+        let path = Path::new("/proc/self/status");
+        assert_eq!(fs::metadata(path).unwrap().len(), 0);
+
+        let contents = unsafe { MmapOrBuffer::open(path) }.unwrap();
+        assert_eq!(contents.strategy(), Strategy::Buffered);
+        assert!(contents.starts_with(b"Name:"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipes_are_buffered() {
+        let _lock = test_lock();
+        let (reader, mut writer) = io::pipe().unwrap();
+        // More than the initial buffer size, so that the buffer grows
+        let data: Vec<u8> = (0..3 * INITIAL_BUFFER_SIZE + 7).map(|i| i as u8).collect();
+        let expected = data.clone();
+        let producer = thread::spawn(move || writer.write_all(&data).unwrap());
+
+        let file = File::from(std::os::unix::io::OwnedFd::from(reader));
+        let contents = unsafe { Source::File(file).open() }.unwrap();
+        producer.join().unwrap();
+        assert_eq!(contents.strategy(), Strategy::Buffered);
+        assert_eq!(&contents[..], &expected[..]);
+        assert!(alignment::is_aligned(contents.as_ptr() as usize, alignment::page_size()));
+    }
+
+    #[test]
+    fn files_are_read_from_their_position() {
+        let _lock = test_lock();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"header:body").unwrap();
+
+        file.seek(SeekFrom::Start(7)).unwrap();
+        let contents = unsafe { Source::from(file.try_clone().unwrap()).open() }.unwrap();
+        assert_eq!(contents.strategy(), Strategy::Mapped);
+        assert_eq!(&contents[..], b"body");
+
+        // Nothing is left past the end
+        file.seek(SeekFrom::End(0)).unwrap();
+        let contents = unsafe { Source::from(file).open() }.unwrap();
+        assert_eq!(contents.strategy(), Strategy::Buffered);
+        assert!(contents.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stdin_is_read_from_its_position() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"header:body").unwrap();
+        file.seek(SeekFrom::Start(7)).unwrap();
+
+        // Run `stdin_child` in a new test process with the file as its stdin
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "source::tests::stdin_child", "--test-threads=1"])
+            .env("MEMBASE_TEST_STDIN_CHILD", "1")
+            .stdin(file)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// The child half of `stdin_is_read_from_its_position`, which does
+    /// nothing when run as part of the normal test suite.
+    #[test]
+    fn stdin_child() {
+        if std::env::var_os("MEMBASE_TEST_STDIN_CHILD").is_none() {
+            return;
+        }
+        let contents = unsafe { Source::from_arg("-").open() }.unwrap();
+        assert_eq!(contents.strategy(), Strategy::Mapped);
+        assert_eq!(&contents[..], b"body");
+    }
+
+    #[test]
+    fn buffers_grow_and_stay_page_aligned() {
+        let data: Vec<u8> = (0..3 * alignment::page_size() + 5).map(|i| (i % 251) as u8).collect();
+
+        let buf = AlignedBuffer::read_from(&data[..], 1).unwrap();
+        assert_eq!(&buf[..], &data[..]);
+        assert!(buf.capacity >= data.len());
+        assert!(alignment::is_aligned(buf.as_ptr() as usize, alignment::page_size()));
+
+        let mut buf = AlignedBuffer::with_capacity(16).unwrap();
+        buf.grow(64).unwrap();
+        assert_eq!(buf.capacity, 64);
+        buf.len = 64;
+        assert!(buf.iter().all(|&byte| byte == 0));
+    }
+}
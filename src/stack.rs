@@ -0,0 +1,74 @@
+//! Guarded stacks for green threads and coroutines.
+//!
+//! This module provides `Stack`, an anonymous memory map suitable for use as
+//! a machine stack, with guard pages below it so that stack overflows fault
+//! instead of corrupting neighbouring memory.
+
+use crate::error::Result;
+use crate::mmap::{MmapMut, MmapOptions};
+use crate::utils::alignment;
+
+/// A memory-mapped stack protected by guard pages.
+///
+/// Stacks grow downwards: execution starts at `top` and moves towards
+/// `bottom`. The guard pages sit directly below `bottom`.
+#[derive(Debug)]
+pub struct Stack {
+    map: MmapMut,
+}
+
+impl Stack {
+    /// The number of guard pages used by `Stack::new`.
+    pub const DEFAULT_GUARD_PAGES: usize = 1;
+
+    /// Allocate a stack of at least `size` bytes with a single guard page.
+    ///
+    /// The size is rounded up to a multiple of the page size.
+    #[inline]
+    pub fn new(size: usize) -> Result<Stack> {
+        Stack::with_guard_pages(size, Stack::DEFAULT_GUARD_PAGES)
+    }
+
+    /// Allocate a stack of at least `size` bytes with `guard_pages` guard pages
+    /// below it.
+    ///
+    /// The size is rounded up to a multiple of the page size.
+    pub fn with_guard_pages(size: usize, guard_pages: usize) -> Result<Stack> {
+        let size = alignment::align_up(size, alignment::page_size());
+        let map = unsafe {
+            MmapOptions::new()
+                .write(true)
+                .stack(true)
+                .guard_pages(guard_pages, 0)
+                .map_anon(size)?
+        };
+
+        Ok(Stack { map })
+    }
+
+    /// Return the usable size of the stack in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Return true if the stack has no usable memory.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Return the top of the stack: the address one past its highest usable
+    /// byte, where execution starts.
+    #[inline]
+    pub fn top(&self) -> *mut u8 {
+        unsafe { self.bottom().add(self.map.len()) }
+    }
+
+    /// Return the bottom of the stack: its lowest usable address, directly
+    /// above the guard pages.
+    #[inline]
+    pub fn bottom(&self) -> *mut u8 {
+        self.map.as_ptr() as *mut u8
+    }
+}
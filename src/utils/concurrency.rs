@@ -13,6 +13,7 @@ use std::ops::{Deref, DerefMut};
 ///
 /// This is a thin wrapper around the standard library's `RwLock` with
 /// additional optimizations for memory mapping operations.
+#[derive(Debug)]
 pub struct RwLock<T> {
     inner: StdRwLock<T>,
 }